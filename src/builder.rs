@@ -0,0 +1,342 @@
+//! Builders of descriptors, to generate .proto files rather than parse them
+//!
+//! The built descriptors can be written back as .proto text with their `Display`
+//! implementation.
+
+use std::ops::Range;
+
+use super::{Enumeration, Extension, Field, FieldType, FileDescriptor, Import, ImportKind,
+            MemberRef, Message, OneOf, ProtobufConstant, ProtobufOption, Rule, Service, Syntax};
+use resolve::resolve_type_kinds;
+
+/// A `Field` builder
+///
+/// Fields are `optional` by default, without options.
+#[derive(Debug, Clone)]
+pub struct FieldBuilder {
+    field: Field,
+}
+
+impl FieldBuilder {
+    /// Starts an `optional` field
+    pub fn new(name: &str, typ: FieldType, number: i32) -> FieldBuilder {
+        FieldBuilder {
+            field: Field {
+                name: name.to_string(),
+                rule: Rule::Optional,
+                typ,
+                number,
+                default: None,
+                packed: None,
+                deprecated: false,
+                json_name: None,
+                options: Vec::new(),
+                in_oneof: false,
+                type_kind: None,
+                comments: Vec::new(),
+                trailing_comment: None,
+                span: None,
+            },
+        }
+    }
+
+    /// Sets the field rule, `Rule::Singular` for a field without label
+    pub fn rule(mut self, rule: Rule) -> FieldBuilder {
+        self.field.rule = rule;
+        self
+    }
+
+    /// Adds an option
+    ///
+    /// `default`, `packed`, `deprecated` and `json_name` also set the matching `Field` member,
+    /// as when parsing.
+    pub fn option(mut self, name: &str, value: ProtobufConstant) -> FieldBuilder {
+        match (name, &value) {
+            ("default", v) => self.field.default = Some(v.to_string()),
+            ("packed", ProtobufConstant::Bool(b)) => self.field.packed = Some(*b),
+            ("deprecated", ProtobufConstant::Bool(b)) => self.field.deprecated = *b,
+            ("json_name", ProtobufConstant::String(s)) => self.field.json_name = Some(s.clone()),
+            _ => (),
+        }
+        self.field.options.push(ProtobufOption {
+            name: name.to_string(),
+            value,
+        });
+        self
+    }
+
+    /// Adds a `default` option
+    pub fn default_value(self, value: ProtobufConstant) -> FieldBuilder {
+        self.option("default", value)
+    }
+
+    /// Adds a `packed` option
+    pub fn packed(self, packed: bool) -> FieldBuilder {
+        self.option("packed", ProtobufConstant::Bool(packed))
+    }
+
+    /// Adds a `deprecated` option
+    pub fn deprecated(self, deprecated: bool) -> FieldBuilder {
+        self.option("deprecated", ProtobufConstant::Bool(deprecated))
+    }
+
+    /// Adds a `json_name` option
+    pub fn json_name(self, json_name: &str) -> FieldBuilder {
+        self.option("json_name", ProtobufConstant::String(json_name.to_string()))
+    }
+
+    /// Adds a line of comment preceding the field
+    pub fn comment(mut self, comment: &str) -> FieldBuilder {
+        self.field.comments.push(comment.to_string());
+        self
+    }
+
+    /// Returns the field
+    pub fn build(self) -> Field {
+        self.field
+    }
+}
+
+/// A `Message` builder
+///
+/// Fields and oneofs are declared in the order they are added.
+#[derive(Debug, Clone)]
+pub struct MessageBuilder {
+    message: Message,
+}
+
+impl MessageBuilder {
+    /// Starts an empty message
+    pub fn new(name: &str) -> MessageBuilder {
+        MessageBuilder {
+            message: Message {
+                name: name.to_string(),
+                ..Message::default()
+            },
+        }
+    }
+
+    /// Adds an `optional` field without options, see `FieldBuilder` for other fields
+    pub fn field(self, name: &str, typ: FieldType, number: i32) -> MessageBuilder {
+        self.add_field(FieldBuilder::new(name, typ, number).build())
+    }
+
+    /// Adds a field
+    pub fn add_field(mut self, field: Field) -> MessageBuilder {
+        let index = self.message.fields.len();
+        self.message.fields.push(field);
+        self.message.declaration_order.push(MemberRef::Field(index));
+        self
+    }
+
+    /// Adds a oneof, setting `Field::in_oneof` for its fields
+    pub fn oneof(mut self, mut oneof: OneOf) -> MessageBuilder {
+        for f in &mut oneof.fields {
+            f.in_oneof = true;
+        }
+        let index = self.message.oneofs.len();
+        self.message.oneofs.push(oneof);
+        self.message.declaration_order.push(MemberRef::OneOf(index));
+        self
+    }
+
+    /// Adds a nested message
+    pub fn nested_message(mut self, message: Message) -> MessageBuilder {
+        self.message.messages.push(message);
+        self
+    }
+
+    /// Adds a nested enum
+    pub fn nested_enum(mut self, enumeration: Enumeration) -> MessageBuilder {
+        self.message.enums.push(enumeration);
+        self
+    }
+
+    /// Adds an extension declared in the message
+    pub fn extension(mut self, extension: Extension) -> MessageBuilder {
+        self.message.extensions.push(extension);
+        self
+    }
+
+    /// Reserves the field numbers `range`, e.g. `5..6` for number 5 alone
+    pub fn reserved_nums(mut self, range: Range<i32>) -> MessageBuilder {
+        self.message.reserved_nums.push(range);
+        self
+    }
+
+    /// Reserves a field name
+    pub fn reserved_name(mut self, name: &str) -> MessageBuilder {
+        self.message.reserved_names.push(name.to_string());
+        self
+    }
+
+    /// Adds an option
+    pub fn option(mut self, name: &str, value: ProtobufConstant) -> MessageBuilder {
+        self.message.options.push(ProtobufOption {
+            name: name.to_string(),
+            value,
+        });
+        self
+    }
+
+    /// Adds a line of comment preceding the message
+    pub fn comment(mut self, comment: &str) -> MessageBuilder {
+        self.message.comments.push(comment.to_string());
+        self
+    }
+
+    /// Returns the message
+    pub fn build(self) -> Message {
+        self.message
+    }
+}
+
+/// A `FileDescriptor` builder
+///
+/// Files are proto2 files by default, without package.
+#[derive(Debug, Clone, Default)]
+pub struct FileDescriptorBuilder {
+    file: FileDescriptor,
+}
+
+impl FileDescriptorBuilder {
+    /// Starts an empty file
+    pub fn new() -> FileDescriptorBuilder {
+        FileDescriptorBuilder::default()
+    }
+
+    /// Sets the syntax
+    pub fn syntax(mut self, syntax: Syntax) -> FileDescriptorBuilder {
+        self.file.syntax = syntax;
+        self
+    }
+
+    /// Sets the edition, e.g. `2023`, replacing the `syntax` statement
+    pub fn edition(mut self, edition: &str) -> FileDescriptorBuilder {
+        self.file.edition = Some(edition.to_string());
+        self
+    }
+
+    /// Sets the package
+    pub fn package(mut self, package: &str) -> FileDescriptorBuilder {
+        self.file.package = package.to_string();
+        self
+    }
+
+    /// Adds an import
+    pub fn import(mut self, path: &str, kind: ImportKind) -> FileDescriptorBuilder {
+        self.file.imports.push(Import {
+            path: path.to_string(),
+            kind,
+        });
+        self
+    }
+
+    /// Adds a message
+    pub fn message(mut self, message: Message) -> FileDescriptorBuilder {
+        self.file.messages.push(message);
+        self
+    }
+
+    /// Adds an enum
+    pub fn enumeration(mut self, enumeration: Enumeration) -> FileDescriptorBuilder {
+        self.file.enums.push(enumeration);
+        self
+    }
+
+    /// Adds an extension
+    pub fn extension(mut self, extension: Extension) -> FileDescriptorBuilder {
+        self.file.extensions.push(extension);
+        self
+    }
+
+    /// Adds a service
+    pub fn service(mut self, service: Service) -> FileDescriptorBuilder {
+        self.file.services.push(service);
+        self
+    }
+
+    /// Adds a file option
+    pub fn option(mut self, name: &str, value: ProtobufConstant) -> FileDescriptorBuilder {
+        self.file.options.push(ProtobufOption {
+            name: name.to_string(),
+            value,
+        });
+        self
+    }
+
+    /// Returns the file, with the `Field::type_kind` of its fields set as when parsing
+    pub fn build(mut self) -> FileDescriptor {
+        resolve_type_kinds(&mut self.file);
+        self.file
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_message() {
+        let message = MessageBuilder::new("A")
+            .field("a", FieldType::Int32, 1)
+            .add_field(
+                FieldBuilder::new("b", FieldType::String, 2)
+                    .rule(Rule::Repeated)
+                    .deprecated(true)
+                    .build(),
+            )
+            .build();
+
+        assert_eq!("A", message.name);
+        let fields = message
+            .fields
+            .iter()
+            .map(|f| (&*f.name, f.rule, &f.typ, f.number, f.deprecated))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("a", Rule::Optional, &FieldType::Int32, 1, false),
+                ("b", Rule::Repeated, &FieldType::String, 2, true),
+            ],
+            fields
+        );
+        assert!(message.fields[0].options.is_empty());
+        assert_eq!(
+            vec![ProtobufOption {
+                name: "deprecated".to_string(),
+                value: ProtobufConstant::Bool(true),
+            }],
+            message.fields[1].options
+        );
+        assert_eq!(vec![MemberRef::Field(0), MemberRef::Field(1)], message.members());
+    }
+
+    #[test]
+    fn test_build_file() {
+        let file = FileDescriptorBuilder::new()
+            .package("pkg")
+            .import("other.proto", ImportKind::Default)
+            .message(
+                MessageBuilder::new("A")
+                    .field("a", FieldType::Int32, 1)
+                    .add_field(
+                        FieldBuilder::new("s", FieldType::String, 2)
+                            .default_value(ProtobufConstant::String("x\ty".to_string()))
+                            .json_name("S")
+                            .build(),
+                    )
+                    .nested_message(MessageBuilder::new("B").build())
+                    .reserved_nums(5..6)
+                    .build(),
+            )
+            .build();
+
+        let s = &file.messages[0].fields[1];
+        assert_eq!(Some(r#""x\ty""#), s.default.as_deref());
+        assert_eq!(Some("S"), s.json_name.as_deref());
+
+        let parsed = FileDescriptor::parse(file.to_string()).unwrap();
+        assert_eq!(file, parsed);
+    }
+}
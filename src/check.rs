@@ -0,0 +1,357 @@
+//! Checks run on a parsed file, for the errors the grammar does not catch
+
+use super::{Enumeration, Field, FieldType, FileDescriptor, Message, ParserError, ParserOptions,
+            Rule, Span, Syntax};
+use parser::is_word;
+use std::collections::HashSet;
+
+/// Is `name` an identifier: a letter or `_` followed by letters, digits or `_`
+fn is_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Is `name` a list of dot separated identifiers
+fn is_full_ident(name: &str) -> bool {
+    name.split('.').all(is_ident)
+}
+
+/// Is `name` a type reference: a full identifier, optionally starting with a dot
+fn is_type_name(name: &str) -> bool {
+    is_full_ident(name.strip_prefix('.').unwrap_or(name))
+}
+
+/// Offset of the first occurrence of the word `word` in `file[start..end]`
+fn find_word(file: &[u8], start: usize, end: usize, word: &str) -> Option<usize> {
+    let word = word.as_bytes();
+    if word.is_empty() || end - start < word.len() {
+        return None;
+    }
+    (start..end - word.len() + 1).find(|&i| {
+        &file[i..i + word.len()] == word
+            && (i == 0 || !is_word(file[i - 1]))
+            && file.get(i + word.len()).is_none_or(|&b| !is_word(b))
+    })
+}
+
+struct Checker<'a> {
+    file: &'a [u8],
+    options: &'a ParserOptions,
+    syntax: Syntax,
+    /// Is the file using editions
+    edition: bool,
+    /// Errors and their offsets
+    errors: Vec<(usize, ParserError)>,
+}
+
+impl<'a> Checker<'a> {
+    /// Checks a name with `valid` if identifiers are strict
+    ///
+    /// The error is reported at the first occurrence of `name` in `span`, or in the whole file.
+    fn name(&mut self, span: Option<Span>, name: &str, valid: fn(&str) -> bool) {
+        if !self.options.strict_identifiers || valid(name) {
+            return;
+        }
+        let (start, end) = span.map_or((0, self.file.len()), |s| (s.start.offset, s.end.offset));
+        let offset = find_word(self.file, start, end, name).unwrap_or(start);
+        self.errors
+            .push((offset, ParserError::InvalidIdentifier(name.to_string())));
+    }
+
+    fn fields(&mut self, span: Option<Span>, fields: &[Field]) {
+        for f in fields {
+            self.name(span, &f.name, is_ident);
+            if f.rule == Rule::Required {
+                let offset = f.span.or(span).map_or(0, |s| s.start.offset);
+                if self.edition {
+                    self.errors
+                        .push((offset, ParserError::RequiredInEdition(f.name.clone())));
+                } else if matches!(self.syntax, Syntax::Proto3) {
+                    self.errors
+                        .push((offset, ParserError::RequiredInProto3(f.name.clone())));
+                }
+            }
+            if f.default.is_some() && !self.edition && matches!(self.syntax, Syntax::Proto3) {
+                let offset = f.span.or(span).map_or(0, |s| s.start.offset);
+                self.errors
+                    .push((offset, ParserError::DefaultInProto3(f.name.clone())));
+            }
+            match f.typ {
+                FieldType::MessageOrEnum(ref name) => self.name(span, name, is_type_name),
+                FieldType::Map(ref map) => {
+                    if let FieldType::MessageOrEnum(ref name) = map.value {
+                        self.name(span, name, is_type_name);
+                    }
+                    if f.rule != Rule::Singular {
+                        let offset = f.span.or(span).map_or(0, |s| s.start.offset);
+                        self.errors
+                            .push((offset, ParserError::LabelOnMap(f.name.clone())));
+                    }
+                }
+                FieldType::Group(ref g) => {
+                    self.name(span, &g.name, is_ident);
+                    self.fields(span, &g.fields);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn message(&mut self, msg: &Message) {
+        self.name(msg.span, &msg.name, is_ident);
+        self.fields(msg.span, &msg.fields);
+        for o in &msg.oneofs {
+            self.name(msg.span, &o.name, is_ident);
+            self.fields(msg.span, &o.fields);
+            for f in &o.fields {
+                let offset = f.span.or(msg.span).map_or(0, |s| s.start.offset);
+                if f.rule != Rule::Singular {
+                    self.errors
+                        .push((offset, ParserError::LabelInOneof(f.name.clone())));
+                }
+                if let FieldType::Map(_) = f.typ {
+                    self.errors
+                        .push((offset, ParserError::MapInOneof(f.name.clone())));
+                }
+            }
+        }
+        for m in &msg.messages {
+            self.message(m);
+        }
+        for e in &msg.enums {
+            self.enumeration(e);
+        }
+        for e in &msg.extensions {
+            self.name(msg.span, &e.extendee, is_type_name);
+            self.fields(msg.span, ::std::slice::from_ref(&e.field));
+        }
+    }
+
+    fn enumeration(&mut self, e: &Enumeration) {
+        self.name(e.span, &e.name, is_ident);
+        let mut numbers = HashSet::new();
+        for v in &e.values {
+            self.name(e.span, &v.name, is_ident);
+            if !numbers.insert(v.number) && !e.allow_alias {
+                let offset = v.span.or(e.span).map_or(0, |s| s.start.offset);
+                self.errors
+                    .push((offset, ParserError::DuplicateEnumValue(v.name.clone())));
+            }
+        }
+    }
+
+    fn file_descriptor(&mut self, desc: &FileDescriptor) {
+        if !desc.package.is_empty() {
+            self.name(None, &desc.package, is_full_ident);
+        }
+        for m in &desc.messages {
+            self.message(m);
+        }
+        for e in &desc.enums {
+            self.enumeration(e);
+        }
+        for e in &desc.extensions {
+            self.name(None, &e.extendee, is_type_name);
+            self.fields(None, ::std::slice::from_ref(&e.field));
+        }
+        for s in &desc.services {
+            self.name(None, &s.name, is_ident);
+            for m in &s.methods {
+                self.name(None, &m.name, is_ident);
+                self.name(None, &m.input_type, is_type_name);
+                self.name(None, &m.output_type, is_type_name);
+            }
+        }
+    }
+}
+
+/// Checks a parsed file, whose spans must be set
+///
+/// Returns the errors and their offsets.
+pub fn check(
+    file: &[u8],
+    desc: &FileDescriptor,
+    options: &ParserOptions,
+) -> Vec<(usize, ParserError)> {
+    let mut checker = Checker {
+        file,
+        options,
+        syntax: desc.syntax,
+        edition: desc.edition.is_some(),
+        errors: Vec::new(),
+    };
+    checker.file_descriptor(desc);
+    checker.errors
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_identifiers() {
+        assert!(is_ident("_foo1"));
+        assert!(!is_ident("1foo"));
+        assert!(!is_ident("foo-bar"));
+        assert!(!is_ident("foo.bar"));
+        assert!(is_full_ident("foo.bar"));
+        assert!(!is_full_ident(".foo.bar"));
+        assert!(is_type_name(".foo.Bar"));
+        assert!(!is_type_name("foo..Bar"));
+    }
+
+    #[test]
+    fn test_strict_identifiers() {
+        let msg = r#"message A {
+            optional int32 a1 = 1;
+            optional int32 1st = 2;
+            optional int32 with-dash = 3;
+            optional Legacy$Type t = 4;
+        }"#;
+
+        let err = FileDescriptor::parse(msg).unwrap_err();
+        assert_eq!(ParserError::InvalidIdentifier("1st".to_string()), err.error);
+        assert_eq!((3, 28), (err.line, err.col));
+
+        let (_, errors) = FileDescriptor::parse_recovering(msg);
+        let errors = errors.into_iter().map(|e| e.error).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ParserError::InvalidIdentifier("1st".to_string()),
+                ParserError::InvalidIdentifier("with-dash".to_string()),
+                ParserError::InvalidIdentifier("Legacy$Type".to_string()),
+            ],
+            errors
+        );
+
+        let options = ParserOptions {
+            strict_identifiers: false,
+            ..ParserOptions::default()
+        };
+        let desc = FileDescriptor::parse_with_options(msg, &options).unwrap();
+        let names = desc.messages[0].fields.iter().map(|f| &*f.name).collect::<Vec<_>>();
+        assert_eq!(vec!["a1", "1st", "with-dash", "t"], names);
+    }
+
+    #[test]
+    fn test_required_in_proto3() {
+        let msg = r#"syntax = "proto3";
+        message A {
+            optional int32 a = 1;
+            required int32 b = 2;
+        }"#;
+        let err = FileDescriptor::parse(msg).unwrap_err();
+        assert_eq!(ParserError::RequiredInProto3("b".to_string()), err.error);
+        assert_eq!((4, 13), (err.line, err.col));
+
+        let msg = r#"syntax = "proto2";
+        message A {
+            optional int32 a = 1;
+            required int32 b = 2;
+        }"#;
+        let desc = FileDescriptor::parse(msg).unwrap();
+        assert_eq!(Rule::Required, desc.messages[0].fields[1].rule);
+    }
+
+    #[test]
+    fn test_default_in_proto3() {
+        let fields = r#"
+        message A {
+            optional int32 a = 1;
+            optional string b = 2 [default = "x"];
+        }"#;
+        let proto2 = format!("syntax = \"proto2\";{}", fields);
+        let desc = FileDescriptor::parse(&proto2).unwrap();
+        assert_eq!(Some("\"x\""), desc.messages[0].fields[1].default.as_deref());
+
+        let proto3 = format!("syntax = \"proto3\";{}", fields);
+        let err = FileDescriptor::parse(&proto3).unwrap_err();
+        assert_eq!(ParserError::DefaultInProto3("b".to_string()), err.error);
+        assert_eq!((4, 13), (err.line, err.col));
+
+        let edition = format!("edition = \"2023\";{}", fields);
+        assert!(FileDescriptor::parse(&edition).is_ok());
+    }
+
+    #[test]
+    fn test_label_in_oneof() {
+        let msg = r#"message A {
+            oneof o {
+                int32 a = 1;
+                repeated int32 b = 2;
+            }
+        }"#;
+        let err = FileDescriptor::parse(msg).unwrap_err();
+        assert_eq!(ParserError::LabelInOneof("b".to_string()), err.error);
+        assert_eq!((4, 17), (err.line, err.col));
+
+        for label in &["optional", "required"] {
+            let msg = format!("message A {{ oneof o {{ {} int32 a = 1; }} }}", label);
+            let err = FileDescriptor::parse(&msg).unwrap_err();
+            assert_eq!(ParserError::LabelInOneof("a".to_string()), err.error);
+        }
+    }
+
+    #[test]
+    fn test_map_label_and_oneof() {
+        let msg = "message A {\n  repeated map<string, int32> m = 1;\n}";
+        let err = FileDescriptor::parse(msg).unwrap_err();
+        assert_eq!(ParserError::LabelOnMap("m".to_string()), err.error);
+        assert_eq!((2, 3), (err.line, err.col));
+
+        let msg = "message A {\n  oneof o {\n    map<string, int32> m = 1;\n  }\n}";
+        let err = FileDescriptor::parse(msg).unwrap_err();
+        assert_eq!(ParserError::MapInOneof("m".to_string()), err.error);
+        assert_eq!((3, 5), (err.line, err.col));
+
+        let desc = FileDescriptor::parse("message A { map<string,int32> m = 1; }").unwrap();
+        let m = &desc.messages[0].fields[0];
+        assert_eq!(Rule::Singular, m.rule);
+        assert!(matches!(m.typ, FieldType::Map(_)));
+    }
+
+    #[test]
+    fn test_required_in_edition() {
+        let msg = r#"edition = "2023";
+        message A {
+            int32 a = 1;
+            required int32 b = 2;
+        }"#;
+        let err = FileDescriptor::parse(msg).unwrap_err();
+        assert_eq!(ParserError::RequiredInEdition("b".to_string()), err.error);
+        assert_eq!((4, 13), (err.line, err.col));
+
+        let msg = r#"edition = "2023"; message A { int32 a = 1; }"#;
+        let desc = FileDescriptor::parse(msg).unwrap();
+        assert_eq!(Some("2023"), desc.edition.as_deref());
+        assert!(desc.to_string().starts_with("edition = \"2023\";\n"));
+    }
+
+    #[test]
+    fn test_enum_alias() {
+        let msg = r#"enum E {
+            A = 0;
+            B = 1 [deprecated = true];
+            C = 1;
+        }"#;
+        let err = FileDescriptor::parse(msg).unwrap_err();
+        assert_eq!(ParserError::DuplicateEnumValue("C".to_string()), err.error);
+        assert_eq!((4, 13), (err.line, err.col));
+
+        let msg = r#"enum E {
+            option allow_alias = true;
+            A = 0;
+            B = 1 [deprecated = true];
+            C = 1;
+        }"#;
+        let desc = FileDescriptor::parse(msg).unwrap();
+        let e = &desc.enums[0];
+        assert!(e.allow_alias);
+        assert_eq!("allow_alias", e.options[0].name);
+        assert_eq!(vec![0, 1, 1], e.values.iter().map(|v| v.number).collect::<Vec<_>>());
+        assert_eq!("deprecated", e.values[1].options[0].name);
+    }
+}
@@ -0,0 +1,315 @@
+//! Typed field default values
+
+use std::convert::TryFrom;
+
+use super::{Field, FieldType};
+
+/// A field default value, parsed according to the field type
+#[derive(Debug, Clone, PartialEq)]
+pub enum DefaultValue {
+    /// Default of a `bool` field
+    Bool(bool),
+    /// Default of a signed integer field (`int32`, `sint64`, `sfixed32`, ...)
+    I64(i64),
+    /// Default of an unsigned integer field (`uint32`, `fixed64`, ...)
+    U64(u64),
+    /// Default of a `float` or `double` field
+    F64(f64),
+    /// Default of a `string` field, unescaped
+    String(String),
+    /// Default of a `bytes` field, unescaped
+    Bytes(Vec<u8>),
+    /// Default of an enum field (or of a `FieldType::MessageOrEnum` field), the value name
+    ///
+    /// This is the identifier as written, e.g. `RED` for `[default = RED]`, which
+    /// `typed_default` first returned as a `ProtobufConstant::Ident`.
+    EnumVariant(String),
+}
+
+/// A default value which does not match the type of its field
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InvalidDefault {
+    /// Field name
+    pub field: String,
+    /// Default value, as written
+    pub default: String,
+}
+
+/// Parses an integer, decimal, octal or hexadecimal, with an optional sign
+fn integer(s: &str) -> Option<(bool, u64)> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(d) => (true, d),
+        None => (false, s),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).ok()
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8).ok()
+    } else if digits.bytes().all(|b| b.is_ascii_digit()) {
+        digits.parse().ok()
+    } else {
+        None
+    };
+    value.map(|v| (negative, v))
+}
+
+fn signed(s: &str, min: i64, max: i64) -> Option<DefaultValue> {
+    let (negative, v) = integer(s)?;
+    let v = if negative {
+        0i64.checked_sub_unsigned(v)?
+    } else {
+        i64::try_from(v).ok()?
+    };
+    if v < min || v > max {
+        return None;
+    }
+    Some(DefaultValue::I64(v))
+}
+
+fn unsigned(s: &str, max: u64) -> Option<DefaultValue> {
+    match integer(s)? {
+        (false, v) if v <= max => Some(DefaultValue::U64(v)),
+        _ => None,
+    }
+}
+
+fn float(s: &str) -> Option<DefaultValue> {
+    let (negative, abs) = match s.strip_prefix('-') {
+        Some(a) => (true, a),
+        None => (false, s),
+    };
+    let f = match abs {
+        "inf" => f64::INFINITY,
+        "nan" => f64::NAN,
+        _ if abs.bytes().next().is_some_and(|b| b.is_ascii_digit() || b == b'.') => {
+            match integer(abs) {
+                Some((_, i)) => i as f64,
+                None => abs.parse().ok()?,
+            }
+        }
+        _ => return None,
+    };
+    Some(DefaultValue::F64(if negative { -f } else { f }))
+}
+
+/// Unescapes a sequence of (adjacent) quoted strings
+fn unquote(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut s = s.trim();
+    while !s.is_empty() {
+        let quote = s.chars().next().filter(|&c| c == '"' || c == '\'')?;
+        let mut chars = s[1..].char_indices().peekable();
+        let end = loop {
+            let (i, c) = chars.next()?;
+            match c {
+                _ if c == quote => break i + 2,
+                '\\' => unescape(&mut chars, &mut bytes)?,
+                _ => {
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+            }
+        };
+        s = s[end..].trim_start();
+    }
+    Some(bytes)
+}
+
+/// Unescapes the content of a quoted string, without its quotes
+pub fn unescape_str(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        if c == '\\' {
+            unescape(&mut chars, &mut bytes)?;
+        } else {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+    }
+    Some(bytes)
+}
+
+/// Pushes the unescaped value of the escape sequence following a `\` to `bytes`
+fn unescape<I>(chars: &mut ::std::iter::Peekable<I>, bytes: &mut Vec<u8>) -> Option<()>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    // at most `max` digits in `radix`
+    fn digits<I>(chars: &mut ::std::iter::Peekable<I>, radix: u32, max: usize) -> Option<u32>
+    where
+        I: Iterator<Item = (usize, char)>,
+    {
+        let mut value = None;
+        for _ in 0..max {
+            match chars.peek().and_then(|&(_, c)| c.to_digit(radix)) {
+                Some(d) => {
+                    value = Some(value.unwrap_or(0) * radix + d);
+                    chars.next();
+                }
+                None => break,
+            }
+        }
+        value
+    }
+
+    let byte = match chars.peek()?.1 {
+        '0'..='7' => {
+            let v = digits(chars, 8, 3)?;
+            u8::try_from(v).ok()?
+        }
+        'x' | 'X' => {
+            chars.next();
+            digits(chars, 16, 2)? as u8
+        }
+        c @ 'u' | c @ 'U' => {
+            chars.next();
+            let v = digits(chars, 16, if c == 'u' { 4 } else { 8 })?;
+            let mut buf = [0; 4];
+            let c = ::std::char::from_u32(v)?;
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            return Some(());
+        }
+        c => {
+            chars.next();
+            match c {
+                'a' => 0x07,
+                'b' => 0x08,
+                'f' => 0x0C,
+                'n' => b'\n',
+                'r' => b'\r',
+                't' => b'\t',
+                'v' => 0x0B,
+                '\\' | '\'' | '"' | '?' => c as u8,
+                _ => return None,
+            }
+        }
+    };
+    bytes.push(byte);
+    Some(())
+}
+
+impl Field {
+    /// The `default` option of the field parsed according to its type, `None` without default
+    ///
+    /// Integers may be decimal, octal or hexadecimal and must fit in the field type, strings
+    /// and bytes are unescaped. The raw value stays available in `Field::default`.
+    pub fn typed_default(&self) -> Result<Option<DefaultValue>, InvalidDefault> {
+        let raw = match self.default {
+            Some(ref d) => d,
+            None => return Ok(None),
+        };
+        let value = match self.typ {
+            FieldType::Bool => match &**raw {
+                "true" => Some(DefaultValue::Bool(true)),
+                "false" => Some(DefaultValue::Bool(false)),
+                _ => None,
+            },
+            FieldType::Int32 | FieldType::Sint32 | FieldType::Sfixed32 => {
+                signed(raw, i32::MIN as i64, i32::MAX as i64)
+            }
+            FieldType::Int64 | FieldType::Sint64 | FieldType::Sfixed64 => {
+                signed(raw, i64::MIN, i64::MAX)
+            }
+            FieldType::Uint32 | FieldType::Fixed32 => unsigned(raw, u32::MAX as u64),
+            FieldType::Uint64 | FieldType::Fixed64 => unsigned(raw, u64::MAX),
+            FieldType::Float | FieldType::Double => float(raw),
+            FieldType::String => unquote(raw)
+                .and_then(|b| String::from_utf8(b).ok())
+                .map(DefaultValue::String),
+            FieldType::Bytes => unquote(raw).map(DefaultValue::Bytes),
+            FieldType::MessageOrEnum(_) => {
+                let mut bytes = raw.bytes();
+                let ident = bytes.next().is_some_and(|b| b.is_ascii_alphabetic() || b == b'_')
+                    && bytes.all(|b| b.is_ascii_alphanumeric() || b == b'_');
+                if ident {
+                    Some(DefaultValue::EnumVariant(raw.clone()))
+                } else {
+                    None
+                }
+            }
+            FieldType::Map(_) | FieldType::Group(_) => None,
+        };
+        match value {
+            Some(v) => Ok(Some(v)),
+            None => Err(InvalidDefault {
+                field: self.name.clone(),
+                default: raw.clone(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use FileDescriptor;
+
+    #[test]
+    fn test_typed_default() {
+        let msg = r#"message A {
+            optional bool a = 1 [default = true];
+            optional int32 b = 2 [default = -5];
+            optional sfixed64 c = 3 [default = -0x10];
+            optional uint32 d = 4 [default = 017];
+            optional fixed64 e = 5 [default = 18446744073709551615];
+            optional double f = 6 [default = 2.5e-1];
+            optional float g = 7 [default = -inf];
+            optional float h = 8 [default = 2];
+            optional string i = 9 [default = 'a\tb\u00e9"é'];
+            optional bytes j = 10 [default = "\x01\0012\377"];
+            optional E k = 11 [default = FOO];
+            optional int32 l = 12;
+        }"#;
+
+        let desc = FileDescriptor::parse(msg).unwrap();
+        let defaults = desc.messages[0]
+            .fields
+            .iter()
+            .map(|f| f.typed_default().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Some(DefaultValue::Bool(true)),
+                Some(DefaultValue::I64(-5)),
+                Some(DefaultValue::I64(-16)),
+                Some(DefaultValue::U64(15)),
+                Some(DefaultValue::U64(u64::MAX)),
+                Some(DefaultValue::F64(0.25)),
+                Some(DefaultValue::F64(f64::NEG_INFINITY)),
+                Some(DefaultValue::F64(2.)),
+                Some(DefaultValue::String("a\tbé\"é".to_string())),
+                Some(DefaultValue::Bytes(vec![1, 1, b'2', 255])),
+                Some(DefaultValue::EnumVariant("FOO".to_string())),
+                None,
+            ],
+            defaults
+        );
+        let raw = desc.messages[0].fields[8].default.as_deref();
+        assert_eq!(Some(r#"'a\tb\u00e9"é'"#), raw);
+    }
+
+    #[test]
+    fn test_invalid_default() {
+        let msg = r#"message A {
+            optional int32 a = 1 [default = "abc"];
+            optional int32 b = 2 [default = 2147483648];
+            optional uint64 c = 3 [default = -1];
+            optional bool d = 4 [default = 1];
+            optional string e = 5 [default = 1];
+            optional E f = 6 [default = 1];
+            optional double g = 7 [default = FOO];
+        }"#;
+
+        let desc = FileDescriptor::parse(msg).unwrap();
+        for f in &desc.messages[0].fields {
+            assert_eq!(
+                Err(InvalidDefault {
+                    field: f.name.clone(),
+                    default: f.default.clone().unwrap(),
+                }),
+                f.typed_default()
+            );
+        }
+    }
+}
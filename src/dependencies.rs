@@ -0,0 +1,158 @@
+//! Ordering of files depending on each other
+
+use std::collections::HashMap;
+
+use super::FileDescriptor;
+
+/// A parsed file along with the path it is imported with
+#[derive(Debug, Clone)]
+pub struct FileDescriptorWithContext {
+    /// Path of the file, as written in the `import` statements of the files importing it
+    pub path: String,
+    /// Parsed file
+    pub descriptor: FileDescriptor,
+}
+
+/// Files importing each other
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CircularImport {
+    /// Paths of the files in the cycle, the first file being repeated at the end
+    /// (e.g. `["a.proto", "b.proto", "a.proto"]`)
+    pub cycle: Vec<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    New,
+    Visiting,
+    Done,
+}
+
+/// Orders files so that every file comes after the files it imports
+///
+/// Files are otherwise kept in the given order. Imports of files not in `descriptors` are
+/// ignored.
+pub fn topological_sort(
+    descriptors: Vec<FileDescriptorWithContext>,
+) -> Result<Vec<FileDescriptorWithContext>, CircularImport> {
+    let indexes = descriptors
+        .iter()
+        .enumerate()
+        .map(|(i, d)| (&*d.path, i))
+        .collect::<HashMap<_, _>>();
+    let imports = descriptors
+        .iter()
+        .map(|d| {
+            d.descriptor
+                .imports
+                .iter()
+                .filter_map(|i| indexes.get(&*i.path).cloned())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut states = vec![State::New; descriptors.len()];
+    let mut order = Vec::with_capacity(descriptors.len());
+    for root in 0..descriptors.len() {
+        if states[root] != State::New {
+            continue;
+        }
+        // depth first search, with each file and the next import to visit
+        let mut stack = vec![(root, 0)];
+        states[root] = State::Visiting;
+        while let Some(&mut (file, ref mut next)) = stack.last_mut() {
+            match imports[file].get(*next) {
+                Some(&import) => {
+                    *next += 1;
+                    match states[import] {
+                        State::New => {
+                            states[import] = State::Visiting;
+                            stack.push((import, 0));
+                        }
+                        State::Visiting => {
+                            let start = stack.iter().position(|&(f, _)| f == import).unwrap_or(0);
+                            let cycle = stack[start..]
+                                .iter()
+                                .map(|&(f, _)| f)
+                                .chain(Some(import))
+                                .map(|f| descriptors[f].path.clone())
+                                .collect();
+                            return Err(CircularImport { cycle });
+                        }
+                        State::Done => (),
+                    }
+                }
+                None => {
+                    states[file] = State::Done;
+                    order.push(file);
+                    stack.pop();
+                }
+            }
+        }
+    }
+
+    let mut descriptors = descriptors.into_iter().map(Some).collect::<Vec<_>>();
+    Ok(order
+        .into_iter()
+        .filter_map(|i| descriptors[i].take())
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn file(path: &str, content: &str) -> FileDescriptorWithContext {
+        FileDescriptorWithContext {
+            path: path.to_string(),
+            descriptor: FileDescriptor::parse(content.as_bytes()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_topological_sort() {
+        let files = vec![
+            file("a.proto", r#"import "b.proto"; import "c.proto";"#),
+            file("b.proto", r#"import "c.proto"; import "google/protobuf/any.proto";"#),
+            file("c.proto", ""),
+            file("d.proto", ""),
+        ];
+        let sorted = topological_sort(files).unwrap();
+        let paths = sorted.iter().map(|f| &*f.path).collect::<Vec<_>>();
+        assert_eq!(vec!["c.proto", "b.proto", "a.proto", "d.proto"], paths);
+    }
+
+    #[test]
+    fn test_circular_import() {
+        let files = vec![
+            file("a.proto", r#"import "b.proto";"#),
+            file("b.proto", r#"import "c.proto";"#),
+            file("c.proto", r#"import "b.proto";"#),
+        ];
+        let err = topological_sort(files).unwrap_err();
+        assert_eq!(vec!["b.proto", "c.proto", "b.proto"], err.cycle);
+    }
+
+    #[test]
+    fn test_circular_import_direct() {
+        let files = vec![
+            file("a.proto", r#"import "b.proto";"#),
+            file("b.proto", r#"import "a.proto";"#),
+        ];
+        let err = topological_sort(files).unwrap_err();
+        assert_eq!(vec!["a.proto", "b.proto", "a.proto"], err.cycle);
+    }
+
+    #[test]
+    fn test_diamond_import() {
+        let files = vec![
+            file("a.proto", r#"import "b.proto"; import "c.proto";"#),
+            file("b.proto", r#"import "d.proto";"#),
+            file("c.proto", r#"import "d.proto";"#),
+            file("d.proto", ""),
+        ];
+        let sorted = topological_sort(files).unwrap();
+        let paths = sorted.iter().map(|f| &*f.path).collect::<Vec<_>>();
+        assert_eq!(vec!["d.proto", "b.proto", "c.proto", "a.proto"], paths);
+    }
+}
@@ -0,0 +1,622 @@
+//! Encoding of a `FileDescriptor` as a `google.protobuf.FileDescriptorProto`
+//!
+//! Only the options with a dedicated descriptor field are encoded (`packed`, `deprecated`,
+//! `allow_alias` and `map_entry`), other options, comments and spans are dropped.
+
+use super::{
+    DefaultValue, EnumValue, Enumeration, Extension, Field, FieldType, FileDescriptor,
+    ImportKind, Message, Method, Rule, Service, Syntax,
+};
+use resolve::{package_scope, Types};
+use TypeKind;
+
+/// Appends `v` as a base 128 varint
+fn varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn key(buf: &mut Vec<u8>, field: u32, wire_type: u32) {
+    varint(buf, u64::from(field << 3 | wire_type));
+}
+
+/// Appends a varint field, negative numbers are sign extended to 10 bytes
+fn int(buf: &mut Vec<u8>, field: u32, v: i64) {
+    key(buf, field, 0);
+    varint(buf, v as u64);
+}
+
+fn boolean(buf: &mut Vec<u8>, field: u32, v: bool) {
+    int(buf, field, v as i64);
+}
+
+fn bytes(buf: &mut Vec<u8>, field: u32, v: &[u8]) {
+    key(buf, field, 2);
+    varint(buf, v.len() as u64);
+    buf.extend_from_slice(v);
+}
+
+fn string(buf: &mut Vec<u8>, field: u32, v: &str) {
+    bytes(buf, field, v.as_bytes());
+}
+
+/// Appends a range message, `end` being encoded as is
+fn range(buf: &mut Vec<u8>, field: u32, start: i32, end: i32) {
+    let mut m = Vec::new();
+    int(&mut m, 1, i64::from(start));
+    int(&mut m, 2, i64::from(end));
+    bytes(buf, field, &m);
+}
+
+/// `FieldDescriptorProto.Type` of a scalar type
+fn scalar_type(typ: &FieldType) -> Option<i64> {
+    Some(match *typ {
+        FieldType::Double => 1,
+        FieldType::Float => 2,
+        FieldType::Int64 => 3,
+        FieldType::Uint64 => 4,
+        FieldType::Int32 => 5,
+        FieldType::Fixed64 => 6,
+        FieldType::Fixed32 => 7,
+        FieldType::Bool => 8,
+        FieldType::String => 9,
+        FieldType::Bytes => 12,
+        FieldType::Uint32 => 13,
+        FieldType::Sfixed32 => 15,
+        FieldType::Sfixed64 => 16,
+        FieldType::Sint32 => 17,
+        FieldType::Sint64 => 18,
+        FieldType::MessageOrEnum(_) | FieldType::Map(_) | FieldType::Group(_) => return None,
+    })
+}
+
+/// Name of the nested message generated for the map field `name` (e.g. `MyMapEntry`)
+fn map_entry_name(name: &str) -> String {
+    let mut entry = String::with_capacity(name.len() + 5);
+    let mut upper = true;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            entry.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            entry.push(c);
+        }
+    }
+    entry + "Entry"
+}
+
+/// Default json name of the field `name`, as computed by protoc (e.g. `fooBar`)
+fn json_name(name: &str) -> String {
+    let mut json = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            json.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            json.push(c);
+        }
+    }
+    json
+}
+
+/// C-escapes a `bytes` default value, as stored in `FieldDescriptorProto.default_value`
+fn escape(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len());
+    for &b in bytes {
+        match b {
+            b'\n' => s.push_str("\\n"),
+            b'\r' => s.push_str("\\r"),
+            b'\t' => s.push_str("\\t"),
+            b'"' => s.push_str("\\\""),
+            b'\'' => s.push_str("\\'"),
+            b'\\' => s.push_str("\\\\"),
+            0x20..=0x7E => s.push(b as char),
+            _ => s.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+    s
+}
+
+struct Encoder<'a> {
+    types: Types<'a>,
+    proto3: bool,
+}
+
+impl<'a> Encoder<'a> {
+    /// Fully qualified name of the type `name` referenced from `scope`, or `name` as is if it
+    /// cannot be resolved
+    fn type_name(&self, scope: &str, name: &str) -> String {
+        self.types
+            .resolve(scope, name)
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    /// Appends the `type` and `type_name` of a reference to `name` from `scope`
+    ///
+    /// The type is left unset if `name` cannot be resolved, as protoc does before linking.
+    fn type_ref(&self, buf: &mut Vec<u8>, scope: &str, name: &str) {
+        let full = self.types.resolve(scope, name);
+        match full.as_ref().and_then(|f| self.types.kind(f)) {
+            Some(TypeKind::Message) => int(buf, 5, 11),
+            Some(TypeKind::Enum) => int(buf, 5, 14),
+            None => (),
+        }
+        string(buf, 6, full.as_deref().unwrap_or(name));
+    }
+
+    /// Encodes a `FieldDescriptorProto`
+    fn field(
+        &self,
+        scope: &str,
+        f: &Field,
+        extendee: Option<&str>,
+        oneof_index: Option<usize>,
+    ) -> Vec<u8> {
+        let mut buf = Vec::new();
+        string(&mut buf, 1, &f.name);
+        if let Some(extendee) = extendee {
+            string(&mut buf, 2, &self.type_name(scope, extendee));
+        }
+        int(&mut buf, 3, i64::from(f.number));
+        let label = match f.rule {
+            _ if matches!(f.typ, FieldType::Map(_)) => 3,
+            Rule::Singular | Rule::Optional => 1,
+            Rule::Required => 2,
+            Rule::Repeated => 3,
+        };
+        int(&mut buf, 4, label);
+        match f.typ {
+            FieldType::MessageOrEnum(ref name) => self.type_ref(&mut buf, scope, name),
+            FieldType::Map(_) => {
+                int(&mut buf, 5, 11);
+                string(&mut buf, 6, &format!("{}.{}", scope, map_entry_name(&f.name)));
+            }
+            FieldType::Group(ref g) => {
+                int(&mut buf, 5, 10);
+                string(&mut buf, 6, &format!("{}.{}", scope, g.name));
+            }
+            ref typ => int(&mut buf, 5, scalar_type(typ).unwrap_or(0)),
+        }
+        if let Some(ref raw) = f.default {
+            let default = match f.typed_default() {
+                Ok(Some(DefaultValue::String(s))) => s,
+                Ok(Some(DefaultValue::Bytes(b))) => escape(&b),
+                _ => raw.clone(),
+            };
+            string(&mut buf, 7, &default);
+        }
+        if f.packed.is_some() || f.deprecated {
+            let mut options = Vec::new();
+            if let Some(packed) = f.packed {
+                boolean(&mut options, 2, packed);
+            }
+            if f.deprecated {
+                boolean(&mut options, 3, true);
+            }
+            bytes(&mut buf, 8, &options);
+        }
+        if let Some(i) = oneof_index {
+            int(&mut buf, 9, i as i64);
+        }
+        match f.json_name {
+            Some(ref json) => string(&mut buf, 10, json),
+            None => string(&mut buf, 10, &json_name(&f.name)),
+        }
+        if self.proto3 && f.rule == Rule::Optional {
+            boolean(&mut buf, 17, true);
+        }
+        buf
+    }
+
+    /// Encodes the `DescriptorProto` of the entry of the map field `f`
+    fn map_entry(&self, scope: &str, f: &Field, key: &FieldType, value: &FieldType) -> Vec<u8> {
+        let name = map_entry_name(&f.name);
+        let scope = format!("{}.{}", scope, name);
+        let entry_field = |name: &str, number, typ: &FieldType| Field {
+            name: name.to_string(),
+            rule: Rule::Singular,
+            typ: typ.clone(),
+            number,
+            default: None,
+            packed: None,
+            deprecated: false,
+            json_name: None,
+            options: Vec::new(),
+            in_oneof: false,
+            type_kind: f.type_kind,
+            comments: Vec::new(),
+            trailing_comment: None,
+            span: None,
+        };
+        let mut buf = Vec::new();
+        string(&mut buf, 1, &name);
+        let key = self.field(&scope, &entry_field("key", 1, key), None, None);
+        bytes(&mut buf, 2, &key);
+        let value = self.field(&scope, &entry_field("value", 2, value), None, None);
+        bytes(&mut buf, 2, &value);
+        let mut options = Vec::new();
+        boolean(&mut options, 7, true);
+        bytes(&mut buf, 7, &options);
+        buf
+    }
+
+    /// Encodes a `DescriptorProto`
+    fn message(&self, scope: &str, msg: &Message) -> Vec<u8> {
+        let scope = format!("{}.{}", scope, msg.name);
+        let mut buf = Vec::new();
+        string(&mut buf, 1, &msg.name);
+
+        // proto3 optional fields are members of a synthetic oneof, after the declared ones
+        let mut synthetic = Vec::new();
+        for f in &msg.fields {
+            let oneof_index = if self.proto3 && f.rule == Rule::Optional {
+                synthetic.push(format!("_{}", f.name));
+                Some(msg.oneofs.len() + synthetic.len() - 1)
+            } else {
+                None
+            };
+            bytes(&mut buf, 2, &self.field(&scope, f, None, oneof_index));
+        }
+        for (i, o) in msg.oneofs.iter().enumerate() {
+            for f in &o.fields {
+                bytes(&mut buf, 2, &self.field(&scope, f, None, Some(i)));
+            }
+        }
+
+        for m in &msg.messages {
+            bytes(&mut buf, 3, &self.message(&scope, m));
+        }
+        let fields = msg.fields.iter().chain(msg.oneofs.iter().flat_map(|o| o.fields.iter()));
+        for f in fields {
+            match f.typ {
+                FieldType::Group(ref g) => {
+                    let group = Message {
+                        name: g.name.clone(),
+                        fields: g.fields.clone(),
+                        ..Message::default()
+                    };
+                    bytes(&mut buf, 3, &self.message(&scope, &group));
+                }
+                FieldType::Map(ref m) => {
+                    bytes(&mut buf, 3, &self.map_entry(&scope, f, &m.key, &m.value));
+                }
+                _ => (),
+            }
+        }
+        for e in &msg.enums {
+            bytes(&mut buf, 4, &enumeration(e));
+        }
+        for r in &msg.extension_ranges {
+            range(&mut buf, 5, r.range.start, r.range.end);
+        }
+        for e in &msg.extensions {
+            bytes(&mut buf, 6, &self.extension(&scope, e));
+        }
+        for name in msg.oneofs.iter().map(|o| &o.name).chain(&synthetic) {
+            let mut oneof = Vec::new();
+            string(&mut oneof, 1, name);
+            bytes(&mut buf, 8, &oneof);
+        }
+        for r in &msg.reserved_nums {
+            range(&mut buf, 9, r.start, r.end);
+        }
+        for name in &msg.reserved_names {
+            string(&mut buf, 10, name);
+        }
+        buf
+    }
+
+    fn extension(&self, scope: &str, e: &Extension) -> Vec<u8> {
+        self.field(scope, &e.field, Some(&e.extendee), None)
+    }
+
+    /// Encodes a `ServiceDescriptorProto`
+    fn service(&self, scope: &str, service: &Service) -> Vec<u8> {
+        let mut buf = Vec::new();
+        string(&mut buf, 1, &service.name);
+        for m in &service.methods {
+            bytes(&mut buf, 2, &self.method(scope, m));
+        }
+        buf
+    }
+
+    fn method(&self, scope: &str, method: &Method) -> Vec<u8> {
+        let mut buf = Vec::new();
+        string(&mut buf, 1, &method.name);
+        string(&mut buf, 2, &self.type_name(scope, &method.input_type));
+        string(&mut buf, 3, &self.type_name(scope, &method.output_type));
+        if method.client_streaming {
+            boolean(&mut buf, 5, true);
+        }
+        if method.server_streaming {
+            boolean(&mut buf, 6, true);
+        }
+        buf
+    }
+}
+
+/// Encodes an `EnumDescriptorProto`
+fn enumeration(e: &Enumeration) -> Vec<u8> {
+    let mut buf = Vec::new();
+    string(&mut buf, 1, &e.name);
+    for v in &e.values {
+        bytes(&mut buf, 2, &enum_value(v));
+    }
+    if e.allow_alias {
+        let mut options = Vec::new();
+        boolean(&mut options, 2, true);
+        bytes(&mut buf, 3, &options);
+    }
+    for r in &e.reserved_nums {
+        range(&mut buf, 4, *r.start(), *r.end());
+    }
+    for name in &e.reserved_names {
+        string(&mut buf, 5, name);
+    }
+    buf
+}
+
+fn enum_value(v: &EnumValue) -> Vec<u8> {
+    let mut buf = Vec::new();
+    string(&mut buf, 1, &v.name);
+    int(&mut buf, 2, i64::from(v.number));
+    buf
+}
+
+impl FileDescriptor {
+    /// Serializes the file as a `google.protobuf.FileDescriptorProto`, in protobuf wire format
+    ///
+    /// Type references are fully qualified when they can be resolved within this file (see
+    /// `resolve_types` to resolve imported types first), map fields get their `Entry` message
+    /// and proto3 `optional` fields their synthetic oneof, as generated by protoc. The file
+    /// has no name, a `FileDescriptorSet` needs it to be set by the caller.
+    ///
+    /// Only the `packed`, `deprecated`, `allow_alias` and map entry options are encoded.
+    pub fn to_descriptor_bytes(&self) -> Vec<u8> {
+        let encoder = Encoder {
+            types: Types::new(self),
+            proto3: self.edition.is_none() && self.syntax == Syntax::Proto3,
+        };
+        let scope = package_scope(&self.package);
+        let mut buf = Vec::new();
+        if !self.package.is_empty() {
+            string(&mut buf, 2, &self.package);
+        }
+        for i in &self.imports {
+            string(&mut buf, 3, &i.path);
+        }
+        for m in &self.messages {
+            bytes(&mut buf, 4, &encoder.message(&scope, m));
+        }
+        for e in &self.enums {
+            bytes(&mut buf, 5, &enumeration(e));
+        }
+        for s in &self.services {
+            bytes(&mut buf, 6, &encoder.service(&scope, s));
+        }
+        for e in &self.extensions {
+            bytes(&mut buf, 7, &encoder.extension(&scope, e));
+        }
+        for (index, i) in self.imports.iter().enumerate() {
+            match i.kind {
+                ImportKind::Public => int(&mut buf, 10, index as i64),
+                ImportKind::Weak => int(&mut buf, 11, index as i64),
+                ImportKind::Default => (),
+            }
+        }
+        match self.edition {
+            Some(ref edition) => {
+                string(&mut buf, 12, "editions");
+                match &**edition {
+                    "2023" => int(&mut buf, 14, 1000),
+                    "2024" => int(&mut buf, 14, 1001),
+                    _ => (),
+                }
+            }
+            None if self.syntax == Syntax::Proto3 => string(&mut buf, 12, "proto3"),
+            None => (),
+        }
+        buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Reads a varint, advancing `buf`
+    fn read_varint(buf: &mut &[u8]) -> u64 {
+        let mut v = 0;
+        for (i, &b) in buf.iter().enumerate() {
+            v |= u64::from(b & 0x7F) << (7 * i);
+            if b < 0x80 {
+                *buf = &buf[i + 1..];
+                return v;
+            }
+        }
+        panic!("truncated varint");
+    }
+
+    /// Decodes the fields of a message, which must only contain varint and length delimited
+    /// fields
+    fn decode(mut buf: &[u8]) -> Vec<(u32, Result<u64, &[u8]>)> {
+        let mut fields = Vec::new();
+        while !buf.is_empty() {
+            let key = read_varint(&mut buf);
+            let value = match key & 7 {
+                0 => Ok(read_varint(&mut buf)),
+                2 => {
+                    let len = read_varint(&mut buf) as usize;
+                    let (value, rest) = buf.split_at(len);
+                    buf = rest;
+                    Err(value)
+                }
+                t => panic!("unexpected wire type {}", t),
+            };
+            fields.push(((key >> 3) as u32, value));
+        }
+        fields
+    }
+
+    fn ints(buf: &[u8], field: u32) -> Vec<u64> {
+        decode(buf)
+            .into_iter()
+            .filter(|&(n, _)| n == field)
+            .map(|(_, v)| v.unwrap())
+            .collect()
+    }
+
+    fn messages(buf: &[u8], field: u32) -> Vec<&[u8]> {
+        decode(buf)
+            .into_iter()
+            .filter(|&(n, _)| n == field)
+            .map(|(_, v)| v.unwrap_err())
+            .collect()
+    }
+
+    fn strings(buf: &[u8], field: u32) -> Vec<&str> {
+        messages(buf, field)
+            .into_iter()
+            .map(|s| ::std::str::from_utf8(s).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_descriptor_bytes() {
+        let proto = r#"
+            syntax = "proto3";
+            package pkg;
+            import public "other.proto";
+            message A {
+                int32 a = 1;
+                optional string b = 2;
+                repeated B c = 3;
+                map<string, E> my_map = 4;
+                oneof o {
+                    bytes e = 5;
+                    A f = 6 [deprecated = true];
+                }
+                message B { sint64 g = 1; }
+                enum E { X = 0; }
+                reserved 10 to 12;
+                reserved "foo_bar";
+            }
+            enum Top {
+                option allow_alias = true;
+                T0 = 0;
+                T1 = 0;
+                reserved 5 to max, 2147483646;
+            }
+            service S { rpc M(A) returns (stream A.B); }
+        "#;
+        let desc = FileDescriptor::parse(proto).unwrap();
+        let file = desc.to_descriptor_bytes();
+        assert_eq!(vec!["pkg"], strings(&file, 2));
+        assert_eq!(vec!["other.proto"], strings(&file, 3));
+        assert_eq!(vec![0], ints(&file, 10));
+        assert_eq!(vec!["proto3"], strings(&file, 12));
+        assert_eq!(1, messages(&file, 5).len());
+        assert_eq!(1, messages(&file, 6).len());
+
+        let msgs = messages(&file, 4);
+        assert_eq!(1, msgs.len());
+        let a = msgs[0];
+        assert_eq!(vec!["A"], strings(a, 1));
+        let fields = messages(a, 2);
+        let names = fields.iter().map(|f| strings(f, 1)[0]).collect::<Vec<_>>();
+        assert_eq!(vec!["a", "b", "c", "my_map", "e", "f"], names);
+        let field_ints = |n| fields.iter().map(|f| ints(f, n)).collect::<Vec<_>>();
+        assert_eq!(vec![vec![1], vec![2], vec![3], vec![4], vec![5], vec![6]], field_ints(3));
+        assert_eq!(vec![vec![1], vec![1], vec![3], vec![3], vec![1], vec![1]], field_ints(4));
+        assert_eq!(vec![vec![5], vec![9], vec![11], vec![11], vec![12], vec![11]], field_ints(5));
+        assert_eq!(vec![vec![], vec![1], vec![], vec![], vec![0], vec![0]], field_ints(9));
+        assert_eq!(vec![vec![], vec![1], vec![], vec![], vec![], vec![]], field_ints(17));
+        assert_eq!(vec![".pkg.A.B"], strings(fields[2], 6));
+        assert_eq!(vec![".pkg.A.MyMapEntry"], strings(fields[3], 6));
+        assert_eq!(vec![".pkg.A"], strings(fields[5], 6));
+        assert_eq!(vec!["myMap"], strings(fields[3], 10));
+        assert_eq!(vec![1], ints(messages(fields[5], 8)[0], 3));
+
+        let oneofs = messages(a, 8).into_iter().map(|o| strings(o, 1)[0]).collect::<Vec<_>>();
+        assert_eq!(vec!["o", "_b"], oneofs);
+        let nested = messages(a, 3);
+        let names = nested.iter().map(|m| strings(m, 1)[0]).collect::<Vec<_>>();
+        assert_eq!(vec!["B", "MyMapEntry"], names);
+        assert_eq!(1, messages(nested[0], 2).len());
+        let entry = messages(nested[1], 2);
+        assert_eq!(vec![9], ints(entry[0], 5));
+        assert_eq!(vec![14], ints(entry[1], 5));
+        assert_eq!(vec![".pkg.A.E"], strings(entry[1], 6));
+        assert_eq!(vec![1], ints(messages(nested[1], 7)[0], 7));
+        assert_eq!(1, messages(a, 4).len());
+        let reserved = messages(a, 9)[0];
+        assert_eq!((vec![10], vec![13]), (ints(reserved, 1), ints(reserved, 2)));
+        assert_eq!(vec!["foo_bar"], strings(a, 10));
+
+        let top = messages(&file, 5)[0];
+        assert_eq!(2, messages(top, 2).len());
+        assert_eq!(vec![1], ints(messages(top, 3)[0], 2));
+        let reserved = messages(top, 4);
+        assert_eq!(vec![i32::MAX as u64], ints(reserved[0], 2));
+        let max = i32::MAX as u64 - 1;
+        assert_eq!((vec![max], vec![max]), (ints(reserved[1], 1), ints(reserved[1], 2)));
+
+        let method = messages(messages(&file, 6)[0], 2)[0];
+        assert_eq!(vec![".pkg.A"], strings(method, 2));
+        assert_eq!(vec![".pkg.A.B"], strings(method, 3));
+        assert_eq!((vec![], vec![1]), (ints(method, 5), ints(method, 6)));
+    }
+
+    #[test]
+    fn test_descriptor_bytes_proto2() {
+        let proto = r#"
+            message A {
+                required int32 a = 1 [default = -2];
+                optional group G = 2 {
+                    optional string s = 1 [default = "a\tb"];
+                }
+                extensions 100 to 199;
+            }
+            extend A {
+                repeated Unknown x = 100 [packed = true];
+            }
+        "#;
+        let desc = FileDescriptor::parse(proto).unwrap();
+        let file = desc.to_descriptor_bytes();
+        assert!(strings(&file, 12).is_empty());
+
+        let a = messages(&file, 4)[0];
+        let fields = messages(a, 2);
+        assert_eq!(vec![1], ints(fields[0], 3));
+        assert_eq!(vec![2], ints(fields[0], 4));
+        assert_eq!(vec!["-2"], strings(fields[0], 7));
+        assert_eq!(vec!["g"], strings(fields[1], 1));
+        assert_eq!(vec![10], ints(fields[1], 5));
+        assert_eq!(vec![".A.G"], strings(fields[1], 6));
+        let group = messages(a, 3)[0];
+        assert_eq!(vec!["G"], strings(group, 1));
+        assert_eq!(vec!["a\tb"], strings(messages(group, 2)[0], 7));
+        let range = messages(a, 5)[0];
+        assert_eq!((vec![100], vec![200]), (ints(range, 1), ints(range, 2)));
+
+        let ext = messages(&file, 7)[0];
+        assert_eq!(vec![".A"], strings(ext, 2));
+        assert_eq!(vec![3], ints(ext, 4));
+        assert!(ints(ext, 5).is_empty());
+        assert_eq!(vec!["Unknown"], strings(ext, 6));
+        assert_eq!(vec![1], ints(messages(ext, 8)[0], 2));
+
+        let mut buf = Vec::new();
+        int(&mut buf, 2, -1);
+        assert_eq!(vec![u64::MAX], ints(&buf, 2));
+        assert_eq!(11, buf.len());
+    }
+}
@@ -0,0 +1,554 @@
+//! Serialization back to .proto text
+//!
+//! Declarations are written with a 2 spaces indentation. The original layout is not kept:
+//! messages list their options, fields and oneofs (in declaration order, see
+//! `Message::members`), nested messages, enums and extensions, then reserved and extension
+//! ranges, enums their options, values then reserved ranges, files their options, messages,
+//! enums, extensions and services.
+
+use std::fmt::{self, Display, Formatter};
+use std::ops::{Range, RangeInclusive};
+
+use super::{Enumeration, EnumValue, Extension, Field, FieldType, FileDescriptor, ImportKind,
+            MemberRef, Message, Method, OneOf, ProtobufConstant, ProtobufOption, Rule, Service,
+            Syntax};
+use parser::{MAX_ENUM_VALUE, MAX_FIELD_NUMBER};
+
+fn indent(f: &mut Formatter, depth: usize) -> fmt::Result {
+    for _ in 0..depth {
+        f.write_str("  ")?;
+    }
+    Ok(())
+}
+
+/// A comment, as a `/* */` block if it spans several lines
+fn comment(f: &mut Formatter, comment: &str) -> fmt::Result {
+    if comment.contains('\n') {
+        write!(f, "/* {} */", comment)
+    } else {
+        write!(f, "// {}", comment)
+    }
+}
+
+/// Leading comments, each on its own line
+fn comments(f: &mut Formatter, depth: usize, comments: &[String]) -> fmt::Result {
+    for c in comments {
+        indent(f, depth)?;
+        comment(f, c)?;
+        writeln!(f)?;
+    }
+    Ok(())
+}
+
+/// Ends the line of a declaration, with its trailing comment
+fn end_line(f: &mut Formatter, trailing_comment: &Option<String>) -> fmt::Result {
+    if let Some(ref c) = *trailing_comment {
+        f.write_str(" ")?;
+        comment(f, c)?;
+    }
+    writeln!(f)
+}
+
+/// `option name = value;` statements
+fn options(f: &mut Formatter, depth: usize, options: &[ProtobufOption]) -> fmt::Result {
+    for o in options {
+        indent(f, depth)?;
+        writeln!(f, "option {} = {};", o.name, o.value)?;
+    }
+    Ok(())
+}
+
+/// Bracketed options, preceded by a space, if any
+///
+/// The value of the `default` option is `default` if set, i.e. as written in the parsed file.
+fn bracketed(f: &mut Formatter, options: &[ProtobufOption], default: Option<&str>) -> fmt::Result {
+    for (i, o) in options.iter().enumerate() {
+        f.write_str(if i == 0 { " [" } else { ", " })?;
+        match default {
+            Some(d) if o.name == "default" => write!(f, "{} = {}", o.name, d)?,
+            _ => write!(f, "{} = {}", o.name, o.value)?,
+        }
+    }
+    if !options.is_empty() {
+        f.write_str("]")?;
+    }
+    Ok(())
+}
+
+/// `reserved` or `extensions` ranges statement, `max` being the value written as `max`
+fn ranges(
+    f: &mut Formatter,
+    depth: usize,
+    keyword: &str,
+    ranges: &[RangeInclusive<i32>],
+    options: &[ProtobufOption],
+    max: i32,
+) -> fmt::Result {
+    if ranges.is_empty() {
+        return Ok(());
+    }
+    indent(f, depth)?;
+    f.write_str(keyword)?;
+    for (i, r) in ranges.iter().enumerate() {
+        f.write_str(if i == 0 { " " } else { ", " })?;
+        if r.start() == r.end() {
+            write!(f, "{}", r.start())?;
+        } else if *r.end() == max {
+            write!(f, "{} to max", r.start())?;
+        } else {
+            write!(f, "{} to {}", r.start(), r.end())?;
+        }
+    }
+    bracketed(f, options, None)?;
+    writeln!(f, ";")
+}
+
+/// Message ranges are end-exclusive
+fn end_inclusive(r: &Range<i32>) -> RangeInclusive<i32> {
+    r.start..=r.end - 1
+}
+
+/// `reserved` names statement
+fn reserved_names(f: &mut Formatter, depth: usize, names: &[String]) -> fmt::Result {
+    if names.is_empty() {
+        return Ok(());
+    }
+    indent(f, depth)?;
+    let names = names
+        .iter()
+        .map(|n| format!("\"{}\"", n))
+        .collect::<Vec<_>>();
+    writeln!(f, "reserved {};", names.join(", "))
+}
+
+fn field(f: &mut Formatter, depth: usize, field: &Field) -> fmt::Result {
+    comments(f, depth, &field.comments)?;
+    indent(f, depth)?;
+    match field.rule {
+        Rule::Singular => (),
+        Rule::Optional => f.write_str("optional ")?,
+        Rule::Repeated => f.write_str("repeated ")?,
+        Rule::Required => f.write_str("required ")?,
+    }
+    if let FieldType::Group(ref g) = field.typ {
+        write!(f, "group {} = {}", g.name, field.number)?;
+        bracketed(f, &field.options, field.default.as_deref())?;
+        writeln!(f, " {{")?;
+        for fd in &g.fields {
+            self::field(f, depth + 1, fd)?;
+        }
+        indent(f, depth)?;
+        f.write_str("}")?;
+    } else {
+        write!(f, "{} {} = {}", field.typ, field.name, field.number)?;
+        bracketed(f, &field.options, field.default.as_deref())?;
+        f.write_str(";")?;
+    }
+    end_line(f, &field.trailing_comment)
+}
+
+fn one_of(f: &mut Formatter, depth: usize, one_of: &OneOf) -> fmt::Result {
+    indent(f, depth)?;
+    writeln!(f, "oneof {} {{", one_of.name)?;
+    options(f, depth + 1, &one_of.options)?;
+    if one_of.deprecated && !one_of.options.iter().any(|o| o.name == "deprecated") {
+        indent(f, depth + 1)?;
+        writeln!(f, "option deprecated = true;")?;
+    }
+    for fd in &one_of.fields {
+        field(f, depth + 1, fd)?;
+    }
+    indent(f, depth)?;
+    writeln!(f, "}}")
+}
+
+fn message(f: &mut Formatter, depth: usize, msg: &Message) -> fmt::Result {
+    comments(f, depth, &msg.comments)?;
+    indent(f, depth)?;
+    writeln!(f, "message {} {{", msg.name)?;
+    options(f, depth + 1, &msg.options)?;
+    for member in msg.members() {
+        match member {
+            MemberRef::Field(i) => field(f, depth + 1, &msg.fields[i])?,
+            MemberRef::OneOf(i) => one_of(f, depth + 1, &msg.oneofs[i])?,
+        }
+    }
+    for m in &msg.messages {
+        message(f, depth + 1, m)?;
+    }
+    for e in &msg.enums {
+        enumeration(f, depth + 1, e)?;
+    }
+    extensions(f, depth + 1, &msg.extensions)?;
+    let max = MAX_FIELD_NUMBER;
+    let nums = msg.reserved_nums.iter().map(end_inclusive).collect::<Vec<_>>();
+    ranges(f, depth + 1, "reserved", &nums, &[], max)?;
+    reserved_names(f, depth + 1, &msg.reserved_names)?;
+    // consecutive ranges sharing their options are written in the same statement
+    for group in msg.extension_ranges.chunk_by(|a, b| a.options == b.options) {
+        let nums = group.iter().map(|r| end_inclusive(&r.range)).collect::<Vec<_>>();
+        ranges(f, depth + 1, "extensions", &nums, &group[0].options, max)?;
+    }
+    indent(f, depth)?;
+    f.write_str("}")?;
+    end_line(f, &msg.trailing_comment)
+}
+
+fn enum_value(f: &mut Formatter, depth: usize, value: &EnumValue) -> fmt::Result {
+    indent(f, depth)?;
+    write!(f, "{} = {}", value.name, value.number)?;
+    bracketed(f, &value.options, None)?;
+    writeln!(f, ";")
+}
+
+fn enumeration(f: &mut Formatter, depth: usize, e: &Enumeration) -> fmt::Result {
+    comments(f, depth, &e.comments)?;
+    indent(f, depth)?;
+    writeln!(f, "enum {} {{", e.name)?;
+    options(f, depth + 1, &e.options)?;
+    for v in &e.values {
+        enum_value(f, depth + 1, v)?;
+    }
+    ranges(f, depth + 1, "reserved", &e.reserved_nums, &[], MAX_ENUM_VALUE)?;
+    reserved_names(f, depth + 1, &e.reserved_names)?;
+    indent(f, depth)?;
+    f.write_str("}")?;
+    end_line(f, &e.trailing_comment)
+}
+
+/// Consecutive extensions of the same extendee are written in the same `extend` block
+fn extensions(f: &mut Formatter, depth: usize, extensions: &[Extension]) -> fmt::Result {
+    let mut start = 0;
+    while start < extensions.len() {
+        let extendee = &extensions[start].extendee;
+        let len = extensions[start..]
+            .iter()
+            .take_while(|e| e.extendee == *extendee)
+            .count();
+        if start > 0 {
+            writeln!(f)?;
+        }
+        indent(f, depth)?;
+        writeln!(f, "extend {} {{", extendee)?;
+        for e in &extensions[start..start + len] {
+            field(f, depth + 1, &e.field)?;
+        }
+        indent(f, depth)?;
+        writeln!(f, "}}")?;
+        start += len;
+    }
+    Ok(())
+}
+
+fn method(f: &mut Formatter, depth: usize, m: &Method) -> fmt::Result {
+    let stream = |s| if s { "stream " } else { "" };
+    comments(f, depth, &m.comments)?;
+    indent(f, depth)?;
+    write!(
+        f,
+        "rpc {}({}{}) returns ({}{})",
+        m.name,
+        stream(m.client_streaming),
+        m.input_type,
+        stream(m.server_streaming),
+        m.output_type
+    )?;
+    if m.options.is_empty() {
+        f.write_str(";")?;
+    } else {
+        writeln!(f, " {{")?;
+        options(f, depth + 1, &m.options)?;
+        indent(f, depth)?;
+        f.write_str("}")?;
+    }
+    end_line(f, &m.trailing_comment)
+}
+
+fn service(f: &mut Formatter, depth: usize, s: &Service) -> fmt::Result {
+    comments(f, depth, &s.comments)?;
+    indent(f, depth)?;
+    writeln!(f, "service {} {{", s.name)?;
+    options(f, depth + 1, &s.options)?;
+    for m in &s.methods {
+        method(f, depth + 1, m)?;
+    }
+    indent(f, depth)?;
+    f.write_str("}")?;
+    end_line(f, &s.trailing_comment)
+}
+
+impl Display for ProtobufConstant {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            ProtobufConstant::Bool(b) => write!(f, "{}", b),
+            ProtobufConstant::Int(i) => write!(f, "{}", i),
+            ProtobufConstant::Float(x) if x.is_nan() => {
+                f.write_str(if x.is_sign_negative() { "-nan" } else { "nan" })
+            }
+            ProtobufConstant::Float(x) if x.is_infinite() => {
+                f.write_str(if x < 0. { "-inf" } else { "inf" })
+            }
+            // debug formatting keeps the decimal point, so the value is not read back as an int
+            ProtobufConstant::Float(x) => write!(f, "{:?}", x),
+            ProtobufConstant::String(ref s) => {
+                f.write_str("\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' | '\\' => write!(f, "\\{}", c)?,
+                        '\n' => f.write_str("\\n")?,
+                        '\r' => f.write_str("\\r")?,
+                        '\t' => f.write_str("\\t")?,
+                        _ if c.is_ascii_control() => write!(f, "\\{:03o}", c as u8)?,
+                        _ => write!(f, "{}", c)?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            ProtobufConstant::Ident(ref s) | ProtobufConstant::Aggregate(ref s) => {
+                f.write_str(s)
+            }
+            ProtobufConstant::Message(ref fields) if fields.is_empty() => f.write_str("{}"),
+            ProtobufConstant::Message(ref fields) => {
+                f.write_str("{")?;
+                for (key, value) in fields {
+                    match *value {
+                        ProtobufConstant::Message(_) => write!(f, " {} {}", key, value)?,
+                        _ => write!(f, " {}: {}", key, value)?,
+                    }
+                }
+                f.write_str(" }")
+            }
+        }
+    }
+}
+
+impl Display for FieldType {
+    /// The type as written in a field declaration, the name of the group for groups
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match *self {
+            FieldType::Int32 => "int32",
+            FieldType::Int64 => "int64",
+            FieldType::Uint32 => "uint32",
+            FieldType::Uint64 => "uint64",
+            FieldType::Sint32 => "sint32",
+            FieldType::Sint64 => "sint64",
+            FieldType::Bool => "bool",
+            FieldType::Fixed64 => "fixed64",
+            FieldType::Sfixed64 => "sfixed64",
+            FieldType::Double => "double",
+            FieldType::String => "string",
+            FieldType::Bytes => "bytes",
+            FieldType::Fixed32 => "fixed32",
+            FieldType::Sfixed32 => "sfixed32",
+            FieldType::Float => "float",
+            FieldType::MessageOrEnum(ref name) => name,
+            FieldType::Map(ref map) => return write!(f, "map<{}, {}>", map.key, map.value),
+            FieldType::Group(ref g) => &g.name,
+        };
+        f.write_str(name)
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        field(f, 0, self)
+    }
+}
+
+impl Display for OneOf {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        one_of(f, 0, self)
+    }
+}
+
+impl Display for Message {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        message(f, 0, self)
+    }
+}
+
+impl Display for Enumeration {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        enumeration(f, 0, self)
+    }
+}
+
+impl Display for Service {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        service(f, 0, self)
+    }
+}
+
+impl Display for FileDescriptor {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match (self.edition.as_ref(), self.syntax) {
+            (Some(e), _) => writeln!(f, "edition = \"{}\";", e)?,
+            (None, Syntax::Proto2) => writeln!(f, "syntax = \"proto2\";")?,
+            (None, Syntax::Proto3) => writeln!(f, "syntax = \"proto3\";")?,
+        }
+        if !self.package.is_empty() {
+            writeln!(f, "\npackage {};", self.package)?;
+        }
+        if !self.imports.is_empty() {
+            writeln!(f)?;
+        }
+        for i in &self.imports {
+            match i.kind {
+                ImportKind::Default => writeln!(f, "import \"{}\";", i.path)?,
+                ImportKind::Public => writeln!(f, "import public \"{}\";", i.path)?,
+                ImportKind::Weak => writeln!(f, "import weak \"{}\";", i.path)?,
+            }
+        }
+        if !self.options.is_empty() {
+            writeln!(f)?;
+        }
+        options(f, 0, &self.options)?;
+        for m in &self.messages {
+            writeln!(f)?;
+            message(f, 0, m)?;
+        }
+        for e in &self.enums {
+            writeln!(f)?;
+            enumeration(f, 0, e)?;
+        }
+        if !self.extensions.is_empty() {
+            writeln!(f)?;
+            extensions(f, 0, &self.extensions)?;
+        }
+        for s in &self.services {
+            writeln!(f)?;
+            service(f, 0, s)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip(proto: &str) -> String {
+        let desc = FileDescriptor::parse(proto).expect("parse");
+        let printed = desc.to_string();
+        let reparsed = FileDescriptor::parse(&printed)
+            .unwrap_or_else(|e| panic!("cannot parse {}: {:?}", printed, e));
+        assert_eq!(desc, reparsed, "{}", printed);
+        printed
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let proto = r#"
+            syntax = "proto2";
+            package foo.bar;
+            import "a.proto";
+            import public "b.proto";
+            option java_package = "com.foo";
+            option (my.opt) = { a: 1 };
+            option (my.str) = "a\tb\001" 'c"d\\';
+
+            // A message
+            message A {
+                option (msg.opt).x = -2;
+                required int32 a = 1 [default = 017, deprecated = true]; // a
+                repeated double b = 2 [packed = true, (f) = 1.0, (g) = -inf];
+                map<string, Nested> m = 3;
+                optional group G = 4 {
+                    optional string s = 1 [default = 'say "hi"'];
+                }
+                /* several
+                   lines */
+                oneof o {
+                    option deprecated = true;
+                    int32 c = 5;
+                    .foo.bar.A d = 6;
+                }
+                message Nested {
+                    optional E e = 1 [default = Y];
+                }
+                enum E {
+                    option allow_alias = true;
+                    X = 0;
+                    Y = 1 [deprecated = true];
+                }
+                extend A { optional A self_ext = 150; }
+                reserved 10, 12 to 15, 1000 to max;
+                reserved "x", "y";
+                extensions 100 to 199;
+                extensions 200, 300 [declaration = { number: 200 }, verification = UNVERIFIED];
+            }
+            enum Top {
+                Z = 0;
+                reserved 2, 5 to 9, 100 to max, 2147483646, 2147483647;
+                reserved "W";
+            }
+            extend A { optional int32 ext1 = 100; }
+            extend A { optional int32 ext2 = 101; }
+            extend Other { optional int32 ext3 = 1; }
+            // A service
+            service S {
+                option deprecated = true;
+                rpc M(A) returns (stream A); // M
+                rpc N(stream A) returns (A) { option (http) = { get: "/n" }; }
+            }
+        "#;
+        let printed = round_trip(proto);
+        assert!(printed.contains("reserved 2, 5 to 9, 100 to max, 2147483646, 2147483647;"));
+    }
+
+    #[test]
+    fn test_print() {
+        let proto = r#"syntax = "proto3";
+            message A {
+                // x
+                int32 x = 1;
+                map<int32, B.C> m = 2 [json_name = "mm"];
+                message B { message C {} }
+            }
+            service S { rpc M(A) returns (A); }
+        "#;
+        let expected = r#"syntax = "proto3";
+
+message A {
+  // x
+  int32 x = 1;
+  map<int32, B.C> m = 2 [json_name = "mm"];
+  message B {
+    message C {
+    }
+  }
+}
+
+service S {
+  rpc M(A) returns (A);
+}
+"#;
+        assert_eq!(expected, round_trip(proto));
+    }
+
+    #[test]
+    fn test_print_member_order() {
+        let proto = r#"syntax = "proto3";
+        message A {
+            int32 a = 1;
+            oneof o { int32 b = 2; }
+            int32 c = 3;
+        }"#;
+        let expected = r#"syntax = "proto3";
+
+message A {
+  int32 a = 1;
+  oneof o {
+    int32 b = 2;
+  }
+  int32 c = 3;
+}
+"#;
+        assert_eq!(expected, round_trip(proto));
+
+        let mut desc = FileDescriptor::parse(proto).unwrap();
+        desc.messages[0].declaration_order.clear();
+        let printed = desc.to_string();
+        assert!(printed.find("int32 c").unwrap() < printed.find("oneof o").unwrap());
+    }
+}
@@ -5,30 +5,60 @@
 
 #[macro_use]
 extern crate nom;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 
+mod builder;
+mod check;
+mod default_value;
+mod descriptor_proto;
+mod dependencies;
+mod display;
+mod merge;
 mod parser;
+mod resolve;
+mod tokenizer;
+mod validate;
+mod visitor;
+mod well_known;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read};
+use std::str;
+use std::ops::{Range, RangeInclusive};
 
-use std::ops::Range;
-use parser::file_descriptor;
+pub use builder::{FieldBuilder, FileDescriptorBuilder, MessageBuilder};
+pub use default_value::{DefaultValue, InvalidDefault};
+pub use dependencies::{topological_sort, CircularImport, FileDescriptorWithContext};
+pub use merge::MergeError;
+pub use parser::TopLevelIter;
+pub use resolve::{relative_type_name, Presence, ResolveError, TypeKind};
+pub use tokenizer::{Token, Tokenizer};
+pub use validate::{ValidationError, ValidationRule, Warning};
+pub use visitor::Visitor;
+pub use well_known::WellKnownType;
 
 /// Protobox syntax
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Syntax {
     /// Protobuf syntax [2](https://developers.google.com/protocol-buffers/docs/proto) (default)
+    #[default]
     Proto2,
     /// Protobuf syntax [3](https://developers.google.com/protocol-buffers/docs/proto3)
     Proto3,
 }
 
-impl Default for Syntax {
-    fn default() -> Syntax {
-        Syntax::Proto2
-    }
-}
-
 /// A field rule
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Rule {
+    /// No label, e.g. proto3 fields, oneof members or maps (see `Field::presence`)
+    Singular,
     /// A well-formed message can have zero or one of this field (but not more than one).
     Optional,
     /// This field can be repeated any number of times (including zero) in a well-formed message.
@@ -39,9 +69,8 @@ pub enum Rule {
 }
 
 /// Protobuf supported field types
-///
-/// TODO: Groups (even if deprecated)
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum FieldType {
     /// Protobuf int32
     ///
@@ -128,13 +157,100 @@ pub enum FieldType {
     /// Protobuf message or enum (holds the name)
     MessageOrEnum(String),
     /// Protobut map
-    Map(Box<(FieldType, FieldType)>),
+    Map(Box<MapType>),
     /// Protobuf group (deprecated)
-    Group(Vec<Field>),
+    Group(Group),
+}
+
+/// A proto2 group
+///
+/// A group declares both a nested message and a field, named after the lowercased group name.
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Group {
+    /// Group name, which is also the name of the implicit nested message
+    pub name: String,
+    /// Group fields
+    pub fields: Vec<Field>,
+}
+
+/// Key and value types of a map field, `map<key, value>`
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MapType {
+    /// Key type, an integral type, `bool` or `string`
+    pub key: FieldType,
+    /// Value type, any type but a map
+    pub value: FieldType,
+}
+
+/// Protobuf wire type, the low 3 bits of a field key
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum WireType {
+    /// Variable-length integer (0)
+    Varint = 0,
+    /// Eight bytes (1)
+    Fixed64 = 1,
+    /// Length-prefixed bytes: strings, bytes, messages, maps and packed fields (2)
+    LengthDelimited = 2,
+    /// Start of a group (3)
+    StartGroup = 3,
+    /// End of a group (4)
+    EndGroup = 4,
+    /// Four bytes (5)
+    Fixed32 = 5,
+}
+
+impl FieldType {
+    /// Wire type of a single value of this type
+    ///
+    /// `MessageOrEnum` is assumed to be a message, hence length-delimited: enums are encoded as
+    /// varints, use `FileDescriptor::resolve_kind` to tell them apart. Packed repeated fields
+    /// are encoded as a single length-delimited record whatever their element type.
+    pub fn wire_type(&self) -> WireType {
+        match *self {
+            FieldType::Int32
+            | FieldType::Int64
+            | FieldType::Uint32
+            | FieldType::Uint64
+            | FieldType::Sint32
+            | FieldType::Sint64
+            | FieldType::Bool => WireType::Varint,
+            FieldType::Fixed64 | FieldType::Sfixed64 | FieldType::Double => WireType::Fixed64,
+            FieldType::Fixed32 | FieldType::Sfixed32 | FieldType::Float => WireType::Fixed32,
+            FieldType::String
+            | FieldType::Bytes
+            | FieldType::MessageOrEnum(_)
+            | FieldType::Map(_) => WireType::LengthDelimited,
+            FieldType::Group(_) => WireType::StartGroup,
+        }
+    }
+
+    /// Number of bytes a value of this type always takes on the wire
+    ///
+    /// Only `fixed32`, `sfixed32` and `float` (4 bytes) and `fixed64`, `sfixed64` and `double`
+    /// (8 bytes) have a fixed size. Varints and length-delimited types return `None`.
+    pub fn fixed_wire_size(&self) -> Option<usize> {
+        match *self {
+            FieldType::Fixed32 | FieldType::Sfixed32 | FieldType::Float => Some(4),
+            FieldType::Fixed64 | FieldType::Sfixed64 | FieldType::Double => Some(8),
+            _ => None,
+        }
+    }
+
+    /// Type of the encoded elements: the value type for a `Map`, `self` otherwise
+    pub fn element_type(&self) -> &FieldType {
+        match *self {
+            FieldType::Map(ref map) => &map.value,
+            _ => self,
+        }
+    }
 }
 
 /// A Protobuf Field
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Field {
     /// Field name
     pub name: String,
@@ -150,10 +266,88 @@ pub struct Field {
     pub packed: Option<bool>,
     /// Is the field deprecated
     pub deprecated: bool,
+    /// Explicit `json_name` option
+    pub json_name: Option<String>,
+    /// All the field options, `default`, `packed`, `deprecated` and `json_name` included, in
+    /// declaration order
+    pub options: Vec<ProtobufOption>,
+    /// Is the field a member of a `OneOf`
+    pub in_oneof: bool,
+    /// Kind of the `FieldType::MessageOrEnum` type of the field (of the value type for maps),
+    /// if it is known
+    ///
+    /// Parsing sets it for the types declared in the file, `resolve_types` and
+    /// `with_resolved_imports` for the imported ones too.
+    pub type_kind: Option<TypeKind>,
+    /// Comments immediately preceding the field, without their `//` or `/* */` markers
+    ///
+    /// A blank line between a comment and the field breaks the association.
+    pub comments: Vec<String>,
+    /// Comment following the field on the same line
+    pub trailing_comment: Option<String>,
+    /// Position in the .proto file (see `FileDescriptor::parse_with_spans`)
+    pub span: Option<Span>,
+}
+
+impl Field {
+    /// Can this field use the packed encoding
+    ///
+    /// Only repeated fields of numeric, `bool` or enum types can be packed. `kind` gives the
+    /// kind of a `FieldType::MessageOrEnum` type from its name, e.g. `Field::type_kind` or
+    /// `FileDescriptor::resolve_kind` from the scope of the field. Types of unknown kind are
+    /// considered messages.
+    pub fn is_packable<F>(&self, kind: F) -> bool
+    where
+        F: FnOnce(&str) -> Option<TypeKind>,
+    {
+        self.rule == Rule::Repeated
+            && match self.typ {
+                FieldType::String
+                | FieldType::Bytes
+                | FieldType::Map(_)
+                | FieldType::Group(_) => false,
+                FieldType::MessageOrEnum(ref name) => kind(name) == Some(TypeKind::Enum),
+                _ => true,
+            }
+    }
+
+    /// Type of each encoded element
+    ///
+    /// This is the type of a single value for repeated fields and the value type for maps (see
+    /// `FieldType::element_type`).
+    pub fn element_type(&self) -> &FieldType {
+        self.typ.element_type()
+    }
+
+    /// Clears the comments of the field and of its group fields
+    fn strip_comments(&mut self) {
+        self.comments.clear();
+        self.trailing_comment = None;
+        if let FieldType::Group(ref mut g) = self.typ {
+            for f in &mut g.fields {
+                f.strip_comments();
+            }
+        }
+    }
+}
+
+/// A range of field numbers reserved for extensions by an `extensions` statement
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ExtensionRange {
+    /// Field numbers
+    ///
+    /// A single number `n` is stored as `n..n + 1`, `max` as the largest field number.
+    pub range: Range<i32>,
+    /// Options of the statement (e.g. `declaration`), in declaration order
+    ///
+    /// All the ranges of a statement share its options.
+    pub options: Vec<ProtobufOption>,
 }
 
 /// A protobuf message
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Message {
     /// Message name
     pub name: String,
@@ -161,46 +355,251 @@ pub struct Message {
     pub fields: Vec<Field>,
     /// Message `OneOf`s
     pub oneofs: Vec<OneOf>,
-    /// Message reserved numbers
+    /// Order in which `fields` and `oneofs` are declared, see `Message::members`
+    pub declaration_order: Vec<MemberRef>,
+    /// Message reserved numbers, in declaration order
+    ///
+    /// A single number `n` is stored as `n..n + 1`, `max` as the largest field number.
     ///
     /// TODO: use RangeInclusive once stable
     pub reserved_nums: Vec<Range<i32>>,
     /// Message reserved names
     pub reserved_names: Vec<String>,
+    /// Extension ranges, in declaration order
+    pub extension_ranges: Vec<ExtensionRange>,
+    /// Extensions declared in the message body, in declaration order
+    ///
+    /// Extendees are kept as written, i.e. relative to the message scope.
+    pub extensions: Vec<Extension>,
     /// Nested messages
     pub messages: Vec<Message>,
     /// Nested enums
     pub enums: Vec<Enumeration>,
+    /// Message options, in declaration order
+    pub options: Vec<ProtobufOption>,
+    /// Comments immediately preceding the message, without their `//` or `/* */` markers
+    ///
+    /// A blank line between a comment and the message breaks the association.
+    pub comments: Vec<String>,
+    /// Comment following the message on the same line
+    pub trailing_comment: Option<String>,
+    /// Position in the .proto file (see `FileDescriptor::parse_with_spans`)
+    pub span: Option<Span>,
+}
+
+impl Message {
+    /// Number of messages, including this one and all nested messages
+    fn count_recursive(&self) -> usize {
+        1 + self.messages.iter().map(Message::count_recursive).sum::<usize>()
+    }
+
+    /// Nesting depth, 1 for a message without nested messages
+    fn depth(&self) -> usize {
+        1 + self.messages.iter().map(Message::depth).max().unwrap_or(0)
+    }
+
+    /// Clears the comments of the message and of all its declarations
+    fn strip_comments(&mut self) {
+        self.comments.clear();
+        self.trailing_comment = None;
+        for f in &mut self.fields {
+            f.strip_comments();
+        }
+        for o in &mut self.oneofs {
+            for f in &mut o.fields {
+                f.strip_comments();
+            }
+        }
+        for m in &mut self.messages {
+            m.strip_comments();
+        }
+        for e in &mut self.enums {
+            e.strip_comments();
+        }
+        for e in &mut self.extensions {
+            e.field.strip_comments();
+        }
+    }
+
+    /// Pushes this message then all its nested messages, depth first
+    fn collect_recursive<'a>(&'a self, messages: &mut Vec<&'a Message>) {
+        messages.push(self);
+        for m in &self.messages {
+            m.collect_recursive(messages);
+        }
+    }
+
+    /// All messages nested in this one, at any depth (depth first)
+    ///
+    /// The message itself and groups are not included.
+    pub fn descendants(&self) -> impl Iterator<Item = &Message> {
+        let mut messages = Vec::new();
+        for m in &self.messages {
+            m.collect_recursive(&mut messages);
+        }
+        messages.into_iter()
+    }
+
+    /// Fields and oneofs of the message, in declaration order
+    ///
+    /// If `declaration_order` does not list all the fields and oneofs (e.g. for a message
+    /// built by hand), fields come first then oneofs.
+    pub fn members(&self) -> Vec<MemberRef> {
+        let mut fields = vec![false; self.fields.len()];
+        let mut oneofs = vec![false; self.oneofs.len()];
+        let complete = self.declaration_order.len() == fields.len() + oneofs.len()
+            && self.declaration_order.iter().all(|m| {
+                let seen = match *m {
+                    MemberRef::Field(i) => fields.get_mut(i),
+                    MemberRef::OneOf(i) => oneofs.get_mut(i),
+                };
+                // each member listed once
+                match seen {
+                    Some(seen) if !*seen => {
+                        *seen = true;
+                        true
+                    }
+                    _ => false,
+                }
+            });
+        if complete {
+            return self.declaration_order.clone();
+        }
+        (0..self.fields.len())
+            .map(MemberRef::Field)
+            .chain((0..self.oneofs.len()).map(MemberRef::OneOf))
+            .collect()
+    }
+
+    /// Returns references to the message `Field`s (not including `OneOf`s) ordered by `strategy`
+    pub fn fields_ordered_by(&self, strategy: FieldOrder) -> Vec<&Field> {
+        let mut fields = self.fields.iter().collect::<Vec<_>>();
+        match strategy {
+            FieldOrder::Declaration => (),
+            FieldOrder::Number => fields.sort_by_key(|f| f.number),
+            FieldOrder::Size => fields.sort_by(|a, b| {
+                let a = a.typ.fixed_wire_size().unwrap_or(0);
+                let b = b.typ.fixed_wire_size().unwrap_or(0);
+                b.cmp(&a)
+            }),
+        }
+        fields
+    }
+
+    /// Fields of the message then fields of its oneofs
+    fn fields_and_oneof_fields(&self) -> impl Iterator<Item = &Field> {
+        self.fields
+            .iter()
+            .chain(self.oneofs.iter().flat_map(|o| o.fields.iter()))
+    }
+
+    /// The field numbered `number`, oneof members included
+    ///
+    /// Fields are searched before oneof members, the first match is returned. Fields of
+    /// groups belong to the group and are not searched.
+    pub fn field_by_number(&self, number: i32) -> Option<&Field> {
+        self.fields_and_oneof_fields().find(|f| f.number == number)
+    }
+
+    /// The field named `name`, oneof members included
+    ///
+    /// Fields are searched before oneof members, the first match is returned.
+    pub fn field_by_name(&self, name: &str) -> Option<&Field> {
+        self.fields_and_oneof_fields().find(|f| f.name == name)
+    }
+}
+
+/// A field or a oneof of a message, see `Message::declaration_order`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MemberRef {
+    /// Index in `Message::fields`
+    Field(usize),
+    /// Index in `Message::oneofs`
+    OneOf(usize),
+}
+
+/// Strategy used to order fields in `Message::fields_ordered_by`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum FieldOrder {
+    /// Order in which fields are declared in the .proto file
+    Declaration,
+    /// Ascending tag number
+    Number,
+    /// Descending `FieldType::fixed_wire_size`, fields without a fixed size come last
+    ///
+    /// Fields of the same size keep their declaration order.
+    Size,
 }
 
 /// A protobuf enumeration field
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct EnumValue {
     /// enum value name
     pub name: String,
     /// enum value number
     pub number: i32,
+    /// enum value options, in declaration order
+    pub options: Vec<ProtobufOption>,
+    /// Position in the .proto file (see `FileDescriptor::parse_with_spans`)
+    pub span: Option<Span>,
 }
 
 /// A protobuf enumerator
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Enumeration {
     /// enum name
     pub name: String,
     /// enum values
     pub values: Vec<EnumValue>,
+    /// enum options, in declaration order
+    pub options: Vec<ProtobufOption>,
+    /// Is `option allow_alias = true;` set, allowing several values to share a number
+    pub allow_alias: bool,
+    /// Enum reserved numbers, in declaration order
+    ///
+    /// Unlike message ranges, enum ranges include their end: a single number `n` is stored as
+    /// `n..=n`, `max` as `i32::MAX`.
+    pub reserved_nums: Vec<RangeInclusive<i32>>,
+    /// Enum reserved names
+    pub reserved_names: Vec<String>,
+    /// Comments immediately preceding the enum, without their `//` or `/* */` markers
+    ///
+    /// A blank line between a comment and the enum breaks the association.
+    pub comments: Vec<String>,
+    /// Comment following the enum on the same line
+    pub trailing_comment: Option<String>,
+    /// Position in the .proto file (see `FileDescriptor::parse_with_spans`)
+    pub span: Option<Span>,
+}
+
+impl Enumeration {
+    fn strip_comments(&mut self) {
+        self.comments.clear();
+        self.trailing_comment = None;
+    }
 }
 
 /// A OneOf
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct OneOf {
     /// OneOf name
     pub name: String,
     /// OneOf fields
     pub fields: Vec<Field>,
+    /// Is the oneof deprecated
+    pub deprecated: bool,
+    /// OneOf options, `deprecated` included, in declaration order
+    pub options: Vec<ProtobufOption>,
+    /// Position in the .proto file (see `FileDescriptor::parse_with_spans`)
+    pub span: Option<Span>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Extension {
     /// Extend this type with field
     pub extendee: String,
@@ -208,37 +607,1273 @@ pub struct Extension {
     pub field: Field,
 }
 
+/// A service method, `rpc Name (Input) returns (Output);`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Method {
+    /// Method name
+    pub name: String,
+    /// Request message type
+    pub input_type: String,
+    /// Response message type
+    pub output_type: String,
+    /// Is the request a stream (`(stream Input)`)
+    pub client_streaming: bool,
+    /// Is the response a stream (`returns (stream Output)`)
+    pub server_streaming: bool,
+    /// Method options, in declaration order
+    pub options: Vec<ProtobufOption>,
+    /// Comments immediately preceding the method, without their `//` or `/* */` markers
+    ///
+    /// A blank line between a comment and the method breaks the association.
+    pub comments: Vec<String>,
+    /// Comment following the method on the same line
+    pub trailing_comment: Option<String>,
+    /// Position in the .proto file (see `FileDescriptor::parse_with_spans`)
+    pub span: Option<Span>,
+}
+
+/// A service
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Service {
+    /// Service name
+    pub name: String,
+    /// Service methods
+    pub methods: Vec<Method>,
+    /// Service options, in declaration order
+    pub options: Vec<ProtobufOption>,
+    /// Comments immediately preceding the service, without their `//` or `/* */` markers
+    ///
+    /// A blank line between a comment and the service breaks the association.
+    pub comments: Vec<String>,
+    /// Comment following the service on the same line
+    pub trailing_comment: Option<String>,
+    /// Position in the .proto file (see `FileDescriptor::parse_with_spans`)
+    pub span: Option<Span>,
+}
+
+/// Kind of an `Import`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ImportKind {
+    /// `import "path";`
+    Default,
+    /// `import public "path";`, the imported definitions are visible to files importing this one
+    Public,
+    /// `import weak "path";`, the imported file may not exist
+    Weak,
+}
+
+/// An import statement
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Import {
+    /// Imported file path
+    pub path: String,
+    /// Import kind
+    pub kind: ImportKind,
+}
+
+/// A constant option value
+///
+/// Floats are compared (and hashed) by their bits so `NaN` equals itself.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ProtobufConstant {
+    /// `true` or `false`
+    Bool(bool),
+    /// An integer, decimal, octal or hexadecimal
+    Int(i64),
+    /// A float, including `inf` and `nan`
+    Float(f64),
+    /// A quoted string, without the quotes and unescaped
+    ///
+    /// Adjacent strings (`"a" "b"`) are concatenated.
+    String(String),
+    /// An identifier, e.g. an enum value
+    Ident(String),
+    /// A message literal `{ a: 1 b { c: "x" } }`, its fields in declaration order
+    ///
+    /// List values (`a: [1, 2]`) are flattened into repeated fields, extension keys are kept
+    /// with their brackets (e.g. `[my.ext]`).
+    Message(Vec<(String, ProtobufConstant)>),
+    /// A message literal `{ ... }` which could not be parsed as a `Message`, as written
+    Aggregate(String),
+}
+
+impl ProtobufConstant {
+    /// Value of the field `name` of a `Message` constant, the last one if it is repeated
+    pub fn field(&self, name: &str) -> Option<&ProtobufConstant> {
+        match *self {
+            ProtobufConstant::Message(ref fields) => fields
+                .iter()
+                .rev()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+impl PartialEq for ProtobufConstant {
+    fn eq(&self, other: &ProtobufConstant) -> bool {
+        use ProtobufConstant::*;
+        match (self, other) {
+            (&Bool(a), &Bool(b)) => a == b,
+            (&Int(a), &Int(b)) => a == b,
+            (&Float(a), &Float(b)) => a.to_bits() == b.to_bits(),
+            (&String(ref a), &String(ref b))
+            | (&Ident(ref a), &Ident(ref b))
+            | (&Aggregate(ref a), &Aggregate(ref b)) => a == b,
+            (Message(a), Message(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for ProtobufConstant {}
+
+impl Hash for ProtobufConstant {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        ::std::mem::discriminant(self).hash(state);
+        match *self {
+            ProtobufConstant::Bool(b) => b.hash(state),
+            ProtobufConstant::Int(i) => i.hash(state),
+            ProtobufConstant::Float(f) => f.to_bits().hash(state),
+            ProtobufConstant::String(ref s)
+            | ProtobufConstant::Ident(ref s)
+            | ProtobufConstant::Aggregate(ref s) => s.hash(state),
+            ProtobufConstant::Message(ref fields) => fields.hash(state),
+        }
+    }
+}
+
+/// An option, `name = value`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ProtobufOption {
+    /// Option name, custom options are parenthesized, e.g. `(my.option).field`
+    pub name: String,
+    /// Option value
+    pub value: ProtobufConstant,
+}
+
+/// A top level declaration of a .proto file, see `FileDescriptor::iter_top_level`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TopLevel {
+    /// `syntax = "...";`
+    Syntax(Syntax),
+    /// `edition = "...";`, the edition without quotes
+    Edition(String),
+    /// `package ...;`
+    Package(String),
+    /// `import "...";`
+    Import(Import),
+    /// `option ... = ...;`
+    Option(ProtobufOption),
+    /// A message
+    Message(Message),
+    /// An enum
+    Enum(Enumeration),
+    /// An `extend` block, its fields in declaration order
+    Extensions(Vec<Extension>),
+    /// A service
+    Service(Service),
+}
+
 /// A File descriptor representing a whole .proto file
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct FileDescriptor {
     /// Imports
-    pub import_paths: Vec<String>,
+    pub imports: Vec<Import>,
     /// Package
     pub package: String,
     /// Protobuf Syntax
     pub syntax: Syntax,
+    /// Edition declared by an `edition = "2023";` statement, without quotes
+    ///
+    /// Editions replace the `syntax` statement, `syntax` is then left to its default.
+    pub edition: Option<String>,
     /// Top level messages
     pub messages: Vec<Message>,
     /// Enums
     pub enums: Vec<Enumeration>,
     /// Extensions
     pub extensions: Vec<Extension>,
+    /// File options, in declaration order
+    pub options: Vec<ProtobufOption>,
+    /// Services
+    pub services: Vec<Service>,
 }
 
 impl FileDescriptor {
     /// Parses a .proto file content into a `FileDescriptor`
-    pub fn parse<S: AsRef<[u8]>>(file: S) -> Result<Self, ::nom::IError> {
-        let file = file.as_ref();
-        match file_descriptor(file) {
-            ::nom::IResult::Done(unparsed, r) => {
-                if !unparsed.is_empty() {
-                    // TODO: make error detection part of parser and report position
-                    Err(::nom::IError::Error(::nom::ErrorKind::NoneOf))
-                } else {
-                    Ok(r)
+    ///
+    /// A leading byte order mark is skipped, positions (including `Span`s) are then relative
+    /// to the content following it. Both `\n` and `\r\n` line endings are supported.
+    pub fn parse<S: AsRef<[u8]>>(file: S) -> Result<Self, ParserErrorWithLocation> {
+        FileDescriptor::parse_inner(file.as_ref(), &ParserOptions::default(), false)
+    }
+
+    /// Parses a .proto file content into a `FileDescriptor` with custom `ParserOptions`
+    pub fn parse_with_options<S: AsRef<[u8]>>(
+        file: S,
+        options: &ParserOptions,
+    ) -> Result<Self, ParserErrorWithLocation> {
+        FileDescriptor::parse_inner(file.as_ref(), options, false)
+    }
+
+    /// Number of messages declared in the file, nested messages included
+    ///
+    /// Groups are not counted.
+    pub fn message_count_recursive(&self) -> usize {
+        self.messages.iter().map(Message::count_recursive).sum()
+    }
+
+    /// Maximum nesting depth of messages, 1 if no message has nested messages and 0 if there
+    /// is no message at all
+    pub fn max_message_depth(&self) -> usize {
+        self.messages.iter().map(Message::depth).max().unwrap_or(0)
+    }
+
+    /// All messages declared in the file, nested messages included (depth first)
+    ///
+    /// Groups are not included.
+    pub fn all_messages(&self) -> impl Iterator<Item = &Message> {
+        let mut messages = Vec::new();
+        for m in &self.messages {
+            m.collect_recursive(&mut messages);
+        }
+        messages.into_iter()
+    }
+
+    /// All fields of all messages (see `all_messages`), paired with their message
+    ///
+    /// Map fields and `OneOf` fields are included, the fields of a message coming before its
+    /// oneof fields. Fields of groups and extensions are not included.
+    pub fn all_fields(&self) -> impl Iterator<Item = (&Message, &Field)> {
+        self.all_messages().flat_map(|m| {
+            m.fields
+                .iter()
+                .chain(m.oneofs.iter().flat_map(|o| o.fields.iter()))
+                .map(move |f| (m, f))
+        })
+    }
+
+    /// Names of the custom options used in the file, without their parentheses
+    ///
+    /// E.g. `my.ext` for `option (my.ext).field = 1;`. Options of the file, messages, fields
+    /// (group fields excepted), oneofs, enums, enum values, services and methods are collected.
+    pub fn custom_option_names(&self) -> HashSet<String> {
+        let messages = self.all_messages().collect::<Vec<_>>();
+        let enums = self
+            .enums
+            .iter()
+            .chain(messages.iter().flat_map(|m| m.enums.iter()));
+        let options = self
+            .options
+            .iter()
+            .chain(messages.iter().flat_map(|m| m.options.iter()))
+            .chain(
+                messages
+                    .iter()
+                    .flat_map(|m| m.oneofs.iter().flat_map(|o| o.options.iter())),
+            )
+            .chain(self.all_fields().flat_map(|(_, f)| f.options.iter()))
+            .chain(self.extensions.iter().flat_map(|e| e.field.options.iter()))
+            .chain(
+                messages
+                    .iter()
+                    .flat_map(|m| m.extensions.iter().flat_map(|e| e.field.options.iter())),
+            )
+            .chain(enums.flat_map(|e| {
+                e.options
+                    .iter()
+                    .chain(e.values.iter().flat_map(|v| v.options.iter()))
+            }))
+            .chain(self.services.iter().flat_map(|s| {
+                s.options
+                    .iter()
+                    .chain(s.methods.iter().flat_map(|m| m.options.iter()))
+            }));
+        options
+            .filter(|o| o.name.starts_with('('))
+            .filter_map(|o| o.name[1..].split(')').next())
+            .map(|n| n.to_string())
+            .collect()
+    }
+
+    /// Extensions grouped by extendee, as declared (e.g. `google.protobuf.FileOptions`)
+    ///
+    /// Extensions keep their declaration order within each group. Extendees are not resolved,
+    /// the same type referenced by different names gives different groups. Extensions declared
+    /// in messages are not included (see `Message::extensions`).
+    pub fn extensions_by_extendee(&self) -> HashMap<String, Vec<&Extension>> {
+        let mut groups = HashMap::new();
+        for e in &self.extensions {
+            groups
+                .entry(e.extendee.clone())
+                .or_insert_with(Vec::new)
+                .push(e);
+        }
+        groups
+    }
+
+    /// Clears the comments of all the declarations of the file
+    ///
+    /// Useful to compare or hash files regardless of their comments.
+    pub fn strip_comments(&mut self) {
+        for m in &mut self.messages {
+            m.strip_comments();
+        }
+        for e in &mut self.enums {
+            e.strip_comments();
+        }
+        for e in &mut self.extensions {
+            e.field.strip_comments();
+        }
+        for s in &mut self.services {
+            s.comments.clear();
+            s.trailing_comment = None;
+            for m in &mut s.methods {
+                m.comments.clear();
+                m.trailing_comment = None;
+            }
+        }
+    }
+
+    /// All imports, in declaration order
+    pub fn dependencies(&self) -> &[Import] {
+        &self.imports
+    }
+
+    /// Paths of all imports
+    pub fn import_paths(&self) -> Vec<&str> {
+        self.imports.iter().map(|i| &*i.path).collect()
+    }
+
+    /// Paths of `import public` statements
+    pub fn public_imports(&self) -> Vec<&str> {
+        self.import_paths_of_kind(ImportKind::Public)
+    }
+
+    /// Paths of `import weak` statements
+    pub fn weak_imports(&self) -> Vec<&str> {
+        self.import_paths_of_kind(ImportKind::Weak)
+    }
+
+    /// Value of the file option `name` (e.g. `go_package` or `(my.opt).field`), `None` if unset
+    ///
+    /// If the option is set several times, the last value wins.
+    pub fn option(&self, name: &str) -> Option<&ProtobufConstant> {
+        self.options
+            .iter()
+            .rev()
+            .find(|o| o.name == name)
+            .map(|o| &o.value)
+    }
+
+    /// Value of the boolean file option `name`, `None` if unset or not a boolean
+    ///
+    /// If the option is set several times, the last value wins.
+    pub fn bool_option(&self, name: &str) -> Option<bool> {
+        match self.option(name) {
+            Some(&ProtobufConstant::Bool(b)) => Some(b),
+            _ => None,
+        }
+    }
+
+    fn import_paths_of_kind(&self, kind: ImportKind) -> Vec<&str> {
+        self.imports
+            .iter()
+            .filter(|i| i.kind == kind)
+            .map(|i| &*i.path)
+            .collect()
+    }
+
+    /// Parses a .proto file content into a `FileDescriptor`, recording the `Span` of each
+    /// message, field, oneof, enum, enum value, service and method
+    pub fn parse_with_spans<S: AsRef<[u8]>>(file: S) -> Result<Self, ParserErrorWithLocation> {
+        FileDescriptor::parse_inner(file.as_ref(), &ParserOptions::default(), true)
+    }
+
+    /// Parses a .proto file content into a best-effort `FileDescriptor`, skipping invalid
+    /// statements
+    ///
+    /// An invalid statement, top level or in a message or enum body, is skipped up to the next
+    /// `;` or `{ }` block, it is omitted from the returned `FileDescriptor` and reported in the
+    /// errors. Parsing only stops if the file ends in the middle of a declaration.
+    pub fn parse_recovering<S: AsRef<[u8]>>(file: S) -> (Self, Vec<ParserErrorWithLocation>) {
+        parser::file_descriptor(file.as_ref(), &ParserOptions::default(), false)
+    }
+
+    /// Iterates over the top level declarations of a .proto file content, parsing them lazily
+    ///
+    /// Declarations can be processed and dropped one by one, without building a whole
+    /// `FileDescriptor`. Invalid statements are skipped as in `parse_recovering`, each yielding
+    /// an error, and the errors of a message or enum come before it. Comments are attached as
+    /// by `parse`, but declarations are not checked (e.g. identifiers or field rules) and no
+    /// `Span` is recorded.
+    pub fn iter_top_level<'a, S>(file: &'a S) -> TopLevelIter<'a>
+    where
+        S: AsRef<[u8]> + ?Sized,
+    {
+        TopLevelIter::new(file.as_ref())
+    }
+
+    /// Parses a .proto file content, checking first that it is valid UTF-8
+    ///
+    /// A leading byte order mark is skipped, positions (including `Span`s) are then relative
+    /// to the content following it.
+    pub fn parse_bytes(bytes: &[u8]) -> Result<Self, ParserErrorWithLocation> {
+        let file = parser::strip_bom(bytes);
+        let bom = bytes.len() - file.len();
+        if let Err(e) = str::from_utf8(file) {
+            let loc = LineIndex::new(file).loc(e.valid_up_to());
+            return Err(ParserErrorWithLocation {
+                error: ParserError::InvalidUtf8 {
+                    offset: bom + e.valid_up_to(),
+                },
+                line: loc.line,
+                col: loc.col,
+            });
+        }
+        FileDescriptor::parse_inner(file, &ParserOptions::default(), false)
+    }
+
+    /// Reads a .proto file content from `reader` until its end and parses it (see
+    /// `FileDescriptor::parse_bytes`)
+    pub fn parse_reader<R: Read>(reader: R) -> Result<Self, ReadError> {
+        FileDescriptor::parse_reader_with_len(reader).map(|(desc, _)| desc)
+    }
+
+    /// Same as `FileDescriptor::parse_reader`, also returning the number of bytes read
+    pub fn parse_reader_with_len<R: Read>(mut reader: R) -> Result<(Self, usize), ReadError> {
+        let mut bytes = Vec::new();
+        let len = reader.read_to_end(&mut bytes).map_err(ReadError::Io)?;
+        let desc = FileDescriptor::parse_bytes(&bytes).map_err(ReadError::Parse)?;
+        Ok((desc, len))
+    }
+
+    fn parse_inner(
+        file: &[u8],
+        options: &ParserOptions,
+        with_spans: bool,
+    ) -> Result<Self, ParserErrorWithLocation> {
+        let (desc, errors) = parser::file_descriptor(file, options, with_spans);
+        match errors.into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(desc),
+        }
+    }
+}
+
+/// A function rewriting import paths, see `ParserOptions::import_rewrite`
+pub type ImportRewrite = Box<dyn Fn(&str) -> String>;
+
+/// Options for `FileDescriptor::parse_with_options`
+pub struct ParserOptions {
+    /// Reject names which are not valid protobuf identifiers (default `true`)
+    ///
+    /// Identifiers are a letter or `_` followed by letters, digits or `_`, type names are dot
+    /// separated identifiers. When `false`, names starting with a digit or containing `$` or
+    /// `-` are also accepted, to ingest non-conformant files.
+    pub strict_identifiers: bool,
+    /// Rewrites each import path, the rewritten path is the one stored in `Import::path`
+    ///
+    /// Useful for build systems remapping paths, e.g. stripping a prefix.
+    pub import_rewrite: Option<ImportRewrite>,
+    /// Syntax of files without `syntax` statement (default `Syntax::Proto2`)
+    ///
+    /// Useful for snippets whose syntax is known out-of-band.
+    pub default_syntax: Syntax,
+}
+
+impl Default for ParserOptions {
+    fn default() -> ParserOptions {
+        ParserOptions {
+            strict_identifiers: true,
+            import_rewrite: None,
+            default_syntax: Syntax::Proto2,
+        }
+    }
+}
+
+impl fmt::Debug for ParserOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ParserOptions")
+            .field("strict_identifiers", &self.strict_identifiers)
+            .field("import_rewrite", &self.import_rewrite.as_ref().map(|_| "Fn"))
+            .field("default_syntax", &self.default_syntax)
+            .finish()
+    }
+}
+
+/// A position in a .proto file
+///
+/// Positions are ordered by offset.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Loc {
+    /// Byte offset from the start of the file
+    pub offset: usize,
+    /// Line number, starting at 1
+    pub line: usize,
+    /// Column number (in chars), starting at 1
+    ///
+    /// `\r` characters are not counted so `\n` and `\r\n` line endings give the same columns
+    pub col: usize,
+}
+
+/// The offsets at which the lines of a file start, to compute the `Loc` of byte offsets
+#[derive(Debug, Clone)]
+struct LineIndex<'a> {
+    file: &'a [u8],
+    /// Offset of the first byte of each line, the first line starting at 0
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(file: &'a [u8]) -> LineIndex<'a> {
+        let starts = Some(0)
+            .into_iter()
+            .chain(file.iter().enumerate().filter(|&(_, &b)| b == b'\n').map(|(i, _)| i + 1))
+            .collect();
+        LineIndex { file, starts }
+    }
+
+    /// Computes the `Loc` of the byte `offset`
+    fn loc(&self, offset: usize) -> Loc {
+        let line = self.starts.partition_point(|&s| s <= offset);
+        let start = self.starts[line - 1];
+        // skip carriage returns and utf8 continuation bytes
+        let col = 1 + self.file[start..offset]
+            .iter()
+            .filter(|&&b| !matches!(b, b'\r' | 0x80..=0xBF))
+            .count();
+        Loc { offset, line, col }
+    }
+}
+
+/// The part of a .proto file a declaration was parsed from
+///
+/// Spans are ordered by start then end position, i.e. in source order.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Span {
+    /// Position of the first char
+    pub start: Loc,
+    /// Position right after the last char
+    pub end: Loc,
+}
+
+impl Span {
+    /// Byte range of the declaration, to slice the original .proto file content
+    pub fn range(&self) -> Range<usize> {
+        self.start.offset..self.end.offset
+    }
+}
+
+/// An error encountered while parsing a .proto file
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ParserError {
+    /// The file ended in the middle of a declaration
+    UnexpectedEof,
+    /// A `/*` comment is not closed by `*/` before the end of the file
+    UnterminatedComment,
+    /// The input is not a valid declaration
+    InvalidDeclaration,
+    /// The `syntax` statement is neither `"proto2"` nor `"proto3"`
+    InvalidSyntax(String),
+    /// The `syntax` statement is not the first declaration of the file
+    MisplacedSyntax,
+    /// A name is not a valid identifier (see `ParserOptions::strict_identifiers`)
+    InvalidIdentifier(String),
+    /// A message or an enum is declared in a oneof
+    TypeDefInOneof,
+    /// A `required` field is declared in a proto3 file
+    RequiredInProto3(String),
+    /// A field of a proto3 file has a `default` option, which only proto2 supports
+    DefaultInProto3(String),
+    /// A oneof field has a `optional`, `repeated` or `required` label
+    LabelInOneof(String),
+    /// A map field has a `optional`, `repeated` or `required` label
+    LabelOnMap(String),
+    /// A map field is declared in a oneof
+    MapInOneof(String),
+    /// A `required` field is declared in an editions file, where
+    /// `features.field_presence = LEGACY_REQUIRED` is used instead
+    RequiredInEdition(String),
+    /// An enum value reuses the number of a previous value without `allow_alias`
+    DuplicateEnumValue(String),
+    /// An integer literal, e.g. a field number or an enum value, does not fit in an `i32`
+    IntegerOverflow(String),
+    /// The input is not valid UTF-8, `offset` being the position of the first invalid byte
+    InvalidUtf8 {
+        /// Byte offset from the start of the input
+        offset: usize,
+    },
+}
+
+/// A `ParserError` with the position of the declaration which caused it
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParserErrorWithLocation {
+    /// The error
+    pub error: ParserError,
+    /// Line number, starting at 1
+    pub line: usize,
+    /// Column number, starting at 1
+    pub col: usize,
+}
+
+impl fmt::Display for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParserError::UnexpectedEof => f.write_str("unexpected end of file"),
+            ParserError::UnterminatedComment => f.write_str("unterminated block comment"),
+            ParserError::InvalidDeclaration => f.write_str("invalid declaration"),
+            ParserError::InvalidSyntax(ref s) => {
+                write!(f, "unknown syntax \"{}\", expected \"proto2\" or \"proto3\"", s)
+            }
+            ParserError::MisplacedSyntax => {
+                f.write_str("`syntax` must be the first declaration of the file")
+            }
+            ParserError::InvalidIdentifier(ref s) => write!(f, "invalid identifier `{}`", s),
+            ParserError::TypeDefInOneof => f.write_str("messages and enums cannot be in a oneof"),
+            ParserError::RequiredInProto3(ref s) => {
+                write!(f, "field `{}`: required fields are not allowed in proto3", s)
+            }
+            ParserError::DefaultInProto3(ref s) => write!(
+                f,
+                "field `{}`: explicit default values are only allowed in proto2",
+                s
+            ),
+            ParserError::LabelInOneof(ref s) => {
+                write!(f, "oneof field `{}` cannot have a label", s)
+            }
+            ParserError::LabelOnMap(ref s) => write!(f, "map field `{}` cannot have a label", s),
+            ParserError::MapInOneof(ref s) => {
+                write!(f, "map field `{}` cannot be declared in a oneof", s)
+            }
+            ParserError::RequiredInEdition(ref s) => write!(
+                f,
+                "field `{}`: use `features.field_presence = LEGACY_REQUIRED` instead of `required`",
+                s
+            ),
+            ParserError::DuplicateEnumValue(ref s) => write!(
+                f,
+                "enum value `{}` reuses a number, set `option allow_alias = true;` to allow it",
+                s
+            ),
+            ParserError::IntegerOverflow(ref s) => write!(f, "integer `{}` is out of range", s),
+            ParserError::InvalidUtf8 { offset } => {
+                write!(f, "invalid UTF-8 at byte offset {}", offset)
+            }
+        }
+    }
+}
+
+impl Error for ParserError {}
+
+impl fmt::Display for ParserErrorWithLocation {
+    /// `parse error at <line>:<col>: <error>`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "parse error at {}:{}: {}", self.line, self.col, self.error)
+    }
+}
+
+impl Error for ParserErrorWithLocation {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// An error of `FileDescriptor::parse_reader`
+#[derive(Debug)]
+pub enum ReadError {
+    /// The content could not be read
+    Io(io::Error),
+    /// The content is not valid UTF-8 or not a valid .proto file
+    Parse(ParserErrorWithLocation),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReadError::Io(ref e) => write!(f, "cannot read .proto file: {}", e),
+            ReadError::Parse(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ReadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ReadError::Io(ref e) => Some(e),
+            ReadError::Parse(ref e) => Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_display() {
+        let msg = "message A {\n    optional int32 a = 1;\n    optional int32 b;\n}";
+        let err = FileDescriptor::parse(msg).unwrap_err();
+        assert_eq!("parse error at 3:5: invalid declaration", err.to_string());
+
+        let err: Box<dyn Error> = Box::new(err);
+        assert_eq!(
+            Some("invalid declaration".to_string()),
+            err.source().map(|e| e.to_string())
+        );
+        assert_eq!(
+            "unknown syntax \"proto4\", expected \"proto2\" or \"proto3\"",
+            ParserError::InvalidSyntax("proto4".to_string()).to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_reader() {
+        let proto = b"syntax = \"proto3\";\nmessage A { int32 a = 1; }\n".to_vec();
+        let len = proto.len();
+        let (desc, read) = FileDescriptor::parse_reader_with_len(io::Cursor::new(proto)).unwrap();
+        assert_eq!(len, read);
+        assert_eq!("A", desc.messages[0].name);
+
+        let bytes = b"message A {}\n// \xff\n".to_vec();
+        match FileDescriptor::parse_reader(io::Cursor::new(bytes)) {
+            Err(ReadError::Parse(e)) => {
+                assert_eq!(ParserError::InvalidUtf8 { offset: 16 }, e.error)
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("broken"))
+            }
+        }
+        match FileDescriptor::parse_reader(Failing) {
+            Err(e @ ReadError::Io(_)) => {
+                assert_eq!("cannot read .proto file: broken", e.to_string())
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_eq_hash() {
+        let proto = r#"
+            syntax = "proto3";
+            option java_package = "a.b";
+            message A {
+                double d = 1 [(my.default) = 1.5];
+                oneof o { string s = 2; }
+                message B {}
+            }
+            enum E { X = 0; Y = 1 [deprecated = true]; }
+            extend A { int32 x = 100; }
+            service S { rpc M(A) returns (A); }
+        "#;
+        let a = FileDescriptor::parse(proto).unwrap();
+        let b = FileDescriptor::parse(proto).unwrap();
+        assert_eq!(a, b);
+        let set = vec![a.clone(), b].into_iter().collect::<HashSet<_>>();
+        assert_eq!(1, set.len());
+
+        let other = FileDescriptor::parse(proto.replace("Y = 1", "Y = 2")).unwrap();
+        assert_ne!(a, other);
+        let messages = a.messages.iter().chain(&other.messages).collect::<HashSet<_>>();
+        assert_eq!(1, messages.len());
+        let enums = a.enums.iter().chain(&other.enums).collect::<HashSet<_>>();
+        assert_eq!(2, enums.len());
+    }
+
+    #[test]
+    fn test_fields_ordered_by() {
+        let msg = r#"message A {
+            optional string s = 3;
+            optional fixed32 f = 5;
+            optional int64 i = 1;
+            optional double d = 4;
+            optional sfixed64 g = 2;
+        }"#;
+
+        let desc = FileDescriptor::parse(msg.as_bytes()).expect("parse");
+        let names = |order| {
+            desc.messages[0]
+                .fields_ordered_by(order)
+                .into_iter()
+                .map(|f| &*f.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["s", "f", "i", "d", "g"], names(FieldOrder::Declaration));
+        assert_eq!(vec!["i", "g", "s", "d", "f"], names(FieldOrder::Number));
+        assert_eq!(vec!["d", "g", "f", "s", "i"], names(FieldOrder::Size));
+    }
+
+    #[test]
+    fn test_wire_type() {
+        assert_eq!(WireType::Varint, FieldType::Sint32.wire_type());
+        assert_eq!(WireType::Varint, FieldType::Bool.wire_type());
+        assert_eq!(WireType::Fixed64, FieldType::Fixed64.wire_type());
+        assert_eq!(WireType::Fixed64, FieldType::Double.wire_type());
+        assert_eq!(WireType::Fixed32, FieldType::Sfixed32.wire_type());
+        assert_eq!(WireType::LengthDelimited, FieldType::String.wire_type());
+        let map = FieldType::Map(Box::new(MapType {
+            key: FieldType::Int32,
+            value: FieldType::Int32,
+        }));
+        assert_eq!(WireType::LengthDelimited, map.wire_type());
+        let group = FieldType::Group(Group {
+            name: "G".to_string(),
+            fields: Vec::new(),
+        });
+        assert_eq!(WireType::StartGroup, group.wire_type());
+        assert_eq!(2, WireType::LengthDelimited as u32);
+    }
+
+    #[test]
+    fn test_is_packable() {
+        let msg = r#"message A {
+            repeated int32 a = 1;
+            repeated double b = 2;
+            repeated bool c = 3;
+            optional int32 d = 4;
+            repeated string e = 5;
+            repeated bytes f = 6;
+            repeated A g = 7;
+            map<int32, int32> h = 8;
+            repeated E i = 9;
+            enum E { X = 0; }
+        }"#;
+
+        let desc = FileDescriptor::parse(msg.as_bytes()).expect("parse");
+        let packable = desc.messages[0]
+            .fields
+            .iter()
+            .map(|f| f.is_packable(|name| desc.resolve_kind(".A", name)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![true, true, true, false, false, false, false, false, true],
+            packable
+        );
+        let e = &desc.messages[0].fields[8];
+        assert!(e.is_packable(|_| e.type_kind));
+        assert!(!e.is_packable(|_| None));
+    }
+
+    #[test]
+    fn test_parse_bytes() {
+        let desc = FileDescriptor::parse_bytes(b"// \xC3\xA9\nmessage A {}").expect("parse");
+        assert_eq!("A", desc.messages[0].name);
+
+        let desc = FileDescriptor::parse_bytes(b"\xEF\xBB\xBFmessage A {}").expect("parse");
+        assert_eq!("A", desc.messages[0].name);
+
+        let err = FileDescriptor::parse_bytes(b"\xEF\xBB\xBF\nmessage \xFFA {}").unwrap_err();
+        assert_eq!(ParserError::InvalidUtf8 { offset: 12 }, err.error);
+        assert_eq!((2, 9), (err.line, err.col));
+    }
+
+    #[test]
+    fn test_typed_default() {
+        let msg = r#"
+            enum Color { RED = 0; BLUE = 1; }
+            message A {
+                optional Color c = 1 [default = RED];
+                optional string s = 2 [default = "RED"];
+                optional double d = 3 [default = 2];
+                optional int32 i = 4 [default = -3];
+                optional bool b = 5;
+            }
+        "#;
+
+        let desc = FileDescriptor::parse(msg.as_bytes()).expect("parse");
+        let defaults = desc.messages[0]
+            .fields
+            .iter()
+            .map(|f| f.typed_default().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Some(DefaultValue::EnumVariant("RED".to_string())),
+                Some(DefaultValue::String("RED".to_string())),
+                Some(DefaultValue::F64(2.)),
+                Some(DefaultValue::I64(-3)),
+                None,
+            ],
+            defaults
+        );
+        assert_eq!(Some("RED"), desc.messages[0].fields[0].default.as_deref());
+    }
+
+    #[test]
+    fn test_element_type() {
+        let msg = r#"message A {
+            optional int32 a = 1;
+            repeated string b = 2;
+            map<int32, A> c = 3;
+            repeated A d = 4;
+        }"#;
+
+        let desc = FileDescriptor::parse(msg.as_bytes()).expect("parse");
+        let types = desc.messages[0]
+            .fields
+            .iter()
+            .map(|f| f.element_type().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                FieldType::Int32,
+                FieldType::String,
+                FieldType::MessageOrEnum("A".to_string()),
+                FieldType::MessageOrEnum("A".to_string()),
+            ],
+            types
+        );
+    }
+
+    #[test]
+    fn test_message_count_and_depth() {
+        let msg = r#"
+            message A {
+                message B {
+                    message C {}
                 }
+                message D {}
             }
-            o => o.to_full_result(),
+            message E {}
+        "#;
+
+        let desc = FileDescriptor::parse(msg.as_bytes()).expect("parse");
+        assert_eq!(5, desc.message_count_recursive());
+        assert_eq!(3, desc.max_message_depth());
+
+        let desc = FileDescriptor::parse("enum E { A = 0; }".as_bytes()).expect("parse");
+        assert_eq!(0, desc.message_count_recursive());
+        assert_eq!(0, desc.max_message_depth());
+    }
+
+    #[test]
+    fn test_field_by() {
+        let msg = r#"message A {
+            optional int32 a = 1;
+            oneof o {
+                string b = 2;
+                int32 c = 3;
+            }
+            optional group G = 4 {
+                optional int32 d = 5;
+            }
+        }"#;
+        let desc = FileDescriptor::parse(msg).unwrap();
+        let msg = &desc.messages[0];
+        assert_eq!(Some("a"), msg.field_by_number(1).map(|f| &*f.name));
+        assert_eq!(Some("c"), msg.field_by_number(3).map(|f| &*f.name));
+        assert_eq!(Some("g"), msg.field_by_number(4).map(|f| &*f.name));
+        assert_eq!(None, msg.field_by_number(5));
+        assert_eq!(Some(1), msg.field_by_name("a").map(|f| f.number));
+        assert_eq!(Some(2), msg.field_by_name("b").map(|f| f.number));
+        assert_eq!(None, msg.field_by_name("d"));
+        assert_eq!(None, msg.field_by_name("o"));
+    }
+
+    #[test]
+    fn test_members() {
+        let msg = r#"message A {
+            oneof o { int32 a = 1; }
+            int32 b = 2;
+            oneof p { int32 c = 3; }
+            int32 d = 4;
+        }"#;
+        let mut msg = FileDescriptor::parse(msg).unwrap().messages.remove(0);
+        let order = vec![
+            MemberRef::OneOf(0),
+            MemberRef::Field(0),
+            MemberRef::OneOf(1),
+            MemberRef::Field(1),
+        ];
+        assert_eq!(order, msg.declaration_order);
+        assert_eq!(order, msg.members());
+
+        // incomplete or duplicated orders are ignored
+        let fallback = vec![
+            MemberRef::Field(0),
+            MemberRef::Field(1),
+            MemberRef::OneOf(0),
+            MemberRef::OneOf(1),
+        ];
+        msg.declaration_order.pop();
+        assert_eq!(fallback, msg.members());
+        msg.declaration_order.push(MemberRef::Field(0));
+        assert_eq!(fallback, msg.members());
+    }
+
+    #[test]
+    fn test_descendants() {
+        let msg = r#"
+            message A {
+                message B {
+                    message C {}
+                }
+                message D {}
+            }
+            message E {}
+        "#;
+
+        let desc = FileDescriptor::parse(msg.as_bytes()).expect("parse");
+        let names = desc.all_messages().map(|m| &*m.name).collect::<Vec<_>>();
+        assert_eq!(vec!["A", "B", "C", "D", "E"], names);
+        let names = desc.messages[0]
+            .descendants()
+            .map(|m| &*m.name)
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["B", "C", "D"], names);
+        assert_eq!(0, desc.messages[1].descendants().count());
+    }
+
+    #[test]
+    fn test_all_fields() {
+        let msg = r#"
+            message A {
+                optional bytes a = 1;
+                map<string, bytes> m = 2;
+                oneof o {
+                    bytes c = 3;
+                }
+                message B {
+                    repeated bytes b = 1;
+                }
+            }
+            message C {}
+        "#;
+
+        let desc = FileDescriptor::parse(msg.as_bytes()).expect("parse");
+        let names = desc.all_messages().map(|m| &*m.name).collect::<Vec<_>>();
+        assert_eq!(vec!["A", "B", "C"], names);
+        let fields = desc
+            .all_fields()
+            .map(|(m, f)| (&*m.name, &*f.name))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![("A", "a"), ("A", "m"), ("A", "c"), ("B", "b")], fields);
+        let bytes = desc
+            .all_fields()
+            .filter(|&(_, f)| f.typ == FieldType::Bytes)
+            .count();
+        assert_eq!(3, bytes);
+    }
+
+    #[test]
+    fn test_option() {
+        let msg = r#"
+            option go_package = "example.com/foo";
+            option java_package = "com.example";
+            option optimize_for = SPEED;
+            option optimize_for = LITE_RUNTIME;
+        "#;
+
+        let desc = FileDescriptor::parse(msg.as_bytes()).expect("parse");
+        assert_eq!(
+            Some(&ProtobufConstant::String("example.com/foo".to_string())),
+            desc.option("go_package")
+        );
+        assert_eq!(
+            Some(&ProtobufConstant::Ident("LITE_RUNTIME".to_string())),
+            desc.option("optimize_for")
+        );
+        assert_eq!(None, desc.option("java_outer_classname"));
+        let names = desc.options.iter().map(|o| &*o.name).collect::<Vec<_>>();
+        assert_eq!(
+            vec!["go_package", "java_package", "optimize_for", "optimize_for"],
+            names
+        );
+    }
+
+    #[test]
+    fn test_bool_option() {
+        let msg = r#"
+            syntax = "proto2";
+            option cc_generic_services = false;
+            option java_generic_services = true;
+            option java_package = "com.example";
+            option optimize_for = SPEED;
+            option (my.opt).enabled = false;
+            option (my.limits) = { max: 10; name: "}" };
+            message A {}
+        "#;
+
+        let desc = FileDescriptor::parse(msg.as_bytes()).expect("parse");
+        assert_eq!(Some(false), desc.bool_option("cc_generic_services"));
+        assert_eq!(Some(true), desc.bool_option("java_generic_services"));
+        assert_eq!(None, desc.bool_option("py_generic_services"));
+        assert_eq!(None, desc.bool_option("java_package"));
+        assert_eq!(Some(false), desc.bool_option("(my.opt).enabled"));
+        assert_eq!(
+            vec![
+                ProtobufConstant::Bool(false),
+                ProtobufConstant::Bool(true),
+                ProtobufConstant::String("com.example".to_string()),
+                ProtobufConstant::Ident("SPEED".to_string()),
+                ProtobufConstant::Bool(false),
+                ProtobufConstant::Message(vec![
+                    ("max".to_string(), ProtobufConstant::Int(10)),
+                    ("name".to_string(), ProtobufConstant::String("}".to_string())),
+                ]),
+            ],
+            desc.options.iter().map(|o| o.value.clone()).collect::<Vec<_>>()
+        );
+        assert_eq!(1, desc.messages.len());
+    }
+
+    #[test]
+    fn test_custom_option_names() {
+        let msg = r#"
+            option (file.opt) = true;
+            option java_package = "a";
+            message A {
+                option (msg.opt).x = 1;
+                optional int32 a = 1 [(field.opt) = 2, deprecated = true];
+                enum E {
+                    option (enum.opt) = "e";
+                    X = 0 [(value.opt) = { a: 1 }];
+                }
+            }
+            service S {
+                option (service.opt) = 1;
+                rpc M(A) returns (A) { option (method.opt) = true; }
+            }
+        "#;
+
+        let desc = FileDescriptor::parse(msg.as_bytes()).expect("parse");
+        let mut names = desc.custom_option_names().into_iter().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            vec![
+                "enum.opt",
+                "field.opt",
+                "file.opt",
+                "method.opt",
+                "msg.opt",
+                "service.opt",
+                "value.opt",
+            ],
+            names
+        );
+    }
+
+    #[test]
+    fn test_extensions_by_extendee() {
+        let msg = r#"
+            extend google.protobuf.FileOptions {
+                optional int32 a = 50000;
+            }
+            extend google.protobuf.MessageOptions {
+                optional int32 b = 50000;
+            }
+            extend google.protobuf.FileOptions {
+                optional int32 c = 50001;
+            }
+        "#;
+
+        let desc = FileDescriptor::parse(msg.as_bytes()).expect("parse");
+        let groups = desc.extensions_by_extendee();
+        assert_eq!(2, groups.len());
+        let names = |extendee: &str| {
+            groups[extendee]
+                .iter()
+                .map(|e| &*e.field.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["a", "c"], names("google.protobuf.FileOptions"));
+        assert_eq!(vec!["b"], names("google.protobuf.MessageOptions"));
+    }
+
+    #[test]
+    fn test_strip_comments() {
+        let msg = r#"
+            // A
+            message A {
+                // a
+                optional group G = 1 {
+                    optional int32 x = 1; // x
+                }
+                oneof o { int32 b = 2; } // b
+                enum E { X = 0; } // E
+            }
+            extend A { optional int32 ext = 100; } // ext
+            /* S */
+            service S { rpc M(A) returns (A); } // M
+        "#;
+
+        let mut desc = FileDescriptor::parse(msg.as_bytes()).expect("parse");
+        assert_eq!(vec!["A"], desc.messages[0].comments);
+        desc.strip_comments();
+        let fields = desc
+            .all_fields()
+            .map(|(_, f)| f)
+            .chain(desc.extensions.iter().map(|e| &e.field))
+            .flat_map(|f| match f.typ {
+                FieldType::Group(ref g) => g.fields.iter().chain(Some(f)).collect(),
+                _ => vec![f],
+            });
+        let comments = desc
+            .all_messages()
+            .map(|m| (&m.comments, &m.trailing_comment))
+            .chain(fields.map(|f| (&f.comments, &f.trailing_comment)))
+            .chain(desc.messages[0].enums.iter().map(|e| (&e.comments, &e.trailing_comment)))
+            .chain(desc.services.iter().map(|s| (&s.comments, &s.trailing_comment)))
+            .chain(desc.services[0].methods.iter().map(|m| (&m.comments, &m.trailing_comment)));
+        for (comments, trailing) in comments {
+            assert!(comments.is_empty());
+            assert_eq!(None, *trailing);
         }
     }
+
+    #[test]
+    fn test_line_index() {
+        let file = "ab\r\n\u{e9}c\n\nd".as_bytes();
+        let lines = LineIndex::new(file);
+        let locs = [0, 2, 4, 6, 7, 8, 9]
+            .iter()
+            .map(|&o| {
+                let loc = lines.loc(o);
+                (loc.offset, loc.line, loc.col)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![(0, 1, 1), (2, 1, 3), (4, 2, 1), (6, 2, 2), (7, 2, 3), (8, 3, 1), (9, 4, 1)],
+            locs
+        );
+    }
+
+    #[test]
+    fn test_span_order() {
+        let msg = r#"
+            enum E { A = 0; }
+            message A {
+                message B {}
+            }
+            message C {}
+        "#;
+
+        let desc = FileDescriptor::parse_with_spans(msg.as_bytes()).expect("parse");
+        let mut spans = desc
+            .all_messages()
+            .map(|m| (m.span.unwrap(), &*m.name))
+            .chain(desc.enums.iter().map(|e| (e.span.unwrap(), &*e.name)))
+            .collect::<Vec<_>>();
+        spans.sort();
+        let names = spans.iter().map(|&(_, n)| n).collect::<Vec<_>>();
+        assert_eq!(vec!["E", "A", "B", "C"], names);
+        assert!(spans[1].0 < spans[2].0 && spans[2].0.end < spans[1].0.end);
+    }
 }
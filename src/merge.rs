@@ -0,0 +1,189 @@
+//! Merging of the files of a package into a single file
+
+use std::collections::HashSet;
+
+use super::{FileDescriptor, ImportKind, Syntax};
+
+/// An error of `FileDescriptor::merge`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum MergeError {
+    /// A file does not declare the package of the first file
+    PackageMismatch {
+        /// Package of the first file
+        expected: String,
+        /// Package of the mismatching file
+        found: String,
+    },
+    /// A file does not have the syntax of the first file
+    SyntaxMismatch {
+        /// Syntax of the first file
+        expected: Syntax,
+        /// Syntax of the mismatching file
+        found: Syntax,
+    },
+    /// A file does not have the edition of the first file
+    EditionMismatch {
+        /// Edition of the first file
+        expected: Option<String>,
+        /// Edition of the mismatching file
+        found: Option<String>,
+    },
+    /// Two files declare a message, enum, service or extension with the same name
+    NameCollision(String),
+}
+
+impl FileDescriptor {
+    /// Merges files of the same package and syntax (or edition) into a single file
+    ///
+    /// Messages, enums, extensions and services are concatenated in file order. Imports are
+    /// deduplicated by path, an import is public if any of the files imports it publicly.
+    /// Options are concatenated too, without duplicates. Spans of the merged items still refer
+    /// to their own files.
+    ///
+    /// No descriptors merge into an empty file.
+    pub fn merge(descriptors: Vec<FileDescriptor>) -> Result<FileDescriptor, MergeError> {
+        let mut descriptors = descriptors.into_iter();
+        let mut merged = match descriptors.next() {
+            Some(d) => d,
+            None => return Ok(FileDescriptor::default()),
+        };
+        let mut names = HashSet::new();
+        for name in merged.top_level_names() {
+            names.insert(name.to_string());
+        }
+        for desc in descriptors {
+            if desc.package != merged.package {
+                return Err(MergeError::PackageMismatch {
+                    expected: merged.package,
+                    found: desc.package,
+                });
+            }
+            if desc.syntax != merged.syntax {
+                return Err(MergeError::SyntaxMismatch {
+                    expected: merged.syntax,
+                    found: desc.syntax,
+                });
+            }
+            if desc.edition != merged.edition {
+                return Err(MergeError::EditionMismatch {
+                    expected: merged.edition,
+                    found: desc.edition,
+                });
+            }
+            for name in desc.top_level_names() {
+                if !names.insert(name.to_string()) {
+                    return Err(MergeError::NameCollision(name.to_string()));
+                }
+            }
+
+            for import in desc.imports {
+                match merged.imports.iter_mut().find(|i| i.path == import.path) {
+                    Some(i) => {
+                        if import.kind == ImportKind::Public {
+                            i.kind = ImportKind::Public;
+                        }
+                    }
+                    None => merged.imports.push(import),
+                }
+            }
+            for option in desc.options {
+                if !merged.options.contains(&option) {
+                    merged.options.push(option);
+                }
+            }
+            merged.messages.extend(desc.messages);
+            merged.enums.extend(desc.enums);
+            merged.extensions.extend(desc.extensions);
+            merged.services.extend(desc.services);
+        }
+        Ok(merged)
+    }
+
+    /// Names declared in the package scope by this file
+    fn top_level_names(&self) -> Vec<&str> {
+        self.messages
+            .iter()
+            .map(|m| &*m.name)
+            .chain(self.enums.iter().map(|e| &*e.name))
+            .chain(self.extensions.iter().map(|e| &*e.field.name))
+            .chain(self.services.iter().map(|s| &*s.name))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let a = r#"
+            syntax = "proto3";
+            package pkg.a;
+            import "common.proto";
+            message A { int32 a = 1; }
+        "#;
+        let b = r#"
+            syntax = "proto3";
+            package pkg.a;
+            import public "common.proto";
+            import "other.proto";
+            message B { A a = 1; }
+            enum E { X = 0; }
+        "#;
+        let files = vec![
+            FileDescriptor::parse(a).unwrap(),
+            FileDescriptor::parse(b).unwrap(),
+        ];
+
+        let merged = FileDescriptor::merge(files).unwrap();
+        assert_eq!("pkg.a", merged.package);
+        assert_eq!(Syntax::Proto3, merged.syntax);
+        let messages = merged.messages.iter().map(|m| &*m.name).collect::<Vec<_>>();
+        assert_eq!(vec!["A", "B"], messages);
+        assert_eq!("E", merged.enums[0].name);
+        let imports = merged
+            .imports
+            .iter()
+            .map(|i| (&*i.path, i.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![("common.proto", ImportKind::Public), ("other.proto", ImportKind::Default)],
+            imports
+        );
+        assert_eq!(Ok(FileDescriptor::default()), FileDescriptor::merge(Vec::new()));
+    }
+
+    #[test]
+    fn test_merge_errors() {
+        let merge = |files: &[&str]| {
+            let files = files.iter().map(|f| FileDescriptor::parse(f).unwrap()).collect();
+            FileDescriptor::merge(files)
+        };
+        assert_eq!(
+            Err(MergeError::PackageMismatch {
+                expected: "a".to_string(),
+                found: "b".to_string(),
+            }),
+            merge(&["package a;", "package b;"])
+        );
+        assert_eq!(
+            Err(MergeError::SyntaxMismatch {
+                expected: Syntax::Proto2,
+                found: Syntax::Proto3,
+            }),
+            merge(&["package a;", "syntax = \"proto3\"; package a;"])
+        );
+        assert_eq!(
+            Err(MergeError::EditionMismatch {
+                expected: Some("2023".to_string()),
+                found: None,
+            }),
+            merge(&["edition = \"2023\";", ""])
+        );
+        assert_eq!(
+            Err(MergeError::NameCollision("A".to_string())),
+            merge(&["message A {}", "message B {}", "enum A { X = 0; }"])
+        );
+    }
+}
@@ -1,287 +1,706 @@
+use std::collections::VecDeque;
+use std::convert::TryFrom;
 use std::str;
-use std::ops::Range;
+use std::ops::{Range, RangeInclusive};
+
+use super::{EnumValue, Enumeration, Extension, ExtensionRange, Field, FieldType, FileDescriptor,
+    Group, Import, ImportKind, LineIndex, MapType, MemberRef, Message, Method, OneOf,
+    ParserError, ParserErrorWithLocation, ParserOptions, ProtobufConstant, ProtobufOption, Rule,
+    Service, Span, Syntax, TopLevel};
+use check::check;
+use default_value::unescape_str;
+use nom::{IResult, InputLength, Needed};
+use resolve::resolve_type_kinds;
+use tokenizer::{lex, Lexeme, Token, Tokenizer};
+
+/// The tokens of a .proto file left to parse, without its comments (see `tokenize`)
+///
+/// Tokens are compared by their number only, they all come from the same file.
+#[derive(Debug, Clone, Copy)]
+struct Tokens<'a> {
+    /// The whole file, to get the text of the tokens as written
+    file: &'a [u8],
+    tokens: &'a [(Token, Span)],
+}
+
+impl<'a> Tokens<'a> {
+    /// Byte offset of the next token, the length of the file if there is none
+    fn offset(&self) -> usize {
+        self.tokens
+            .first()
+            .map_or(self.file.len(), |&(_, span)| span.start.offset)
+    }
+
+    /// The tokens following the first `n` ones
+    fn skip(&self, n: usize) -> Tokens<'a> {
+        Tokens {
+            file: self.file,
+            tokens: &self.tokens[n..],
+        }
+    }
+
+    /// Text of the tokens parsed up to `rest`, as written, `None` if it is not valid UTF-8
+    fn text(&self, rest: Tokens<'a>) -> Option<&'a str> {
+        str::from_utf8(&self.file[span(*self, rest)?.range()]).ok()
+    }
+}
+
+impl<'a> PartialEq for Tokens<'a> {
+    fn eq(&self, other: &Tokens<'a>) -> bool {
+        self.tokens.len() == other.tokens.len()
+    }
+}
+
+impl<'a> InputLength for Tokens<'a> {
+    fn input_len(&self) -> usize {
+        self.tokens.len()
+    }
+}
+
+/// The tokens left, to record the `Span` of a declaration (see `span`)
+fn position(i: Tokens) -> IResult<Tokens, Tokens> {
+    IResult::Done(i, i)
+}
+
+/// Span of the tokens parsed from `start` up to `end`, `None` if there is none
+fn span(start: Tokens, end: Tokens) -> Option<Span> {
+    let last = start.tokens.len().checked_sub(end.tokens.len() + 1)?;
+    Some(Span {
+        start: start.tokens[0].1.start,
+        end: start.tokens[last].1.end,
+    })
+}
+
+/// A declaration along with the errors, and their byte offsets, of the invalid statements
+/// skipped while parsing it
+type Recovered<T> = (T, Vec<(usize, ParserError)>);
+
+/// Skips an invalid statement, returning its offset and error
+///
+/// The statement ends after the next `;` or balanced `{ }` block, or before the `}` closing
+/// the current block.
+///
+/// The error is an `IntegerOverflow` if the statement has an integer literal not fitting in an
+/// `i32` outside of blocks and of option lists (whose values may be 64-bit), an
+/// `InvalidDeclaration` otherwise.
+fn skip_statement(i: Tokens) -> IResult<Tokens, (usize, ParserError)> {
+    let mut error = ParserError::InvalidDeclaration;
+    let mut depth = 0;
+    let mut brackets = 0;
+    let mut negative = false;
+    for (idx, (token, _)) in i.tokens.iter().enumerate() {
+        match *token {
+            Token::Symbol('{') => depth += 1,
+            Token::Symbol('}') if depth == 0 => {
+                return if idx == 0 {
+                    IResult::Error(error_position!(::nom::ErrorKind::Custom(0), i))
+                } else {
+                    IResult::Done(i.skip(idx), (i.offset(), error))
+                };
+            }
+            Token::Symbol('}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return IResult::Done(i.skip(idx + 1), (i.offset(), error));
+                }
+            }
+            Token::Symbol(';') if depth == 0 => {
+                return IResult::Done(i.skip(idx + 1), (i.offset(), error));
+            }
+            Token::Symbol('[') => brackets += 1,
+            Token::Symbol(']') => brackets -= 1,
+            Token::IntLit(ref lit) if depth == 0 && brackets == 0 => {
+                let lit = if negative {
+                    format!("-{}", lit)
+                } else {
+                    lit.clone()
+                };
+                if wide_int_value(&lit).is_some_and(|v| i32::try_from(v).is_err()) {
+                    error = ParserError::IntegerOverflow(lit);
+                }
+            }
+            _ => (),
+        }
+        negative = *token == Token::Symbol('-');
+    }
+    IResult::Incomplete(Needed::Unknown)
+}
+
+/// The symbol `c`
+fn symbol(i: Tokens, c: char) -> IResult<Tokens, ()> {
+    match i.tokens.first() {
+        Some(&(Token::Symbol(s), _)) if s == c => IResult::Done(i.skip(1), ()),
+        Some(_) => IResult::Error(error_position!(::nom::ErrorKind::Custom(0), i)),
+        None => IResult::Incomplete(Needed::Size(1)),
+    }
+}
+
+/// The sign `c`, written right before the following token (e.g. `-1`)
+fn sign(i: Tokens, c: char) -> IResult<Tokens, ()> {
+    let (rest, _) = try_parse!(i, apply!(symbol, c));
+    match rest.tokens.first() {
+        Some(&(_, next)) if i.tokens[0].1.end == next.start => IResult::Done(rest, ()),
+        Some(_) => IResult::Error(error_position!(::nom::ErrorKind::Custom(0), i)),
+        None => IResult::Incomplete(Needed::Size(1)),
+    }
+}
 
-use super::{EnumValue, Enumeration, Extension, Field, FieldType, FileDescriptor, Message, OneOf,
-    Rule, Syntax};
-use nom::{digit, hex_digit, multispace};
+/// Chars accepted in names
+///
+/// This is broader than the protobuf grammar, names are checked once parsed if
+/// `ParserOptions::strict_identifiers` is set.
+pub fn is_word(b: u8) -> bool {
+    matches!(b, b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' | b'.' | b'$' | b'-')
+}
 
-fn is_word(b: u8) -> bool {
-    match b {
-        b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'_' | b'.' => true,
+/// Is `token` made of `is_word` chars, to be part of a name
+fn is_word_token(token: &Token) -> bool {
+    match *token {
+        Token::Ident(ref s) | Token::IntLit(ref s) | Token::FloatLit(ref s) => {
+            s.bytes().all(is_word)
+        }
+        Token::Symbol(c) => c.is_ascii() && is_word(c as u8),
         _ => false,
     }
 }
 
+/// A name as written, e.g. `foo.Bar`: the following `is_word_token`s without any whitespace
+/// or comment in between
+fn word_ref<'a>(i: Tokens<'a>) -> IResult<Tokens<'a>, &'a str> {
+    let len = i
+        .tokens
+        .iter()
+        .zip(i.tokens.iter().skip(1))
+        .take_while(|&(prev, next)| is_word_token(&prev.0) && prev.1.end == next.1.start)
+        .count();
+    let len = match i.tokens.get(len) {
+        Some((token, _)) if is_word_token(token) => len + 1,
+        Some(_) if len > 0 => len,
+        Some(_) => return IResult::Error(error_position!(::nom::ErrorKind::Custom(0), i)),
+        None => return IResult::Incomplete(Needed::Size(1)),
+    };
+    let rest = i.skip(len);
+    match i.text(rest) {
+        Some(s) => IResult::Done(rest, s),
+        None => IResult::Error(error_position!(::nom::ErrorKind::Custom(0), i)),
+    }
+}
+
+named!(word<Tokens, String>, map!(word_ref, str::to_string));
+
+/// The keyword `kw`, which must not be followed by other name chars
+fn keyword<'a>(i: Tokens<'a>, kw: &str) -> IResult<Tokens<'a>, ()> {
+    match word_ref(i) {
+        IResult::Done(rest, w) if w == kw => IResult::Done(rest, ()),
+        IResult::Incomplete(n) => IResult::Incomplete(n),
+        _ => IResult::Error(error_position!(::nom::ErrorKind::Custom(0), i)),
+    }
+}
+
+/// Value of an integer literal, decimal, octal (leading `0`) or hexadecimal (`0x`), with an
+/// optional minus sign, `None` if the digits are invalid or it does not even fit in an `i128`
+fn wide_int_value(lit: &str) -> Option<i128> {
+    let (negative, digits) = match lit.strip_prefix('-') {
+        Some(d) => (true, d),
+        None => (false, lit),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i128::from_str_radix(hex, 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i128::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse()
+    };
+    value.ok().map(|v| if negative { -v } else { v })
+}
+
+/// Value of an integer literal (see `wide_int_value`), `Err` if it does not fit in an `i32`
+fn int_value(lit: &str) -> Result<i32, ()> {
+    wide_int_value(lit)
+        .and_then(|v| i32::try_from(v).ok())
+        .ok_or(())
+}
+
+/// An integer literal, as written
+fn int_lit<'a>(i: Tokens<'a>) -> IResult<Tokens<'a>, &'a str> {
+    match i.tokens.first() {
+        Some(&(Token::IntLit(ref lit), _)) => IResult::Done(i.skip(1), lit),
+        Some(_) => IResult::Error(error_position!(::nom::ErrorKind::Custom(0), i)),
+        None => IResult::Incomplete(Needed::Size(1)),
+    }
+}
+
+// a non negative integer, e.g. a field number
 named!(
-    word<String>,
-    map_res!(take_while!(is_word), |b: &[u8]| String::from_utf8(
-        b.to_vec()
-    ))
+    integer<Tokens, i32>,
+    map_res!(int_lit, int_value)
 );
+
+// an integer with an optional minus sign, e.g. an enum value
 named!(
-    word_ref<&str>,
-    map_res!(take_while!(is_word), str::from_utf8)
+    signed_integer<Tokens, i32>,
+    map_res!(
+        do_parse!(minus: opt!(apply!(sign, '-')) >> lit: int_lit >> ((minus, lit))),
+        |(minus, lit): (Option<()>, &str)| match minus {
+            Some(()) => int_value(&format!("-{}", lit)),
+            None => int_value(lit),
+        }
+    )
 );
 
+// an unknown syntax is returned as written, for `next_event` to report it
 named!(
-    hex_integer<i32>,
+    syntax<Tokens, Result<Syntax, String>>,
     do_parse!(
-        tag!("0x") >> num: map_res!(map_res!(hex_digit, str::from_utf8), |s| {
-            i32::from_str_radix(s, 16)
-        }) >> (num)
+        apply!(keyword, "syntax") >> apply!(symbol, '=') >> proto: string_lit
+            >> apply!(symbol, ';')
+            >> (match &*proto {
+                "proto2" => Ok(Syntax::Proto2),
+                "proto3" => Ok(Syntax::Proto3),
+                _ => Err(proto),
+            })
     )
 );
 
 named!(
-    integer<i32>,
-    map_res!(map_res!(digit, str::from_utf8), str::FromStr::from_str)
+    edition<Tokens, String>,
+    do_parse!(
+        apply!(keyword, "edition") >> apply!(symbol, '=') >> edition: string_lit
+            >> apply!(symbol, ';') >> (edition)
+    )
 );
 
 named!(
-    comment<()>,
-    do_parse!(tag!("//") >> take_until_and_consume!("\n") >> ())
-);
-named!(
-    block_comment<()>,
-    do_parse!(tag!("/*") >> take_until_and_consume!("*/") >> ())
+    import_kind<Tokens, ImportKind>,
+    alt!(apply!(keyword, "public") => { |_| ImportKind::Public } |
+            apply!(keyword, "weak") => { |_| ImportKind::Weak })
 );
 
-/// word break: multispace or comment
 named!(
-    br<()>,
-    alt!(map!(multispace, |_| ()) | comment | block_comment)
+    import<Tokens, Import>,
+    do_parse!(
+        apply!(keyword, "import") >> kind: opt!(import_kind) >> path: string_lit
+            >> apply!(symbol, ';') >> (Import {
+            path,
+            kind: kind.unwrap_or(ImportKind::Default),
+        })
+    )
 );
 
 named!(
-    syntax<Syntax>,
+    package<Tokens, String>,
     do_parse!(
-        tag!("syntax") >> many0!(br) >> tag!("=") >> many0!(br)
-            >> proto:
-                alt!(tag!("\"proto2\"") => { |_| Syntax::Proto2 } |
-                             tag!("\"proto3\"") => { |_| Syntax::Proto3 }) >> many0!(br)
-            >> tag!(";") >> (proto)
+        apply!(keyword, "package") >> package: word >> apply!(symbol, ';') >> (package)
     )
 );
 
-named!(
-    import<String>,
+/// Largest field number, the value of `max` in message ranges
+pub const MAX_FIELD_NUMBER: i32 = 536_870_911;
+
+/// Largest enum value, the value of `max` in enum ranges
+pub const MAX_ENUM_VALUE: i32 = i32::MAX;
+
+fn range_end(i: Tokens, max: i32) -> IResult<Tokens, i32> {
+    alt!(i, integer | apply!(keyword, "max") => { |_| max })
+}
+
+fn num_range(i: Tokens, max: i32) -> IResult<Tokens, RangeInclusive<i32>> {
     do_parse!(
-        tag!("import") >> many1!(br) >> tag!("\"")
-            >> path: map_res!(take_until!("\""), |b: &[u8]| String::from_utf8(b.to_vec()))
-            >> tag!("\"") >> many0!(br) >> tag!(";") >> (path)
+        i,
+        from_: integer >> apply!(keyword, "to") >> to_: apply!(range_end, max) >> (from_..=to_)
     )
-);
+}
 
-named!(
-    package<String>,
+fn num_ranges(i: Tokens, max: i32) -> IResult<Tokens, Vec<RangeInclusive<i32>>> {
+    separated_list!(
+        i,
+        apply!(symbol, ','),
+        alt!(apply!(num_range, max) | integer => { |i: i32| i..=i })
+    )
+}
+
+/// Field number ranges are stored end-exclusive, like in `DescriptorProto`
+fn end_exclusive(ranges: Vec<RangeInclusive<i32>>) -> Vec<Range<i32>> {
+    ranges
+        .into_iter()
+        .map(|r| *r.start()..r.end().saturating_add(1))
+        .collect()
+}
+
+fn reserved_nums(i: Tokens, max: i32) -> IResult<Tokens, Vec<RangeInclusive<i32>>> {
     do_parse!(
-        tag!("package") >> many1!(br) >> package: word >> many0!(br) >> tag!(";") >> (package)
+        i,
+        apply!(keyword, "reserved") >> nums: apply!(num_ranges, max) >> apply!(symbol, ';')
+            >> (nums)
     )
-);
+}
 
 named!(
-    num_range<Range<i32>>,
+    extension_ranges<Tokens, Vec<ExtensionRange>>,
     do_parse!(
-        from_: integer >> many1!(br) >> tag!("to") >> many1!(br) >> to_: integer
-            >> (from_..to_.saturating_add(1))
+        apply!(keyword, "extensions") >> nums: apply!(num_ranges, MAX_FIELD_NUMBER)
+            >> options: opt!(field_options) >> apply!(symbol, ';')
+            >> ({
+                let options = options
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(o, _)| o)
+                    .collect::<Vec<_>>();
+                end_exclusive(nums)
+                    .into_iter()
+                    .map(|range| ExtensionRange {
+                        range,
+                        options: options.clone(),
+                    })
+                    .collect()
+            })
     )
 );
 
 named!(
-    reserved_nums<Vec<Range<i32>>>,
+    reserved_names<Tokens, Vec<String>>,
     do_parse!(
-        tag!("reserved") >> many1!(br)
-            >> nums:
-                separated_list!(
-                    do_parse!(many0!(br) >> tag!(",") >> many0!(br) >> (())),
-                    alt!(num_range | integer => { |i: i32| i..i.saturating_add(1) })
-                ) >> many0!(br) >> tag!(";") >> (nums)
+        apply!(keyword, "reserved") >> first: single_string
+            >> others: many0!(do_parse!(apply!(symbol, ',') >> name: single_string >> (name)))
+            >> apply!(symbol, ';')
+            >> (Some(first).into_iter().chain(others).collect())
     )
 );
 
-named!(
-    reserved_names<Vec<String>>,
+/// A constant along with its text
+fn raw_constant<'a>(i: Tokens<'a>) -> IResult<Tokens<'a>, (&'a str, ProtobufConstant)> {
+    let (rest, c) = try_parse!(i, constant);
+    match i.text(rest) {
+        Some(raw) => IResult::Done(rest, (raw, c)),
+        None => IResult::Error(error_position!(::nom::ErrorKind::Custom(0), i)),
+    }
+}
+
+// a field option, along with its value text
+//
+// A list value (`name = [a, b]`) gives one option per value
+fn field_option<'a>(i: Tokens<'a>) -> IResult<Tokens<'a>, Vec<(ProtobufOption, &'a str)>> {
     do_parse!(
-        tag!("reserved") >> many1!(br)
-            >> names:
-                many1!(do_parse!(
-                    tag!("\"") >> name: word >> tag!("\"")
-                        >> many0!(alt!(br | tag!(",") => { |_| () })) >> (name)
-                )) >> many0!(br) >> tag!(";") >> (names)
+        i,
+        name: option_name >> apply!(symbol, '=')
+            >> values: alt!(
+                do_parse!(
+                    apply!(symbol, '[')
+                        >> values: separated_list!(apply!(symbol, ','), raw_constant)
+                        >> apply!(symbol, ']') >> (values)
+                ) |
+                raw_constant => { |v| vec![v] }
+            )
+            >> (values
+                .into_iter()
+                .map(|(raw, value)| {
+                    let option = ProtobufOption {
+                        name: name.clone(),
+                        value,
+                    };
+                    (option, raw)
+                })
+                .collect())
     )
-);
+}
 
-named!(
-    key_val<(&str, &str)>,
+fn field_options<'a>(i: Tokens<'a>) -> IResult<Tokens<'a>, Vec<(ProtobufOption, &'a str)>> {
     do_parse!(
-        tag!("[") >> many0!(br) >> key: word_ref >> many0!(br) >> tag!("=") >> many0!(br)
-            >> value: map_res!(is_not!("]"), str::from_utf8) >> tag!("]") >> many0!(br)
-            >> ((key, value.trim()))
+        i,
+        apply!(symbol, '[')
+            >> options: separated_list!(apply!(symbol, ','), field_option)
+            >> apply!(symbol, ']')
+            >> (options.into_iter().flatten().collect())
     )
+}
+named!(
+    rule<Tokens, Rule>,
+    alt!(apply!(keyword, "optional") => { |_| Rule::Optional } |
+            apply!(keyword, "repeated") => { |_| Rule::Repeated } |
+            apply!(keyword, "required") => { |_| Rule::Required } )
 );
 
+/// The scalar type named `name`, `None` for a message or enum type
+fn scalar_type(name: &str) -> Option<FieldType> {
+    Some(match name {
+        "int32" => FieldType::Int32,
+        "int64" => FieldType::Int64,
+        "uint32" => FieldType::Uint32,
+        "uint64" => FieldType::Uint64,
+        "sint32" => FieldType::Sint32,
+        "sint64" => FieldType::Sint64,
+        "fixed32" => FieldType::Fixed32,
+        "sfixed32" => FieldType::Sfixed32,
+        "fixed64" => FieldType::Fixed64,
+        "sfixed64" => FieldType::Sfixed64,
+        "bool" => FieldType::Bool,
+        "string" => FieldType::String,
+        "bytes" => FieldType::Bytes,
+        "float" => FieldType::Float,
+        "double" => FieldType::Double,
+        _ => return None,
+    })
+}
+
 named!(
-    rule<Rule>,
-    alt!(tag!("optional") => { |_| Rule::Optional } |
-            tag!("repeated") => { |_| Rule::Repeated } |
-            tag!("required") => { |_| Rule::Required } )
+    field_type<Tokens, FieldType>,
+    alt!(map_field => { |m| FieldType::Map(Box::new(m)) } |
+            word => { |w: String| match scalar_type(&w) {
+                Some(typ) => typ,
+                None => FieldType::MessageOrEnum(w),
+            } })
 );
 
+// map keys must be of an integral type, `bool` or `string`
 named!(
-    field_type<FieldType>,
-    alt!(tag!("int32") => { |_| FieldType::Int32 } |
-            tag!("int64") => { |_| FieldType::Int64 } |
-            tag!("uint32") => { |_| FieldType::Uint32 } |
-            tag!("uint64") => { |_| FieldType::Uint64 } |
-            tag!("sint32") => { |_| FieldType::Sint32 } |
-            tag!("sint64") => { |_| FieldType::Sint64 } |
-            tag!("fixed32") => { |_| FieldType::Fixed32 } |
-            tag!("sfixed32") => { |_| FieldType::Sfixed32 } |
-            tag!("fixed64") => { |_| FieldType::Fixed64 } |
-            tag!("sfixed64") => { |_| FieldType::Sfixed64 } |
-            tag!("bool") => { |_| FieldType::Bool } |
-            tag!("string") => { |_| FieldType::String } |
-            tag!("bytes") => { |_| FieldType::Bytes } |
-            tag!("float") => { |_| FieldType::Float } |
-            tag!("double") => { |_| FieldType::Double } |
-            tag!("group") => { |_| FieldType::Group(Vec::new()) } |
-            map_field => { |(k, v)| FieldType::Map(Box::new((k, v))) } |
-            word => { |w| FieldType::MessageOrEnum(w) })
+    map_key<Tokens, FieldType>,
+    map_opt!(word_ref, |w| match scalar_type(w) {
+        Some(FieldType::Float) | Some(FieldType::Double) | Some(FieldType::Bytes) => None,
+        typ => typ,
+    })
 );
 
 named!(
-    map_field<(FieldType, FieldType)>,
+    map_field<Tokens, MapType>,
     do_parse!(
-        tag!("map") >> many0!(br) >> tag!("<") >> many0!(br) >> key: field_type >> many0!(br)
-            >> tag!(",") >> many0!(br) >> value: field_type >> tag!(">") >> ((key, value))
+        apply!(keyword, "map") >> apply!(symbol, '<') >> key: map_key >> apply!(symbol, ',')
+            >> value: field_type >> apply!(symbol, '>') >> (MapType { key, value })
     )
 );
 
 named!(
-    fields_in_braces<Vec<Field>>,
+    fields_in_braces<Tokens, Vec<Field>>,
     do_parse!(
-        tag!("{") >> many0!(br)
-        >> fields: separated_list!(br, message_field)
-        >> many0!(br) >> tag!("}") >> (fields)
+        apply!(symbol, '{') >> fields: many0!(message_field) >> apply!(symbol, '}') >> (fields)
     )
 );
 
+enum OneOfEvent {
+    Field(Box<Field>),
+    Option(ProtobufOption),
+    /// A nested message or enum, which is not allowed
+    TypeDef(usize),
+    Error((usize, ParserError)),
+    Ignore,
+}
+
+named!(
+    one_of_event<Tokens, OneOfEvent>,
+    alt!(do_parse!(pos: position >> alt!(map!(message, |_| ()) | map!(enumerator, |_| ()))
+                >> (pos.offset()))
+                => { OneOfEvent::TypeDef } |
+            message_field => { |f| OneOfEvent::Field(Box::new(f)) } |
+            option => { OneOfEvent::Option } |
+            empty_statement => { |_| OneOfEvent::Ignore } |
+            skip_statement => { OneOfEvent::Error })
+);
+
 named!(
-    one_of<OneOf>,
+    one_of<Tokens, Recovered<OneOf>>,
     do_parse!(
-        tag!("oneof") >> many1!(br) >> name: word >> many0!(br)
-            >> fields: fields_in_braces >> many0!(br)
-            >> (OneOf {
-                name: name,
-                fields: fields,
+        start: position >> apply!(keyword, "oneof") >> name: word >> apply!(symbol, '{')
+            >> events: many0!(one_of_event) >> apply!(symbol, '}') >> end: position
+            >> ({
+                let mut one_of = OneOf {
+                    name,
+                    span: span(start, end),
+                    ..OneOf::default()
+                };
+                let mut errors = Vec::new();
+                for e in events {
+                    match e {
+                        OneOfEvent::Field(f) => one_of.fields.push(Field {
+                            in_oneof: true,
+                            ..*f
+                        }),
+                        OneOfEvent::TypeDef(pos) => errors.push((pos, ParserError::TypeDefInOneof)),
+                        OneOfEvent::Error(e) => errors.push(e),
+                        OneOfEvent::Option(o) => {
+                            if let ("deprecated", &ProtobufConstant::Bool(b)) = (&*o.name, &o.value)
+                            {
+                                one_of.deprecated = b;
+                            }
+                            one_of.options.push(o);
+                        }
+                        OneOfEvent::Ignore => (),
+                    }
+                }
+                (one_of, errors)
             })
     )
 );
 
+fn field(
+    rule: Option<Rule>,
+    typ: FieldType,
+    name: String,
+    number: i32,
+    options: Option<Vec<(ProtobufOption, &str)>>,
+) -> Field {
+    let options = options.unwrap_or_default();
+    let value = |name: &str| {
+        options
+            .iter()
+            .rev()
+            .find(|&(o, _)| o.name == name)
+            .map(|&(ref o, raw)| (&o.value, raw))
+    };
+    let boolean = |name: &str| match value(name) {
+        Some((&ProtobufConstant::Bool(b), _)) => Some(b),
+        _ => None,
+    };
+    Field {
+        name,
+        rule: rule.unwrap_or(Rule::Singular),
+        typ,
+        number,
+        // the default is kept as written, e.g. with its quotes for strings
+        default: value("default").map(|(_, raw)| raw.to_string()),
+        packed: boolean("packed"),
+        deprecated: boolean("deprecated").unwrap_or(false),
+        json_name: match value("json_name") {
+            Some((ProtobufConstant::String(s), _)) => Some(s.clone()),
+            _ => None,
+        },
+        options: options.into_iter().map(|(o, _)| o).collect(),
+        in_oneof: false,
+        type_kind: None,
+        comments: Vec::new(),
+        trailing_comment: None,
+        span: None,
+    }
+}
+
+/// Group names must start with a capital letter
+fn is_group_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+}
+
 named!(
-    group_fields_or_semicolon<Option<Vec<Field>>>,
-    alt!(
-        tag!(";") => { |_| None } |
-        fields_in_braces => { Some })
+    group<Tokens, Field>,
+    do_parse!(
+        rule: opt!(rule) >> apply!(keyword, "group")
+            >> name: verify!(word_ref, is_group_name)
+            >> apply!(symbol, '=') >> number: integer
+            >> options: opt!(field_options)
+            >> fields: fields_in_braces >> ({
+                // the group implicitly declares a field with the lowercased name
+                let typ = FieldType::Group(Group {
+                    name: name.to_string(),
+                    fields,
+                });
+                field(rule, typ, name.to_lowercase(), number, options)
+            })
+    )
 );
 
 named!(
-    message_field<Field>,
+    message_field<Tokens, Field>,
     do_parse!(
-        rule: opt!(rule) >> many0!(br) >> typ: field_type >> many1!(br) >> name: word >> many0!(br)
-            >> tag!("=") >> many0!(br) >> number: integer >> many0!(br)
-            >> key_vals: many0!(key_val) >> many0!(br)
-            >> group_fields: group_fields_or_semicolon >> ({
-
-                let typ = match (typ, group_fields) {
-                    (FieldType::Group(..), Some(group_fields)) => {
-                        FieldType::Group(group_fields)
-                    }
-                    // TODO: produce error if semicolon is after group or group is without fields
-                    (typ, _) => typ
-                };
-
-                Field {
-                    name: name,
-                    rule: rule.unwrap_or(Rule::Optional),
-                    typ: typ,
-                    number: number,
-                    default: key_vals
-                        .iter()
-                        .find(|&&(k, _)| k == "default")
-                        .map(|&(_, v)| v.to_string()),
-                    packed: key_vals
-                        .iter()
-                        .find(|&&(k, _)| k == "packed")
-                        .map(|&(_, v)| str::FromStr::from_str(v).expect("Cannot parse Packed value")),
-                    deprecated: key_vals
-                        .iter()
-                        .find(|&&(k, _)| k == "deprecated")
-                        .map_or(false, |&(_, v)| str::FromStr::from_str(v)
-                            .expect("Cannot parse Deprecated value")),
-                }})
+        start: position
+            >> field: alt!(
+                group |
+                do_parse!(
+                    rule: opt!(rule) >> typ: field_type >> name: word >> apply!(symbol, '=')
+                        >> number: integer >> options: opt!(field_options) >> apply!(symbol, ';')
+                        >> (field(rule, typ, name, number, options))
+                )
+            ) >> end: position >> (Field {
+            span: span(start, end),
+            ..field
+        })
     )
 );
 
 enum MessageEvent {
-    Message(Message),
-    Enumeration(Enumeration),
+    Message(Recovered<Message>),
+    Enumeration(Recovered<Enumeration>),
     Field(Field),
     ReservedNums(Vec<Range<i32>>),
     ReservedNames(Vec<String>),
-    OneOf(OneOf),
+    ExtensionRanges(Vec<ExtensionRange>),
+    Extensions(Vec<Extension>),
+    OneOf(Recovered<OneOf>),
+    Option(ProtobufOption),
+    Error((usize, ParserError)),
     Ignore,
 }
 
 named!(
-    message_event<MessageEvent>,
-    alt!(reserved_nums => { |r| MessageEvent::ReservedNums(r) } |
-                                         reserved_names => { |r| MessageEvent::ReservedNames(r) } |
-                                         message_field => { |f| MessageEvent::Field(f) } |
-                                         message => { |m| MessageEvent::Message(m) } |
-                                         enumerator => { |e| MessageEvent::Enumeration(e) } |
-                                         one_of => { |o| MessageEvent::OneOf(o) } |
-                                         br => { |_| MessageEvent::Ignore })
+    message_event<Tokens, MessageEvent>,
+    alt!(option => { MessageEvent::Option } |
+                                         apply!(reserved_nums, MAX_FIELD_NUMBER) => {
+                                             |r| MessageEvent::ReservedNums(end_exclusive(r))
+                                         } |
+                                         reserved_names => { MessageEvent::ReservedNames } |
+                                         extension_ranges => { MessageEvent::ExtensionRanges } |
+                                         extensions => { MessageEvent::Extensions } |
+                                         message_field => { MessageEvent::Field } |
+                                         message => { MessageEvent::Message } |
+                                         enumerator => { MessageEvent::Enumeration } |
+                                         one_of => { MessageEvent::OneOf } |
+                                         empty_statement => { |_| MessageEvent::Ignore } |
+                                         skip_statement => { MessageEvent::Error })
 );
 
 named!(
-    message_events<(String, Vec<MessageEvent>)>,
+    message_events<Tokens, (String, Vec<MessageEvent>, Option<Span>)>,
     do_parse!(
-        tag!("message") >> many1!(br) >> name: word >> many0!(br) >> tag!("{") >> many0!(br)
-            >> events: many0!(message_event) >> many0!(br) >> tag!("}") >> many0!(br)
-            >> many0!(tag!(";")) >> ((name, events))
+        start: position >> apply!(keyword, "message") >> name: word >> apply!(symbol, '{')
+            >> events: many0!(message_event) >> apply!(symbol, '}') >> end: position
+            >> many0!(apply!(symbol, ';'))
+            >> ((name, events, span(start, end)))
     )
 );
 
 named!(
-    message<Message>,
+    message<Tokens, Recovered<Message>>,
     map!(
         message_events,
-        |(name, events): (String, Vec<MessageEvent>)| {
+        |(name, events, span): (String, Vec<MessageEvent>, Option<Span>)| {
             let mut msg = Message {
-                name: name.clone(),
+                name,
+                span,
                 ..Message::default()
             };
+            let mut errors = Vec::new();
             for e in events {
                 match e {
-                    MessageEvent::Field(f) => msg.fields.push(f),
-                    MessageEvent::ReservedNums(r) => msg.reserved_nums = r,
-                    MessageEvent::ReservedNames(r) => msg.reserved_names = r,
-                    MessageEvent::Message(m) => msg.messages.push(m),
-                    MessageEvent::Enumeration(e) => msg.enums.push(e),
-                    MessageEvent::OneOf(o) => msg.oneofs.push(o),
+                    MessageEvent::Field(f) => {
+                        msg.declaration_order.push(MemberRef::Field(msg.fields.len()));
+                        msg.fields.push(f);
+                    }
+                    MessageEvent::ReservedNums(r) => msg.reserved_nums.extend(r),
+                    MessageEvent::ReservedNames(r) => msg.reserved_names.extend(r),
+                    MessageEvent::ExtensionRanges(r) => msg.extension_ranges.extend(r),
+                    MessageEvent::Extensions(e) => msg.extensions.extend(e),
+                    MessageEvent::Message((m, e)) => {
+                        msg.messages.push(m);
+                        errors.extend(e);
+                    }
+                    MessageEvent::Enumeration((en, e)) => {
+                        msg.enums.push(en);
+                        errors.extend(e);
+                    }
+                    MessageEvent::Error(e) => errors.push(e),
+                    MessageEvent::OneOf((o, e)) => {
+                        msg.declaration_order.push(MemberRef::OneOf(msg.oneofs.len()));
+                        msg.oneofs.push(o);
+                        errors.extend(e);
+                    }
+                    MessageEvent::Option(o) => msg.options.push(o),
                     MessageEvent::Ignore => (),
                 }
             }
-            msg
+            (msg, errors)
         }
     )
 );
 
 named!(
-    extensions<Vec<Extension>>,
+    extensions<Tokens, Vec<Extension>>,
     do_parse!(
-        tag!("extend") >> many1!(br) >> extendee: word >> many0!(br) >>
+        apply!(keyword, "extend") >> extendee: word >>
             fields: fields_in_braces >> (
                 fields.into_iter().map(|field| Extension {
                     extendee: extendee.clone(),
@@ -292,129 +711,933 @@ named!(
 );
 
 named!(
-    enum_value<EnumValue>,
+    enum_value<Tokens, EnumValue>,
+    do_parse!(
+        start: position >> name: word >> apply!(symbol, '=') >> number: signed_integer
+            >> options: opt!(field_options) >> apply!(symbol, ';') >> end: position
+            >> (EnumValue {
+                name,
+                number,
+                options: options
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(o, _)| o)
+                    .collect(),
+                span: span(start, end),
+            })
+    )
+);
+
+enum EnumEvent {
+    Value(EnumValue),
+    Option(ProtobufOption),
+    ReservedNums(Vec<RangeInclusive<i32>>),
+    ReservedNames(Vec<String>),
+    Error((usize, ParserError)),
+    Ignore,
+}
+
+named!(
+    enum_event<Tokens, EnumEvent>,
+    alt!(enum_value => { EnumEvent::Value } |
+            option => { EnumEvent::Option } |
+            apply!(reserved_nums, MAX_ENUM_VALUE) => { EnumEvent::ReservedNums } |
+            reserved_names => { EnumEvent::ReservedNames } |
+            empty_statement => { |_| EnumEvent::Ignore } |
+            skip_statement => { EnumEvent::Error })
+);
+
+named!(
+    enumerator<Tokens, Recovered<Enumeration>>,
     do_parse!(
-        name: word >> many0!(br) >> tag!("=") >> many0!(br) >> number: alt!(hex_integer | integer)
-            >> many0!(br) >> tag!(";") >> many0!(br) >> (EnumValue {
-            name: name,
-            number: number,
+        start: position >> apply!(keyword, "enum") >> name: word >> apply!(symbol, '{')
+            >> events: many0!(enum_event) >> apply!(symbol, '}') >> end: position
+            >> many0!(apply!(symbol, ';')) >> ({
+            let mut e = Enumeration {
+                name,
+                values: Vec::new(),
+                options: Vec::new(),
+                allow_alias: false,
+                reserved_nums: Vec::new(),
+                reserved_names: Vec::new(),
+                comments: Vec::new(),
+                trailing_comment: None,
+                span: span(start, end),
+            };
+            let mut errors = Vec::new();
+            for event in events {
+                match event {
+                    EnumEvent::Value(v) => e.values.push(v),
+                    EnumEvent::Option(o) => {
+                        if o.name == "allow_alias" {
+                            e.allow_alias = o.value == ProtobufConstant::Bool(true);
+                        }
+                        e.options.push(o)
+                    }
+                    EnumEvent::ReservedNums(r) => e.reserved_nums.extend(r),
+                    EnumEvent::ReservedNames(r) => e.reserved_names.extend(r),
+                    EnumEvent::Error(e) => errors.push(e),
+                    EnumEvent::Ignore => (),
+                }
+            }
+            (e, errors)
         })
     )
 );
 
+/// A quoted string, escapes are kept as written
+///
+/// Strings cannot span several lines.
+fn quoted(i: Tokens) -> IResult<Tokens, String> {
+    let (s, span) = match i.tokens.first() {
+        Some(&(Token::StringLit(ref s), span)) => (s, span),
+        Some(_) => return IResult::Error(error_position!(::nom::ErrorKind::Custom(0), i)),
+        None => return IResult::Incomplete(Needed::Size(1)),
+    };
+    let raw = &i.file[span.range()];
+    match lex(raw) {
+        Some((Lexeme::StringLit { closed: true }, _)) if str::from_utf8(raw).is_ok() => {
+            IResult::Done(i.skip(1), s.clone())
+        }
+        Some((Lexeme::StringLit { closed: false }, _)) if span.end.offset == i.file.len() => {
+            IResult::Incomplete(Needed::Unknown)
+        }
+        _ => IResult::Error(error_position!(::nom::ErrorKind::Custom(0), i)),
+    }
+}
+
+/// The content of a single quoted string, unescaped
+///
+/// Invalid UTF-8 sequences (e.g. from `\xff` escapes) are replaced by `U+FFFD`.
+fn single_string(i: Tokens) -> IResult<Tokens, String> {
+    let (rest, s) = try_parse!(i, quoted);
+    match unescape_str(&s) {
+        Some(b) => IResult::Done(rest, String::from_utf8_lossy(&b).into_owned()),
+        None => IResult::Error(error_position!(::nom::ErrorKind::Custom(0), i)),
+    }
+}
+
+/// The content of one or more adjacent quoted strings, concatenated and unescaped
+///
+/// Invalid UTF-8 sequences (e.g. from `\xff` escapes) are replaced by `U+FFFD`.
+fn string_lit(i: Tokens) -> IResult<Tokens, String> {
+    let (mut rest, first) = try_parse!(i, quoted);
+    let mut parts = vec![first];
+    while let IResult::Done(r, s) = quoted(rest) {
+        rest = r;
+        parts.push(s);
+    }
+    let mut bytes = Vec::new();
+    for p in &parts {
+        match unescape_str(p) {
+            Some(b) => bytes.extend(b),
+            None => return IResult::Error(error_position!(::nom::ErrorKind::Custom(0), i)),
+        }
+    }
+    IResult::Done(rest, String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// A balanced `{ }` block, returned as written
+///
+/// Braces in quoted strings and comments are ignored.
+fn aggregate(i: Tokens) -> IResult<Tokens, String> {
+    try_parse!(i, apply!(symbol, '{'));
+    let mut depth = 0;
+    for (idx, (token, _)) in i.tokens.iter().enumerate() {
+        match *token {
+            // checks that the string is terminated
+            Token::StringLit(_) => {
+                try_parse!(i.skip(idx), quoted);
+            }
+            Token::Symbol('{') => depth += 1,
+            Token::Symbol('}') => {
+                depth -= 1;
+                if depth == 0 {
+                    let rest = i.skip(idx + 1);
+                    return match i.text(rest) {
+                        Some(s) => IResult::Done(rest, s.to_string()),
+                        None => IResult::Error(error_position!(::nom::ErrorKind::Custom(0), i)),
+                    };
+                }
+            }
+            _ => (),
+        }
+    }
+    IResult::Incomplete(Needed::Unknown)
+}
+
+/// An unquoted constant as written: a number or a (dotted) identifier, with an optional sign
+fn scalar<'a>(i: Tokens<'a>) -> IResult<Tokens<'a>, &'a str> {
+    let (rest, _) = try_parse!(i, opt!(alt!(apply!(sign, '-') | apply!(sign, '+'))));
+    let tokens = rest.tokens;
+    let len = match tokens.first() {
+        Some(&(Token::IntLit(_), _)) | Some(&(Token::FloatLit(_), _)) => 1,
+        Some(&(Token::Ident(_), _)) => {
+            // `.` and identifiers following it without whitespace
+            let mut len = 1;
+            while let (Some(&(Token::Symbol('.'), dot)), Some(&(Token::Ident(_), next))) =
+                (tokens.get(len), tokens.get(len + 1))
+            {
+                if tokens[len - 1].1.end != dot.start || dot.end != next.start {
+                    break;
+                }
+                len += 2;
+            }
+            len
+        }
+        Some(_) => return IResult::Error(error_position!(::nom::ErrorKind::Custom(0), i)),
+        None => return IResult::Incomplete(Needed::Size(1)),
+    };
+    let rest = rest.skip(len);
+    match i.text(rest) {
+        Some(s) => IResult::Done(rest, s),
+        None => IResult::Error(error_position!(::nom::ErrorKind::Custom(0), i)),
+    }
+}
+
+/// Converts an unquoted constant: a bool, a number or an identifier
+fn scalar_constant(s: &str) -> ProtobufConstant {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(d) => (true, d),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let int = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(&digits[1..], 8).ok()
+    } else {
+        digits.parse::<i64>().ok()
+    };
+    match s {
+        "true" => return ProtobufConstant::Bool(true),
+        "false" => return ProtobufConstant::Bool(false),
+        _ => (),
+    }
+    if let Some(i) = int {
+        return ProtobufConstant::Int(if negative { -i } else { i });
+    }
+    let numeric = digits
+        .bytes()
+        .next()
+        .is_some_and(|b| b.is_ascii_digit() || b == b'.');
+    match digits.parse::<f64>() {
+        Ok(f) if numeric || digits == "inf" || digits == "nan" => {
+            ProtobufConstant::Float(if negative { -f } else { f })
+        }
+        _ => ProtobufConstant::Ident(s.to_string()),
+    }
+}
+
+// a message constant field key, `[...]` extension keys are kept as written
+fn constant_key(i: Tokens) -> IResult<Tokens, String> {
+    if let Some(&(Token::Symbol('['), _)) = i.tokens.first() {
+        return match i.tokens.iter().position(|t| t.0 == Token::Symbol(']')) {
+            Some(end) => {
+                let rest = i.skip(end + 1);
+                match i.text(rest) {
+                    Some(key) => IResult::Done(rest, key.to_string()),
+                    None => IResult::Error(error_position!(::nom::ErrorKind::Custom(0), i)),
+                }
+            }
+            None => IResult::Incomplete(Needed::Unknown),
+        };
+    }
+    word(i)
+}
+
+named!(
+    constant_value<Tokens, ProtobufConstant>,
+    alt!(message_constant => { ProtobufConstant::Message } |
+            string_lit => { ProtobufConstant::String } |
+            scalar => { scalar_constant })
+);
+
+named!(
+    constant_separator<Tokens, ()>,
+    alt!(apply!(symbol, ':') | apply!(symbol, '='))
+);
+
+// `key: value`, `key { ... }` or `key: [value, ...]`, a list giving one field per value
+//
+// `=` is accepted in place of `:`
+named!(
+    constant_field<Tokens, Vec<(String, ProtobufConstant)>>,
+    do_parse!(
+        key: constant_key
+            >> values: alt!(
+                do_parse!(
+                    opt!(constant_separator) >> m: message_constant
+                        >> (vec![ProtobufConstant::Message(m)])
+                ) |
+                do_parse!(
+                    constant_separator >> apply!(symbol, '[')
+                        >> values: separated_list!(apply!(symbol, ','), constant_value)
+                        >> apply!(symbol, ']') >> (values)
+                ) |
+                do_parse!(constant_separator >> value: constant_value >> (vec![value]))
+            ) >> opt!(alt!(apply!(symbol, ',') | apply!(symbol, ';')))
+            >> (values.into_iter().map(|v| (key.clone(), v)).collect())
+    )
+);
+
+named!(
+    message_constant<Tokens, Vec<(String, ProtobufConstant)>>,
+    do_parse!(
+        apply!(symbol, '{') >> fields: many0!(constant_field) >> apply!(symbol, '}')
+            >> (fields.into_iter().flatten().collect())
+    )
+);
+
+named!(
+    constant<Tokens, ProtobufConstant>,
+    alt!(string_lit => { ProtobufConstant::String } |
+            message_constant => { ProtobufConstant::Message } |
+            aggregate => { ProtobufConstant::Aggregate } |
+            scalar => { scalar_constant })
+);
+
+// `word`, `(full.ident)` or `(full.ident).word`
+named!(
+    option_name<Tokens, String>,
+    alt!(do_parse!(
+            apply!(symbol, '(') >> extension: word_ref >> apply!(symbol, ')')
+                >> field: opt!(word_ref)
+                >> (format!("({}){}", extension, field.unwrap_or("")))
+        ) |
+        word)
+);
+
+named!(
+    option<Tokens, ProtobufOption>,
+    do_parse!(
+        apply!(keyword, "option") >> name: option_name >> apply!(symbol, '=') >> value: constant
+            >> apply!(symbol, ';') >> (ProtobufOption { name, value })
+    )
+);
+
+// A stray `;`, tolerated wherever a declaration is expected, as protoc does
+named!(empty_statement<Tokens, ()>, apply!(symbol, ';'));
+
+// `(Type)` or `(stream Type)`
+named!(
+    rpc_type<Tokens, (bool, String)>,
+    do_parse!(
+        apply!(symbol, '(')
+            >> typ: alt!(do_parse!(apply!(keyword, "stream") >> typ: word >> ((true, typ))) |
+                    word => { |typ| (false, typ) })
+            >> apply!(symbol, ')') >> (typ)
+    )
+);
+
+named!(
+    method_options<Tokens, Vec<ProtobufOption>>,
+    alt!(apply!(symbol, ';') => { |_| Vec::new() } |
+            do_parse!(
+                apply!(symbol, '{')
+                    >> options: many0!(alt!(option => { Some } |
+                                           empty_statement => { |_| None }))
+                    >> apply!(symbol, '}') >> opt!(apply!(symbol, ';'))
+                    >> (options.into_iter().flatten().collect())
+            ))
+);
+
 named!(
-    enumerator<Enumeration>,
+    method<Tokens, Method>,
     do_parse!(
-        tag!("enum") >> many1!(br) >> name: word >> many0!(br) >> tag!("{") >> many0!(br)
-            >> values: many0!(enum_value) >> many0!(br) >> tag!("}") >> many0!(br)
-            >> many0!(tag!(";")) >> (Enumeration {
-            name: name,
-            values: values,
+        start: position >> apply!(keyword, "rpc") >> name: word >> input: rpc_type
+            >> apply!(keyword, "returns") >> output: rpc_type >> options: method_options
+            >> end: position
+            >> (Method {
+            name,
+            input_type: input.1,
+            output_type: output.1,
+            client_streaming: input.0,
+            server_streaming: output.0,
+            options,
+            span: span(start, end),
+            ..Method::default()
         })
     )
 );
 
+enum ServiceEvent {
+    Method(Method),
+    Option(ProtobufOption),
+    Error((usize, ParserError)),
+    Ignore,
+}
+
 named!(
-    option_ignore<()>,
-    do_parse!(tag!("option") >> many1!(br) >> take_until_and_consume!(";") >> ())
+    service_event<Tokens, ServiceEvent>,
+    alt!(method => { ServiceEvent::Method } |
+            option => { ServiceEvent::Option } |
+            empty_statement => { |_| ServiceEvent::Ignore } |
+            skip_statement => { ServiceEvent::Error })
 );
 
 named!(
-    service_ignore<()>,
+    service<Tokens, Recovered<Service>>,
     do_parse!(
-        tag!("service") >> many1!(br) >> word >> many0!(br) >> tag!("{")
-            >> take_until_and_consume!("}") >> ()
+        start: position >> apply!(keyword, "service") >> name: word >> apply!(symbol, '{')
+            >> events: many0!(service_event) >> apply!(symbol, '}') >> end: position >> ({
+                let mut service = Service {
+                    name,
+                    span: span(start, end),
+                    ..Service::default()
+                };
+                let mut errors = Vec::new();
+                for e in events {
+                    match e {
+                        ServiceEvent::Method(m) => service.methods.push(m),
+                        ServiceEvent::Option(o) => service.options.push(o),
+                        ServiceEvent::Error(e) => errors.push(e),
+                        ServiceEvent::Ignore => (),
+                    }
+                }
+                (service, errors)
+            })
     )
 );
 
 enum Event {
     Syntax(Syntax),
-    Import(String),
+    InvalidSyntax(String),
+    Edition(String),
+    Import(Import),
     Package(String),
-    Message(Message),
-    Enum(Enumeration),
+    Message(Recovered<Message>),
+    Enum(Recovered<Enumeration>),
     Extensions(Vec<Extension>),
+    Option(ProtobufOption),
+    Service(Recovered<Service>),
     Ignore,
 }
 
 named!(
-    event<Event>,
-    alt!(syntax => { |s| Event::Syntax(s) } |
-            import => { |i| Event::Import(i) } |
-            package => { |p| Event::Package(p) } |
-            message => { |m| Event::Message(m) } |
-            enumerator => { |e| Event::Enum(e) } |
-            extensions => { |e| Event::Extensions(e) } |
-            option_ignore => { |_| Event::Ignore } |
-            service_ignore => { |_| Event::Ignore } |
-            br => { |_| Event::Ignore })
-);
-
-named!(pub file_descriptor<FileDescriptor>,
-       map!(many0!(event), |events: Vec<Event>| {
-           let mut desc = FileDescriptor::default();
-           for event in events {
-               match event {
-                   Event::Syntax(s) => desc.syntax = s,
-                   Event::Import(i) => desc.import_paths.push(i),
-                   Event::Package(p) => desc.package = p,
-                   Event::Message(m) => desc.messages.push(m),
-                   Event::Enum(e) => desc.enums.push(e),
-                   Event::Extensions(e) => desc.extensions.extend(e),
-                   Event::Ignore => (),
-               }
-           }
-           desc
-       }));
-
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    #[test]
-    fn test_message() {
-        let msg = r#"message ReferenceData
-    {
-        repeated ScenarioInfo  scenarioSet = 1;
-        repeated CalculatedObjectInfo calculatedObjectSet = 2;
-        repeated RiskFactorList riskFactorListSet = 3;
-        repeated RiskMaturityInfo riskMaturitySet = 4;
-        repeated IndicatorInfo indicatorSet = 5;
-        repeated RiskStrikeInfo riskStrikeSet = 6;
-        repeated FreeProjectionList freeProjectionListSet = 7;
-        repeated ValidationProperty ValidationSet = 8;
-        repeated CalcProperties calcPropertiesSet = 9;
-        repeated MaturityInfo maturitySet = 10;
-    }"#;
+    event<Tokens, Event>,
+    alt!(syntax => { |s: Result<_, _>| s.map_or_else(Event::InvalidSyntax, Event::Syntax) } |
+            edition => { Event::Edition } |
+            import => { Event::Import } |
+            package => { Event::Package } |
+            message => { Event::Message } |
+            enumerator => { Event::Enum } |
+            extensions => { Event::Extensions } |
+            option => { Event::Option } |
+            service => { Event::Service } |
+            empty_statement => { |_| Event::Ignore })
+);
 
-        let mess = message(msg.as_bytes());
-        if let ::nom::IResult::Done(_, mess) = mess {
-            assert_eq!(10, mess.fields.len());
+/// Splits a .proto file into the tokens to parse and the byte ranges of its comments
+fn tokenize(file: &[u8]) -> (Vec<(Token, Span)>, Vec<Range<usize>>) {
+    let mut tokens = Vec::new();
+    let mut comments = Vec::new();
+    for (token, span) in Tokenizer::from_bytes(file) {
+        match token {
+            Token::Comment(_) => comments.push(span.range()),
+            token => tokens.push((token, span)),
         }
     }
+    (tokens, comments)
+}
 
-    #[test]
-    fn test_enum() {
-        let msg = r#"enum PairingStatus {
-                DEALPAIRED        = 0;
-                INVENTORYORPHAN   = 1;
-                CALCULATEDORPHAN  = 2;
-                CANCELED          = 3;
-    }"#;
-
-        let enumeration = enumerator(msg.as_bytes());
-        if let ::nom::IResult::Done(_, mess) = enumeration {
-            assert_eq!(4, mess.values.len());
-        }
+/// Offset of the `/*` of a block comment without closing `*/`, which can only be the last
+/// comment
+fn unterminated_comment(file: &[u8], comments: &[Range<usize>]) -> Option<usize> {
+    let last = comments.last()?;
+    match lex(&file[last.clone()]) {
+        Some((Lexeme::Comment { closed: false }, _)) => Some(last.start),
+        _ => None,
     }
+}
 
-    #[test]
-    fn test_ignore() {
-        let msg = r#"option optimize_for = SPEED;"#;
+/// What a `SpanFixer` does to the declarations
+enum SpanPass<'a> {
+    /// Attaches the comments surrounding each declaration, given the byte ranges of all the
+    /// comments (see `tokenize`)
+    Comments(&'a [Range<usize>]),
+    /// Sets the spans to `None`
+    Clear,
+}
 
-        match option_ignore(msg.as_bytes()) {
-            ::nom::IResult::Done(_, _) => (),
-            e => panic!("Expecting done {:?}", e),
+/// Completes the declarations once parsed, from their spans
+struct SpanFixer<'a> {
+    file: &'a [u8],
+    pass: SpanPass<'a>,
+}
+
+impl<'a> SpanFixer<'a> {
+    fn span(&self, span: &mut Option<Span>) {
+        if let SpanPass::Clear = self.pass {
+            *span = None;
+        }
+    }
+
+    /// Comment text, without its markers
+    fn comment(&self, range: &Range<usize>) -> String {
+        let text = &self.file[range.clone()];
+        let text = if text.starts_with(b"//") {
+            &text[2..]
+        } else {
+            let text = &text[2..];
+            text.strip_suffix(b"*/").unwrap_or(text)
+        };
+        String::from_utf8_lossy(text).trim().to_string()
+    }
+
+    /// Comments before `start`, each on its own lines, without blank lines in between
+    fn leading_comments(&self, ranges: &[Range<usize>], start: usize) -> Vec<String> {
+        let is_blank = |b: &[u8]| b.iter().all(|b| b.is_ascii_whitespace());
+        let before = ranges.partition_point(|r| r.end <= start);
+        let mut next = start;
+        let mut comments = Vec::new();
+        for range in ranges[..before].iter().rev() {
+            let gap = &self.file[range.end..next];
+            let line = self.file[..range.start]
+                .iter()
+                .rposition(|&b| b == b'\n')
+                .map_or(0, |p| p + 1);
+            if !is_blank(gap)
+                || gap.iter().filter(|&&b| b == b'\n').count() > 1
+                || !is_blank(&self.file[line..range.start])
+            {
+                break;
+            }
+            comments.push(self.comment(range));
+            next = range.start;
+        }
+        comments.reverse();
+        comments
+    }
+
+    /// Comment starting on the same line, after `end`
+    fn trailing_comment(&self, ranges: &[Range<usize>], end: usize) -> Option<String> {
+        let after = ranges.partition_point(|r| r.start < end);
+        ranges
+            .get(after)
+            .filter(|r| self.file[end..r.start].iter().all(|&b| b == b' ' || b == b'\t'))
+            .map(|r| self.comment(r))
+    }
+
+    fn declaration(
+        &self,
+        span: &mut Option<Span>,
+        comments: &mut Vec<String>,
+        trailing_comment: &mut Option<String>,
+    ) {
+        self.span(span);
+        if let (SpanPass::Comments(ranges), Some(span)) = (&self.pass, *span) {
+            *comments = self.leading_comments(ranges, span.start.offset);
+            *trailing_comment = self.trailing_comment(ranges, span.end.offset);
+        }
+    }
+
+    fn fields(&self, fields: &mut [Field]) {
+        for f in fields {
+            self.declaration(&mut f.span, &mut f.comments, &mut f.trailing_comment);
+            if let FieldType::Group(ref mut g) = f.typ {
+                self.fields(&mut g.fields);
+            }
+        }
+    }
+
+    fn message(&self, msg: &mut Message) {
+        self.declaration(&mut msg.span, &mut msg.comments, &mut msg.trailing_comment);
+        self.fields(&mut msg.fields);
+        for o in &mut msg.oneofs {
+            self.span(&mut o.span);
+            self.fields(&mut o.fields);
+        }
+        for m in &mut msg.messages {
+            self.message(m);
+        }
+        for e in &mut msg.enums {
+            self.enumeration(e);
+        }
+        for e in &mut msg.extensions {
+            self.fields(::std::slice::from_mut(&mut e.field));
+        }
+    }
+
+    fn enumeration(&self, e: &mut Enumeration) {
+        self.declaration(&mut e.span, &mut e.comments, &mut e.trailing_comment);
+        for v in &mut e.values {
+            self.span(&mut v.span);
+        }
+    }
+
+    fn file_descriptor(&self, desc: &mut FileDescriptor) {
+        for m in &mut desc.messages {
+            self.message(m);
+        }
+        for e in &mut desc.enums {
+            self.enumeration(e);
+        }
+        for e in &mut desc.extensions {
+            self.fields(::std::slice::from_mut(&mut e.field));
+        }
+        for s in &mut desc.services {
+            self.service(s);
+        }
+    }
+
+    fn service(&self, s: &mut Service) {
+        self.declaration(&mut s.span, &mut s.comments, &mut s.trailing_comment);
+        for m in &mut s.methods {
+            self.declaration(&mut m.span, &mut m.comments, &mut m.trailing_comment);
+        }
+    }
+}
+
+
+/// Parses the next top level event, skipping invalid statements (see `skip_statement`)
+///
+/// `first` is whether no statement, even empty or invalid, was parsed yet, it is cleared once
+/// one is: a `syntax` statement must come first and be either `proto2` or `proto3`, otherwise
+/// it is ignored with an error.
+///
+/// `unterminated` is the offset of the block comment without `*/` ending the file, if any (see
+/// `unterminated_comment`), it is taken once reported.
+///
+/// Errors are pushed with their offsets. Returns `None` at the end of the file or if it ends in
+/// the middle of a declaration.
+fn next_event(
+    input: &mut Tokens,
+    unterminated: &mut Option<usize>,
+    errors: &mut Vec<(usize, ParserError)>,
+    first: &mut bool,
+) -> Option<Event> {
+    while !input.tokens.is_empty() {
+        let is_first = *first;
+        *first = false;
+        match event(*input) {
+            IResult::Done(rest, event) => {
+                let offset = input.offset();
+                *input = rest;
+                let error = match event {
+                    Event::Syntax(_) | Event::InvalidSyntax(_) if !is_first => {
+                        ParserError::MisplacedSyntax
+                    }
+                    Event::InvalidSyntax(s) => ParserError::InvalidSyntax(s),
+                    event => return Some(event),
+                };
+                errors.push((offset, error));
+                return Some(Event::Ignore);
+            }
+            IResult::Error(_) => {
+                let invalid = (input.offset(), ParserError::InvalidDeclaration);
+                let (rest, error) = match skip_statement(*input) {
+                    IResult::Done(rest, error) => (rest, error),
+                    // a `}` without matching `{`
+                    IResult::Error(_) => (input.skip(1), invalid),
+                    IResult::Incomplete(_) => {
+                        errors.push(invalid);
+                        *unterminated = None;
+                        break;
+                    }
+                };
+                errors.push(error);
+                *input = rest;
+            }
+            IResult::Incomplete(_) => {
+                errors.push(match unterminated.take() {
+                    Some(offset) => (offset, ParserError::UnterminatedComment),
+                    None => (input.offset(), ParserError::UnexpectedEof),
+                });
+                break;
+            }
+        }
+    }
+    if let Some(offset) = unterminated.take() {
+        errors.push((offset, ParserError::UnterminatedComment));
+    }
+    *input = input.skip(input.tokens.len());
+    None
+}
+
+/// `file` without its leading UTF-8 byte order mark, if any
+pub fn strip_bom(file: &[u8]) -> &[u8] {
+    file.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(file)
+}
+
+/// Parses and checks a whole .proto file
+///
+/// A leading byte order mark is skipped, positions are relative to the content following it.
+///
+/// Invalid statements are skipped (see `skip_statement`), parsing only stops if the file ends
+/// in the middle of a declaration. Returns the errors sorted by position.
+pub fn file_descriptor(
+    file: &[u8],
+    options: &ParserOptions,
+    with_spans: bool,
+) -> (FileDescriptor, Vec<ParserErrorWithLocation>) {
+    let file = strip_bom(file);
+    let mut desc = FileDescriptor {
+        syntax: options.default_syntax,
+        ..FileDescriptor::default()
+    };
+    let (tokens, comments) = tokenize(file);
+    let mut unterminated = unterminated_comment(file, &comments);
+    // errors and their offsets
+    let mut errors = Vec::new();
+    let mut input = Tokens {
+        file,
+        tokens: &tokens,
+    };
+    let mut first = true;
+    while let Some(event) = next_event(&mut input, &mut unterminated, &mut errors, &mut first) {
+        match event {
+            Event::Syntax(s) => desc.syntax = s,
+            Event::Edition(e) => desc.edition = Some(e),
+            Event::Import(i) => desc.imports.push(i),
+            Event::Package(p) => desc.package = p,
+            Event::Message((m, e)) => {
+                desc.messages.push(m);
+                errors.extend(e);
+            }
+            Event::Enum((en, e)) => {
+                desc.enums.push(en);
+                errors.extend(e);
+            }
+            Event::Extensions(e) => desc.extensions.extend(e),
+            Event::Option(o) => desc.options.push(o),
+            Event::Service((s, e)) => {
+                desc.services.push(s);
+                errors.extend(e);
+            }
+            // reported by `next_event`
+            Event::InvalidSyntax(_) | Event::Ignore => (),
+        }
+    }
+
+    if let Some(ref rewrite) = options.import_rewrite {
+        for import in &mut desc.imports {
+            import.path = rewrite(&import.path);
+        }
+    }
+
+    resolve_type_kinds(&mut desc);
+    SpanFixer {
+        file,
+        pass: SpanPass::Comments(&comments),
+    }.file_descriptor(&mut desc);
+    errors.extend(check(file, &desc, options));
+    if !with_spans {
+        SpanFixer {
+            file,
+            pass: SpanPass::Clear,
+        }.file_descriptor(&mut desc);
+    }
+
+    let lines = LineIndex::new(file);
+    errors.sort_by_key(|&(offset, _)| offset);
+    let errors = errors
+        .into_iter()
+        .map(|(offset, error)| {
+            let loc = lines.loc(offset);
+            ParserErrorWithLocation {
+                error,
+                line: loc.line,
+                col: loc.col,
+            }
+        })
+        .collect();
+    (desc, errors)
+}
+
+/// An iterator over the top level declarations of a .proto file, see
+/// `FileDescriptor::iter_top_level`
+#[derive(Debug, Clone)]
+pub struct TopLevelIter<'a> {
+    file: &'a [u8],
+    lines: LineIndex<'a>,
+    /// The tokens of the file and the byte ranges of its comments (see `tokenize`)
+    tokens: Vec<(Token, Span)>,
+    comments: Vec<Range<usize>>,
+    /// Number of tokens parsed
+    parsed: usize,
+    /// Offset of a block comment without `*/` ending the file, until reported
+    unterminated: Option<usize>,
+    /// Errors not returned yet, and their offsets
+    errors: VecDeque<(usize, ParserError)>,
+    /// Last declaration, returned after its errors
+    pending: Option<TopLevel>,
+    /// Whether no statement was parsed yet
+    first: bool,
+}
+
+impl<'a> TopLevelIter<'a> {
+    /// Creates an iterator over the declarations of the .proto file content `file`
+    ///
+    /// A leading byte order mark is skipped.
+    pub fn new(file: &'a [u8]) -> TopLevelIter<'a> {
+        let file = strip_bom(file);
+        let (tokens, comments) = tokenize(file);
+        TopLevelIter {
+            file,
+            lines: LineIndex::new(file),
+            tokens,
+            unterminated: unterminated_comment(file, &comments),
+            comments,
+            parsed: 0,
+            errors: VecDeque::new(),
+            pending: None,
+            first: true,
+        }
+    }
+}
+
+impl<'a> Iterator for TopLevelIter<'a> {
+    type Item = Result<TopLevel, ParserErrorWithLocation>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // attaches the comments as `parse` does, then clears the spans
+        let fixers = [
+            SpanFixer {
+                file: self.file,
+                pass: SpanPass::Comments(&self.comments),
+            },
+            SpanFixer {
+                file: self.file,
+                pass: SpanPass::Clear,
+            },
+        ];
+        loop {
+            if let Some((offset, error)) = self.errors.pop_front() {
+                let loc = self.lines.loc(offset);
+                return Some(Err(ParserErrorWithLocation {
+                    error,
+                    line: loc.line,
+                    col: loc.col,
+                }));
+            }
+            if let Some(top_level) = self.pending.take() {
+                return Some(Ok(top_level));
+            }
+            let mut input = Tokens {
+                file: self.file,
+                tokens: &self.tokens[self.parsed..],
+            };
+            let mut errors = Vec::new();
+            let event = next_event(
+                &mut input,
+                &mut self.unterminated,
+                &mut errors,
+                &mut self.first,
+            );
+            self.parsed = self.tokens.len() - input.tokens.len();
+            self.errors.extend(errors);
+            let event = match event {
+                Some(event) => event,
+                None if self.errors.is_empty() => return None,
+                None => continue,
+            };
+            self.pending = match event {
+                Event::Syntax(s) => Some(TopLevel::Syntax(s)),
+                Event::Edition(e) => Some(TopLevel::Edition(e)),
+                Event::Import(i) => Some(TopLevel::Import(i)),
+                Event::Package(p) => Some(TopLevel::Package(p)),
+                Event::Message((mut m, e)) => {
+                    for fixer in &fixers {
+                        fixer.message(&mut m);
+                    }
+                    self.errors.extend(e);
+                    Some(TopLevel::Message(m))
+                }
+                Event::Enum((mut en, e)) => {
+                    for fixer in &fixers {
+                        fixer.enumeration(&mut en);
+                    }
+                    self.errors.extend(e);
+                    Some(TopLevel::Enum(en))
+                }
+                Event::Extensions(mut e) => {
+                    for fixer in &fixers {
+                        for ext in &mut e {
+                            fixer.fields(::std::slice::from_mut(&mut ext.field));
+                        }
+                    }
+                    Some(TopLevel::Extensions(e))
+                }
+                Event::Option(o) => Some(TopLevel::Option(o)),
+                Event::Service((mut s, e)) => {
+                    for fixer in &fixers {
+                        fixer.service(&mut s);
+                    }
+                    self.errors.extend(e);
+                    Some(TopLevel::Service(s))
+                }
+                Event::InvalidSyntax(_) | Event::Ignore => None,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use DefaultValue;
+
+    /// Runs `parser` on the tokens of `file`
+    fn parse<T>(parser: fn(Tokens) -> IResult<Tokens, T>, file: &str) -> IResult<(), T> {
+        let (tokens, _) = tokenize(file.as_bytes());
+        let input = Tokens {
+            file: file.as_bytes(),
+            tokens: &tokens,
+        };
+        match parser(input) {
+            IResult::Done(_, o) => IResult::Done((), o),
+            IResult::Error(e) => IResult::Error(e),
+            IResult::Incomplete(n) => IResult::Incomplete(n),
+        }
+    }
+
+    #[test]
+    fn test_message() {
+        let msg = r#"message ReferenceData
+    {
+        repeated ScenarioInfo  scenarioSet = 1;
+        repeated CalculatedObjectInfo calculatedObjectSet = 2;
+        repeated RiskFactorList riskFactorListSet = 3;
+        repeated RiskMaturityInfo riskMaturitySet = 4;
+        repeated IndicatorInfo indicatorSet = 5;
+        repeated RiskStrikeInfo riskStrikeSet = 6;
+        repeated FreeProjectionList freeProjectionListSet = 7;
+        repeated ValidationProperty ValidationSet = 8;
+        repeated CalcProperties calcPropertiesSet = 9;
+        repeated MaturityInfo maturitySet = 10;
+    }"#;
+
+        let mess = parse(message, msg);
+        if let ::nom::IResult::Done(_, (mess, _)) = mess {
+            assert_eq!(10, mess.fields.len());
+        }
+    }
+
+    #[test]
+    fn test_enum() {
+        let msg = r#"enum PairingStatus {
+                DEALPAIRED        = 0;
+                INVENTORYORPHAN   = 1;
+                CALCULATEDORPHAN  = 2;
+                CANCELED          = 3;
+    }"#;
+
+        let enumeration = parse(enumerator, msg);
+        if let ::nom::IResult::Done(_, (mess, _)) = enumeration {
+            assert_eq!(4, mess.values.len());
+        }
+    }
+
+    #[test]
+    fn test_option() {
+        let msg = r#"option optimize_for = SPEED;"#;
+
+        match parse(option, msg) {
+            ::nom::IResult::Done(_, o) => {
+                assert_eq!("optimize_for", o.name);
+                assert_eq!(ProtobufConstant::Ident("SPEED".to_string()), o.value);
+            }
+            e => panic!("Expecting done {:?}", e),
         }
     }
 
@@ -429,10 +1652,10 @@ mod test {
         optional ContainerForNested.NestedEnum e = 2;
     }
     "#;
-        let desc = file_descriptor(msg.as_bytes()).to_full_result().unwrap();
+        let desc = FileDescriptor::parse(msg.as_bytes()).unwrap();
         assert_eq!(
             vec!["test_import_nested_imported_pb.proto"],
-            desc.import_paths
+            desc.import_paths()
         );
     }
 
@@ -446,7 +1669,7 @@ mod test {
         optional ContainerForNested.NestedEnum e = 2;
     }
     "#;
-        let desc = file_descriptor(msg.as_bytes()).to_full_result().unwrap();
+        let desc = FileDescriptor::parse(msg.as_bytes()).unwrap();
         assert_eq!("foo.bar".to_string(), desc.package);
     }
 
@@ -461,8 +1684,8 @@ mod test {
         optional b = 1;
     }"#;
 
-        let mess = message(msg.as_bytes());
-        if let ::nom::IResult::Done(_, mess) = mess {
+        let mess = parse(message, msg);
+        if let ::nom::IResult::Done(_, (mess, _)) = mess {
             assert!(mess.messages.len() == 1);
         }
     }
@@ -474,12 +1697,15 @@ mod test {
         optional map<string, int32> b = 1;
     }"#;
 
-        let mess = message(msg.as_bytes());
-        if let ::nom::IResult::Done(_, mess) = mess {
+        let mess = parse(message, msg);
+        if let ::nom::IResult::Done(_, (mess, _)) = mess {
             assert_eq!(1, mess.fields.len());
             match mess.fields[0].typ {
-                FieldType::Map(ref f) => match &**f {
-                    &(FieldType::String, FieldType::Int32) => (),
+                FieldType::Map(ref f) => match **f {
+                    MapType {
+                        key: FieldType::String,
+                        value: FieldType::Int32,
+                    } => (),
                     ref f => panic!("Expecting Map<String, Int32> found {:?}", f),
                 },
                 ref f => panic!("Expecting map, got {:?}", f),
@@ -489,6 +1715,48 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_map_key_type() {
+        let msg = "message A { map<string, MyMsg> m = 1; }";
+        let mess = parse(message, msg).unwrap().1 .0;
+        let map = MapType {
+            key: FieldType::String,
+            value: FieldType::MessageOrEnum("MyMsg".to_string()),
+        };
+        assert_eq!(FieldType::Map(Box::new(map)), mess.fields[0].typ);
+
+        for key in &["double", "float", "bytes", "MyMsg", "map<int32, int32>"] {
+            let msg = format!("message A {{ map<{}, int32> m = 1; }}", key);
+            assert!(FileDescriptor::parse(&msg).is_err(), "{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_map_absolute_value() {
+        let msg = r#"message A
+    {
+        map<string, .pkg.Msg> m = 1;
+        map<int32, other.pkg.Msg > n = 2;
+    }"#;
+
+        let mess = parse(message, msg).unwrap().1 .0;
+        let values = mess
+            .fields
+            .iter()
+            .map(|f| match f.typ {
+                FieldType::Map(ref m) => m.value.clone(),
+                ref f => panic!("Expecting map, got {:?}", f),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                FieldType::MessageOrEnum(".pkg.Msg".to_string()),
+                FieldType::MessageOrEnum("other.pkg.Msg".to_string()),
+            ],
+            values
+        );
+    }
+
     #[test]
     fn test_oneof() {
         let msg = r#"message A
@@ -502,8 +1770,8 @@ mod test {
         repeated bool a5 = 5;
     }"#;
 
-        let mess = message(msg.as_bytes());
-        if let ::nom::IResult::Done(_, mess) = mess {
+        let mess = parse(message, msg);
+        if let ::nom::IResult::Done(_, (mess, _)) = mess {
             assert_eq!(1, mess.oneofs.len());
             assert_eq!(3, mess.oneofs[0].fields.len());
         }
@@ -518,8 +1786,8 @@ mod test {
        bytes name =2;
     }"#;
 
-        let mess = message(msg.as_bytes());
-        if let ::nom::IResult::Done(_, mess) = mess {
+        let mess = parse(message, msg);
+        if let ::nom::IResult::Done(_, (mess, _)) = mess {
             assert_eq!(vec![4..5, 15..16, 17..21, 30..31], mess.reserved_nums);
             assert_eq!(
                 vec!["foo".to_string(), "bar".to_string()],
@@ -531,23 +1799,272 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_reserved_mixed() {
+        let msg = r#"message Sample {
+       reserved 2, 10 to 12, 15, 20 to max;
+    }"#;
+
+        let mess = parse(message, msg).unwrap().1 .0;
+        assert_eq!(
+            vec![2..3, 10..13, 15..16, 20..MAX_FIELD_NUMBER + 1],
+            mess.reserved_nums
+        );
+
+        let msg = "message Sample { reserved 2, 15, 9 to 11, 40 to max; }";
+        let mess = parse(message, msg).unwrap().1 .0;
+        assert_eq!(
+            vec![2..3, 15..16, 9..12, 40..MAX_FIELD_NUMBER + 1],
+            mess.reserved_nums
+        );
+
+        // numbers and names cannot be mixed in one statement
+        for msg in &[
+            "message Sample { reserved 2, \"foo\"; }",
+            "message Sample { reserved \"foo\", 2 to 4; }",
+        ] {
+            assert!(FileDescriptor::parse(msg).is_err(), "{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_reserved_several_statements() {
+        let msg = r#"message Sample {
+       reserved 4, 5;
+       reserved "foo";
+       reserved 8;  // comment
+       reserved "bar";
+    }"#;
+
+        let mess = parse(message, msg).unwrap().1 .0;
+        assert_eq!(vec![4..5, 5..6, 8..9], mess.reserved_nums);
+        assert_eq!(
+            vec!["foo".to_string(), "bar".to_string()],
+            mess.reserved_names
+        );
+
+        // each name is a single string, names are separated by one comma
+        for names in &["\"foo\" \"bar\"", "\"foo\",, \"bar\"", "\"foo\",", "\"foo\" , , \"bar\""] {
+            let msg = format!("message Sample {{ reserved {}; }}", names);
+            assert!(FileDescriptor::parse(&msg).is_err(), "{}", msg);
+            let msg = format!("enum Sample {{ A = 0; reserved {}; }}", names);
+            assert!(FileDescriptor::parse(&msg).is_err(), "{}", msg);
+        }
+        let desc = FileDescriptor::parse("message A { reserved \"f\\x6fo\" ,\"bar\"; }").unwrap();
+        assert_eq!(vec!["foo", "bar"], desc.messages[0].reserved_names);
+    }
+
+    #[test]
+    fn test_enum_reserved() {
+        let msg = r#"enum Sample {
+            A = 0;
+            reserved 2, 15 to 20;
+            reserved "FOO", "BAR";
+            reserved 40 to max;
+        }"#;
+
+        let e = parse(enumerator, msg).unwrap().1 .0;
+        assert_eq!(vec![2..=2, 15..=20, 40..=i32::MAX], e.reserved_nums);
+        assert_eq!(vec!["FOO".to_string(), "BAR".to_string()], e.reserved_names);
+        assert_eq!(1, e.values.len());
+
+        let msg = r#"enum Sample {
+            reserved 2147483647, 2147483646;
+            reserved 2147483646 to max;
+        }"#;
+        let e = parse(enumerator, msg).unwrap().1 .0;
+        let max = i32::MAX;
+        assert_eq!(vec![max..=max, max - 1..=max - 1, max - 1..=max], e.reserved_nums);
+
+        let msg = r#"enum Sample {
+            A = 0;
+            reserved 2, "FOO";
+        }"#;
+        let (e, errors) = parse(enumerator, msg).unwrap().1;
+        assert!(e.reserved_nums.is_empty() && e.reserved_names.is_empty());
+        assert_eq!(1, errors.len());
+        assert_eq!(ParserError::InvalidDeclaration, errors[0].1);
+    }
+
+    #[test]
+    fn test_integer_literals() {
+        let msg = r#"message A {
+            optional int32 a = 0x1F;
+            optional int32 b = 0X20 [default = 017];
+            optional int32 c = 010 [default = -5];
+            reserved 0x40 to 0100;
+        }
+        enum E {
+            NEG = -5;
+            HEX = 0x7FFFFFFF;
+            OCT = -010;
+            MIN = -2147483648;
+        }"#;
+        let desc = FileDescriptor::parse(msg).unwrap();
+        let fields = &desc.messages[0].fields;
+        let numbers = fields.iter().map(|f| f.number).collect::<Vec<_>>();
+        assert_eq!(vec![31, 32, 8], numbers);
+        assert_eq!(Ok(Some(DefaultValue::I64(15))), fields[1].typed_default());
+        assert_eq!(Ok(Some(DefaultValue::I64(-5))), fields[2].typed_default());
+        assert_eq!(vec![64..65], desc.messages[0].reserved_nums);
+        let values = desc.enums[0].values.iter().map(|v| v.number).collect::<Vec<_>>();
+        assert_eq!(vec![-5, i32::MAX, -8, i32::MIN], values);
+
+        for msg in &[
+            "message A { optional int32 a = -1; }",
+            "message A { optional int32 a = 0x100000000; }",
+            "message A { optional int32 a = 09; }",
+            "enum E { A = 2147483648; }",
+            "enum E { A = -0x80000001; }",
+        ] {
+            assert!(FileDescriptor::parse(msg).is_err(), "{}", msg);
+        }
+
+        let msg = "message A {\n    optional int32 x = 99999999999;\n}";
+        let err = FileDescriptor::parse(msg).unwrap_err();
+        assert_eq!(ParserError::IntegerOverflow("99999999999".to_string()), err.error);
+        assert_eq!((2, 5), (err.line, err.col));
+        for &(msg, lit) in &[
+            ("enum E { A = -2147483649; }", "-2147483649"),
+            ("enum E { A = 0x80000000 [deprecated = true]; }", "0x80000000"),
+            ("message A { reserved 1 to 3000000000; }", "3000000000"),
+            ("message A { optional group G = 4294967296 {} }", "4294967296"),
+        ] {
+            let err = FileDescriptor::parse(msg).unwrap_err();
+            assert_eq!(ParserError::IntegerOverflow(lit.to_string()), err.error, "{}", msg);
+        }
+        // option values may be 64-bit, invalid literals do not overflow
+        for msg in &[
+            "message A { optional int64 a = [default = 99999999999]; }",
+            "message A { optional int32 a = 09; }",
+        ] {
+            let err = FileDescriptor::parse(msg).unwrap_err();
+            assert_eq!(ParserError::InvalidDeclaration, err.error, "{}", msg);
+        }
+        let msg = "message A { optional int64 a = 1 [default = 99999999999]; }";
+        assert!(FileDescriptor::parse(msg).is_ok());
+    }
+
     #[test]
     fn test_default_value_int() {
         let msg = r#"message Sample {
             optional int32 x = 1 [default = 17];
         }"#;
 
-        let mess = message(msg.as_bytes()).unwrap().1;
+        let mess = parse(message, msg).unwrap().1 .0;
         assert_eq!("17", mess.fields[0].default.as_ref().expect("default"));
     }
 
+    #[test]
+    fn test_optional_rules() {
+        let rules = |msg: &str| {
+            let desc = FileDescriptor::parse(msg).unwrap();
+            desc.messages[0].fields.iter().map(|f| f.rule).collect::<Vec<_>>()
+        };
+        let proto2 = r#"syntax = "proto2";
+        message A {
+            optional int32 a = 1;
+            required int32 b = 2;
+            map<int32, int32> c = 3;
+        }"#;
+        assert_eq!(vec![Rule::Optional, Rule::Required, Rule::Singular], rules(proto2));
+        let proto3 = r#"syntax = "proto3";
+        message A {
+            optional int32 a = 1;
+            int32 b = 2;
+            repeated int32 c = 3;
+        }"#;
+        assert_eq!(vec![Rule::Optional, Rule::Singular, Rule::Repeated], rules(proto3));
+    }
+
+    #[test]
+    fn test_field_options_list() {
+        let msg = r#"message Sample {
+            string s = 1 [json_name = "s", (custom) = 1, deprecated = true];
+            repeated int32 r = 2 [ packed = false , (my.opt).x = "y" ];
+        }"#;
+
+        let mess = parse(message, msg).unwrap().1 .0;
+        let s = &mess.fields[0];
+        assert_eq!(Some("s"), s.json_name.as_deref());
+        assert!(s.deprecated);
+        assert_eq!(
+            vec![
+                ProtobufOption {
+                    name: "json_name".to_string(),
+                    value: ProtobufConstant::String("s".to_string()),
+                },
+                ProtobufOption {
+                    name: "(custom)".to_string(),
+                    value: ProtobufConstant::Int(1),
+                },
+                ProtobufOption {
+                    name: "deprecated".to_string(),
+                    value: ProtobufConstant::Bool(true),
+                },
+            ],
+            s.options
+        );
+        let r = &mess.fields[1];
+        assert_eq!(Some(false), r.packed);
+        assert!(!r.deprecated);
+        assert_eq!(None, r.json_name);
+        assert_eq!("(my.opt).x", r.options[1].name);
+    }
+
+    #[test]
+    fn test_json_name_with_packed() {
+        let msg = r#"message Sample {
+            repeated int32 foo = 1 [json_name = "fooList", packed = true];
+            repeated int32 bar = 2 [packed = false, json_name = "barList"];
+            repeated int32 baz = 3 [packed = true];
+        }"#;
+
+        let mess = parse(message, msg).unwrap().1 .0;
+        let options = mess
+            .fields
+            .iter()
+            .map(|f| (f.json_name.as_deref(), f.packed))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (Some("fooList"), Some(true)),
+                (Some("barList"), Some(false)),
+                (None, Some(true)),
+            ],
+            options
+        );
+    }
+
+    #[test]
+    fn test_field_options_multiline() {
+        let msg = r#"message Sample {
+            int32 x = 1 [
+                deprecated = true, // no longer set, see [docs]
+                /* custom */
+                (foo) = "bar"
+                , json_name = "y" // trailing
+            ];
+            int32 z = 2;
+        }"#;
+
+        let desc = FileDescriptor::parse(msg.as_bytes()).unwrap();
+        assert_eq!(2, desc.messages[0].fields.len());
+        let x = &desc.messages[0].fields[0];
+        assert!(x.deprecated);
+        assert_eq!(Some("y"), x.json_name.as_deref());
+        let names = x.options.iter().map(|o| &*o.name).collect::<Vec<_>>();
+        assert_eq!(vec!["deprecated", "(foo)", "json_name"], names);
+        assert_eq!(ProtobufConstant::String("bar".to_string()), x.options[1].value);
+    }
+
     #[test]
     fn test_default_value_string() {
         let msg = r#"message Sample {
             optional string x = 1 [default = "ab\nc d\"g\'h\0\"z"];
         }"#;
 
-        let mess = message(msg.as_bytes()).unwrap().1;
+        let mess = parse(message, msg).unwrap().1 .0;
         assert_eq!(r#""ab\nc d\"g\'h\0\"z""#, mess.fields[0].default.as_ref().expect("default"));
     }
 
@@ -557,10 +2074,37 @@ mod test {
             optional bytes x = 1 [default = "ab\nc d\xfeE\"g\'h\0\"z"];
         }"#;
 
-        let mess = message(msg.as_bytes()).unwrap().1;
+        let mess = parse(message, msg).unwrap().1 .0;
         assert_eq!(r#""ab\nc d\xfeE\"g\'h\0\"z""#, mess.fields[0].default.as_ref().expect("default"));
     }
 
+    #[test]
+    fn test_string_literals() {
+        let msg = r#"
+            option (a) = 'single "quoted"';
+            option (b) = "tab\t" /* comment */ 'newline\n'
+                "\x41\101\u00e9\"";
+            message Sample {
+                optional string x = 1 [default = "foo" "bar", json_name = 'x\x58'];
+            }"#;
+        let desc = FileDescriptor::parse(msg).unwrap();
+        let strings = desc.options.iter().map(|o| o.value.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ProtobufConstant::String("single \"quoted\"".to_string()),
+                ProtobufConstant::String("tab\tnewline\nAAé\"".to_string()),
+            ],
+            strings
+        );
+        let x = &desc.messages[0].fields[0];
+        assert_eq!(Some(r#""foo" "bar""#), x.default.as_deref());
+        assert_eq!(Ok(Some(DefaultValue::String("foobar".to_string()))), x.typed_default());
+        assert_eq!(Some("xX"), x.json_name.as_deref());
+
+        let msg = r#"message A { optional string a = 1 [(o) = "\q"]; }"#;
+        assert!(FileDescriptor::parse(msg).is_err());
+    }
+
     #[test]
     fn test_group() {
         let msg = r#"message MessageWithGroup {
@@ -573,11 +2117,13 @@ mod test {
 
             required int bbb = 3;
         }"#;
-        let mess = message(msg.as_bytes()).unwrap().1;
+        let mess = parse(message, msg).unwrap().1 .0;
 
-        assert_eq!("Identifier", mess.fields[1].name);
-        if let FieldType::Group(ref group_fields) = mess.fields[1].typ {
-            assert_eq!(2, group_fields.len());
+        assert_eq!("identifier", mess.fields[1].name);
+        assert_eq!(18, mess.fields[1].number);
+        if let FieldType::Group(ref group) = mess.fields[1].typ {
+            assert_eq!("Identifier", group.name);
+            assert_eq!(2, group.fields.len());
         } else {
             panic!("expecting group");
         }
@@ -618,4 +2164,1101 @@ mod test {
         assert_eq!("google.protobuf.MessageOptions", fd.extensions[2].extendee);
         assert_eq!(17003, fd.extensions[2].field.number);
     }
+
+    #[test]
+    fn test_mixed_line_endings() {
+        let msg = "syntax = \"proto2\";\r\n\
+                   // a comment\n\
+                   message A {\r\n\
+                   \x20 optional int32 a = 1; // trailing\r\n\
+                   }\n\
+                   \r\n\
+                   \x20\x20foo bar;\r\n";
+
+        let err = FileDescriptor::parse(msg.as_bytes()).unwrap_err();
+        assert_eq!(ParserError::InvalidDeclaration, err.error);
+        assert_eq!(7, err.line);
+        assert_eq!(3, err.col);
+    }
+
+    #[test]
+    fn test_bom() {
+        let msg = "\u{feff}syntax = \"proto3\";\r\nmessage A {\r\n  int32 a = 1;\r\n}\r\n";
+        let desc = FileDescriptor::parse_with_spans(msg).unwrap();
+        assert_eq!(Syntax::Proto3, desc.syntax);
+        let span = desc.messages[0].span.unwrap();
+        assert_eq!((2, 1), (span.start.line, span.start.col));
+        assert_eq!((4, 2), (span.end.line, span.end.col));
+        // positions are relative to the content following the byte order mark
+        assert_eq!("message A {\r\n  int32 a = 1;\r\n}", &msg[3..][span.range()]);
+
+        let msg = "\u{feff}message A {\r\n  int32 a = 1;\r\n  foo;\r\n}";
+        let err = FileDescriptor::parse(msg).unwrap_err();
+        assert_eq!((3, 3), (err.line, err.col));
+        let items = FileDescriptor::iter_top_level(msg).collect::<Vec<_>>();
+        assert_eq!(3, items[0].clone().unwrap_err().line);
+        assert!(items[1].is_ok());
+    }
+
+    #[test]
+    fn test_comment_at_eof() {
+        let msg = "message A {}\r\n// no line ending";
+        let desc = FileDescriptor::parse(msg.as_bytes()).unwrap();
+        assert_eq!(1, desc.messages.len());
+    }
+
+    #[test]
+    fn test_unexpected_eof() {
+        let msg = "message A {}\n\nmessage B {\n    optional int32 a = 1;\n";
+        let err = FileDescriptor::parse(msg.as_bytes()).unwrap_err();
+        assert_eq!(ParserError::UnexpectedEof, err.error);
+        assert_eq!((3, 1), (err.line, err.col));
+    }
+
+    #[test]
+    fn test_spans_slice_declarations() {
+        let msg = r#"syntax = "proto2";
+
+message A {
+    optional int32 a = 1;
+    message B {}
+} ;
+// comment
+enum E { X = 0; }
+"#;
+        let desc = FileDescriptor::parse_with_spans(msg).unwrap();
+        let a = desc.messages[0].span.expect("message span");
+        assert_eq!(
+            "message A {\n    optional int32 a = 1;\n    message B {}\n}",
+            &msg[a.range()]
+        );
+        assert_eq!((3, 1), (a.start.line, a.start.col));
+        assert_eq!((6, 2), (a.end.line, a.end.col));
+        let e = desc.enums[0].span.expect("enum span");
+        assert_eq!("enum E { X = 0; }", &msg[e.range()]);
+
+        let desc = FileDescriptor::parse(msg).unwrap();
+        assert!(desc.messages[0].span.is_none());
+        assert!(desc.enums[0].span.is_none());
+    }
+
+    #[test]
+    fn test_spans_all_declarations() {
+        let msg = r#"syntax = "proto3";
+package pkg;
+
+message A {
+ int32 value = 123;
+  oneof o { string s = 2; }
+}
+enum E {
+  X = 0; // zero
+}
+service S { rpc M(A) returns (A); }
+"#;
+        let desc = FileDescriptor::parse_with_spans(msg).unwrap();
+        let field = desc.messages[0].fields[0].span.expect("field span");
+        assert_eq!((5, 2), (field.start.line, field.start.col));
+        assert_eq!((5, 20), (field.end.line, field.end.col));
+        assert_eq!("int32 value = 123;", &msg[field.range()]);
+
+        let range = |span: Option<Span>| &msg[span.expect("span").range()];
+        let oneof = &desc.messages[0].oneofs[0];
+        assert_eq!("oneof o { string s = 2; }", range(oneof.span));
+        assert_eq!("string s = 2;", range(oneof.fields[0].span));
+        assert_eq!("X = 0;", range(desc.enums[0].values[0].span));
+        let service = &desc.services[0];
+        assert_eq!("service S { rpc M(A) returns (A); }", range(service.span));
+        assert_eq!("rpc M(A) returns (A);", range(service.methods[0].span));
+
+        let desc = FileDescriptor::parse(msg).unwrap();
+        assert!(desc.messages[0].fields[0].span.is_none());
+        assert!(desc.messages[0].oneofs[0].span.is_none());
+        assert!(desc.enums[0].values[0].span.is_none());
+        assert!(desc.services[0].span.is_none());
+    }
+
+    #[test]
+    fn test_extend_absolute_extendee() {
+        let proto = r#"
+            extend .google.protobuf.FieldOptions {
+                optional bool foo = 17001;
+            }
+        "#;
+
+        let fd = FileDescriptor::parse(proto.as_bytes()).expect("fd");
+        assert_eq!(".google.protobuf.FieldOptions", fd.extensions[0].extendee);
+    }
+
+    #[test]
+    fn test_extend_several_fields() {
+        let proto = r#"
+            extend google.protobuf.MessageOptions {
+                optional string a = 1000;
+                optional int32 b = 1001;
+            }
+            message Foo {
+                optional int32 x = 1;
+                extend Bar {
+                    optional Foo foo = 100;
+                    repeated string names = 101;
+                }
+                message Bar { extensions 100 to max; }
+            }
+        "#;
+
+        let fd = FileDescriptor::parse_with_spans(proto.as_bytes()).expect("fd");
+        fn fields(e: &[Extension]) -> Vec<(&str, &str, i32)> {
+            e.iter()
+                .map(|e| (&*e.extendee, &*e.field.name, e.field.number))
+                .collect()
+        }
+        assert_eq!(
+            vec![
+                ("google.protobuf.MessageOptions", "a", 1000),
+                ("google.protobuf.MessageOptions", "b", 1001),
+            ],
+            fields(&fd.extensions)
+        );
+        let foo = &fd.messages[0];
+        assert_eq!(vec![("Bar", "foo", 100), ("Bar", "names", 101)], fields(&foo.extensions));
+        assert_eq!(1, foo.fields.len());
+        assert_eq!(1, foo.messages.len());
+        let span = foo.extensions[1].field.span.unwrap();
+        assert_eq!("repeated string names = 101;", &proto[span.range()]);
+    }
+
+    #[test]
+    fn test_extensions_only() {
+        let proto = r#"
+            syntax = "proto2";
+            package my.options;
+
+            import "google/protobuf/descriptor.proto";
+
+            extend google.protobuf.FieldOptions {
+                optional string my_field_option = 50000;
+            }
+            extend google.protobuf.MessageOptions {
+                optional int32 my_message_option = 50001;
+            }
+        "#;
+
+        let fd = FileDescriptor::parse(proto.as_bytes()).expect("fd");
+        assert_eq!(vec!["google/protobuf/descriptor.proto"], fd.import_paths());
+        assert_eq!(2, fd.extensions.len());
+        assert!(fd.messages.is_empty());
+        assert!(fd.enums.is_empty());
+    }
+
+    #[test]
+    fn test_import_kinds() {
+        let msg = r#"syntax = "proto3";
+
+    import "a.proto";
+    import public "b.proto";
+    import weak "c.proto";
+    import public "d.proto";
+    "#;
+        let desc = FileDescriptor::parse(msg.as_bytes()).unwrap();
+        assert_eq!(
+            vec![ImportKind::Default, ImportKind::Public, ImportKind::Weak, ImportKind::Public],
+            desc.dependencies().iter().map(|i| i.kind).collect::<Vec<_>>()
+        );
+        assert_eq!(vec!["a.proto", "b.proto", "c.proto", "d.proto"], desc.import_paths());
+        assert_eq!(vec!["b.proto", "d.proto"], desc.public_imports());
+        assert_eq!(vec!["c.proto"], desc.weak_imports());
+    }
+
+    #[test]
+    fn test_option_before_package() {
+        let msg = r#"syntax = "proto3";
+    option java_package = "com.example.foo";
+    option java_multiple_files = true;
+    package foo.bar;
+    import "a.proto";
+    option go_package = "example.com/foo";
+
+    message A {}
+    "#;
+        let desc = FileDescriptor::parse(msg.as_bytes()).unwrap();
+        assert_eq!("foo.bar", desc.package);
+        assert_eq!(vec!["a.proto"], desc.import_paths());
+        let names = desc.options.iter().map(|o| &*o.name).collect::<Vec<_>>();
+        assert_eq!(vec!["java_package", "java_multiple_files", "go_package"], names);
+        assert_eq!(1, desc.messages.len());
+    }
+
+    #[test]
+    fn test_invalid_file_option() {
+        for msg in &["option java_package = ;", "option (x) = 1 2 3;"] {
+            let err = FileDescriptor::parse(msg).unwrap_err();
+            assert_eq!(ParserError::InvalidDeclaration, err.error);
+            assert_eq!((1, 1), (err.line, err.col));
+        }
+
+        let msg = "option java_package = ;\noption go_package = \"foo\";\nmessage A {}";
+        let (desc, errors) = FileDescriptor::parse_recovering(msg);
+        assert_eq!(1, errors.len());
+        assert_eq!(vec!["go_package"], desc.options.iter().map(|o| &*o.name).collect::<Vec<_>>());
+        assert_eq!(1, desc.messages.len());
+    }
+
+    #[test]
+    fn test_invalid_element_options() {
+        let msg = r#"message A {
+    option (x) = 1 2 3;
+    optional int32 a = 1;
+    oneof o {
+        option (y) = ;
+        int32 b = 2;
+    }
+}
+enum E {
+    option allow_alias = ;
+    X = 0;
+}
+service S {
+    option deprecated = ;
+    rpc M (A) returns (A) { option (z) = 1 2; }
+    rpc N (A) returns (A);
+}
+"#;
+        assert!(FileDescriptor::parse(msg).is_err());
+        let (desc, errors) = FileDescriptor::parse_recovering(msg);
+        let locs = errors.iter().map(|e| (e.line, e.col)).collect::<Vec<_>>();
+        assert_eq!(vec![(2, 5), (5, 9), (10, 5), (14, 5), (15, 5)], locs);
+        assert!(errors.iter().all(|e| e.error == ParserError::InvalidDeclaration));
+
+        let a = &desc.messages[0];
+        assert!(a.options.is_empty() && a.oneofs[0].options.is_empty());
+        assert_eq!(("a", "b"), (&*a.fields[0].name, &*a.oneofs[0].fields[0].name));
+        assert_eq!(1, desc.enums[0].values.len());
+        let s = &desc.services[0];
+        assert!(s.options.is_empty());
+        assert_eq!(vec!["N"], s.methods.iter().map(|m| &*m.name).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_service() {
+        let msg = r#"syntax = "proto3";
+    package google.example;
+
+    service Library {
+        option (google.api.default_host) = "library.example.com";
+        option (google.api.oauth_scopes) =
+            "https://www.googleapis.com/auth/cloud-platform";
+
+        // unary
+        rpc GetBook(GetBookRequest) returns (Book) {
+            option (google.api.http) = { get: "/v1/{name=shelves/*/books/*}" };
+            option deprecated = true;
+        }
+        rpc UploadBooks (stream Book) returns (UploadSummary);
+        rpc ListBooks(ListBooksRequest) returns ( stream Book ) {}
+        rpc Chat(stream .google.example.Message) returns (stream Message) {};
+    }
+    "#;
+        let desc = FileDescriptor::parse(msg.as_bytes()).unwrap();
+        assert_eq!(1, desc.services.len());
+        let service = &desc.services[0];
+        assert_eq!("Library", service.name);
+        assert_eq!(
+            vec![
+                ProtobufOption {
+                    name: "(google.api.default_host)".to_string(),
+                    value: ProtobufConstant::String("library.example.com".to_string()),
+                },
+                ProtobufOption {
+                    name: "(google.api.oauth_scopes)".to_string(),
+                    value: ProtobufConstant::String(
+                        "https://www.googleapis.com/auth/cloud-platform".to_string(),
+                    ),
+                },
+            ],
+            service.options
+        );
+
+        let methods = service
+            .methods
+            .iter()
+            .map(|m| {
+                (
+                    &*m.name,
+                    &*m.input_type,
+                    &*m.output_type,
+                    m.client_streaming,
+                    m.server_streaming,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("GetBook", "GetBookRequest", "Book", false, false),
+                ("UploadBooks", "Book", "UploadSummary", true, false),
+                ("ListBooks", "ListBooksRequest", "Book", false, true),
+                ("Chat", ".google.example.Message", "Message", true, true),
+            ],
+            methods
+        );
+        assert_eq!(
+            vec![
+                ProtobufOption {
+                    name: "(google.api.http)".to_string(),
+                    value: ProtobufConstant::Message(vec![(
+                        "get".to_string(),
+                        ProtobufConstant::String("/v1/{name=shelves/*/books/*}".to_string()),
+                    )]),
+                },
+                ProtobufOption {
+                    name: "deprecated".to_string(),
+                    value: ProtobufConstant::Bool(true),
+                },
+            ],
+            service.methods[0].options
+        );
+    }
+
+    #[test]
+    fn test_method_options_nested() {
+        let msg = r#"service S {
+        rpc Create(Req) returns (Resp) {
+            // http binding
+            option (google.api.http) = {
+                post: "/v1/items"
+                body: "*"
+                additional_bindings { put: "/v1/items/{id}" body: "item" }
+            };
+            /* not deprecated */
+            option (my.timeout) = 3;
+        };
+        rpc Get(Req) returns (Resp) { }
+    }"#;
+        let desc = FileDescriptor::parse(msg).unwrap();
+        let methods = &desc.services[0].methods;
+        assert_eq!(2, methods.len());
+        let http = &methods[0].options[0];
+        assert_eq!("(google.api.http)", http.name);
+        assert_eq!(
+            Some(&ProtobufConstant::String("/v1/items".to_string())),
+            http.value.field("post")
+        );
+        let binding = http.value.field("additional_bindings").unwrap();
+        assert_eq!(
+            Some(&ProtobufConstant::String("item".to_string())),
+            binding.field("body")
+        );
+        assert_eq!(
+            ProtobufOption {
+                name: "(my.timeout)".to_string(),
+                value: ProtobufConstant::Int(3),
+            },
+            methods[0].options[1]
+        );
+        assert!(methods[1].options.is_empty());
+    }
+
+    #[test]
+    fn test_several_services() {
+        let msg = r#"
+    service A { rpc A1(Req) returns (Resp); rpc A2(Req) returns (stream Resp) {} }
+    message Req {}
+    service B {}
+    service C {
+        rpc C1(stream Req) returns (Resp) { option deprecated = true; }
+        rpc C2(Req) returns (Resp);
+        rpc C3(Req) returns (Resp) {};
+    }
+    message Resp {}
+    "#;
+        let desc = FileDescriptor::parse(msg.as_bytes()).unwrap();
+        let services = desc
+            .services
+            .iter()
+            .map(|s| {
+                let methods = s.methods.iter().map(|m| &*m.name).collect::<Vec<_>>();
+                (&*s.name, methods)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("A", vec!["A1", "A2"]),
+                ("B", vec![]),
+                ("C", vec!["C1", "C2", "C3"]),
+            ],
+            services
+        );
+        assert_eq!(2, desc.messages.len());
+    }
+
+    #[test]
+    fn test_block_comments() {
+        let msg = r#"message A {
+            optional string a = 1 [default = "/* not a comment"];
+            /* a /* b */ optional int32 b = 2; // */
+            optional string c = 3 [default = "*/"];
+        }"#;
+        let desc = FileDescriptor::parse(msg).unwrap();
+        let fields = &desc.messages[0].fields;
+        assert_eq!(3, fields.len());
+        assert_eq!(Some(r#""/* not a comment""#), fields[0].default.as_deref());
+
+        for msg in &[
+            "message A {}\n  /* unterminated",
+            "message A {\n  /* unterminated }",
+            "message A { optional int32 a = 1; /* closed */\n  /* unterminated",
+        ] {
+            let err = FileDescriptor::parse(msg).unwrap_err();
+            assert_eq!(ParserError::UnterminatedComment, err.error, "{}", msg);
+            assert_eq!((2, 3), (err.line, err.col), "{}", msg);
+        }
+        let msg = "message A {} /*/";
+        let err = FileDescriptor::parse(msg).unwrap_err();
+        assert_eq!(ParserError::UnterminatedComment, err.error);
+        let err = FileDescriptor::iter_top_level(msg).last().unwrap().unwrap_err();
+        assert_eq!(ParserError::UnterminatedComment, err.error);
+    }
+
+    #[test]
+    fn test_lexing_as_tokenizer() {
+        let msg = r#"edition = '2023';
+import 'a.proto';
+message A { reserved 'x', "y"; }"#;
+        let desc = FileDescriptor::parse(msg).unwrap();
+        assert_eq!(Some("2023"), desc.edition.as_deref());
+        assert_eq!("a.proto", desc.imports[0].path);
+        assert_eq!(vec!["x".to_string(), "y".to_string()], desc.messages[0].reserved_names);
+
+        // strings end with their line
+        let err = FileDescriptor::parse("option a = \"b\nc\";\nmessage A {}").unwrap_err();
+        assert_eq!((ParserError::InvalidDeclaration, 1, 1), (err.error, err.line, err.col));
+
+        // a `;` or `}` in a comment does not end a skipped statement
+        let msg = "message A {\n    int32 a = x // ; }\n    ;\n    int32 b = 2;\n}";
+        let (desc, errors) = FileDescriptor::parse_recovering(msg);
+        let locs = errors.iter().map(|e| (e.line, e.col)).collect::<Vec<_>>();
+        assert_eq!(vec![(2, 5)], locs);
+        assert_eq!("b", desc.messages[0].fields[0].name);
+    }
+
+    #[test]
+    fn test_parse_tokens() {
+        // keywords and scalar types are whole tokens, comments can be between any two tokens
+        let msg = "message /* a */ A { int32x /* b */ a = 1; optional/**/int32 b = 2; }";
+        let mess = parse(message, msg).unwrap().1 .0;
+        let fields = mess
+            .fields
+            .iter()
+            .map(|f| (&*f.name, f.rule, &f.typ))
+            .collect::<Vec<_>>();
+        let int32x = FieldType::MessageOrEnum("int32x".to_string());
+        assert_eq!(
+            vec![
+                ("a", Rule::Singular, &int32x),
+                ("b", Rule::Optional, &FieldType::Int32),
+            ],
+            fields
+        );
+
+        // the declarations before an unterminated comment are kept
+        let (desc, errors) = FileDescriptor::parse_recovering("message A {}\n/* a");
+        assert_eq!(1, desc.messages.len());
+        let errors = errors
+            .iter()
+            .map(|e| (&e.error, e.line, e.col))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(&ParserError::UnterminatedComment, 2, 1)], errors);
+    }
+
+    #[test]
+    fn test_comments() {
+        let msg = r#"syntax = "proto3";
+
+    // Detached, followed by a blank line
+
+    // A message
+    /* spanning
+       two lines */
+    message A {
+        // The x
+        int32 x = 1; // trailing x
+        string url = 2 [(my.url) = "http://a"]; /* trailing url */
+
+        int32 y = 3;
+        // Not attached to y
+        message B {} // trailing B
+    }
+    enum E { X = 0; } // trailing E
+    // The service
+    service S {
+        // The method
+        rpc M(A) returns (A); // trailing M
+    }
+    "#;
+        let desc = FileDescriptor::parse(msg.as_bytes()).unwrap();
+        let a = &desc.messages[0];
+        assert_eq!(vec!["A message", "spanning\n       two lines"], a.comments);
+        assert_eq!(None, a.trailing_comment);
+        let fields = a
+            .fields
+            .iter()
+            .map(|f| (&*f.name, f.comments.clone(), f.trailing_comment.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("x", vec!["The x".to_string()], Some("trailing x")),
+                ("url", vec![], Some("trailing url")),
+                ("y", vec![], None),
+            ],
+            fields
+        );
+        assert_eq!(vec!["Not attached to y"], a.messages[0].comments);
+        assert_eq!(Some("trailing B"), a.messages[0].trailing_comment.as_deref());
+        assert!(desc.enums[0].comments.is_empty());
+        assert_eq!(Some("trailing E"), desc.enums[0].trailing_comment.as_deref());
+        let service = &desc.services[0];
+        assert_eq!(vec!["The service"], service.comments);
+        assert_eq!(vec!["The method"], service.methods[0].comments);
+        assert_eq!(Some("trailing M"), service.methods[0].trailing_comment.as_deref());
+        assert!(desc.messages[0].span.is_none());
+    }
+
+    #[test]
+    fn test_import_rewrite() {
+        let msg = r#"import "vendor/x.proto";
+    import public "y.proto";
+    "#;
+        let options = ParserOptions {
+            import_rewrite: Some(Box::new(|path| {
+                path.trim_start_matches("vendor/").to_string()
+            })),
+            ..ParserOptions::default()
+        };
+        let desc = FileDescriptor::parse_with_options(msg, &options).unwrap();
+        assert_eq!(vec!["x.proto", "y.proto"], desc.import_paths());
+    }
+
+    #[test]
+    fn test_default_syntax() {
+        let options = ParserOptions {
+            default_syntax: Syntax::Proto3,
+            ..ParserOptions::default()
+        };
+        let msg = "message A { int32 a = 1; }";
+        let desc = FileDescriptor::parse_with_options(msg, &options).unwrap();
+        assert!(matches!(desc.syntax, Syntax::Proto3));
+        let desc = FileDescriptor::parse(msg).unwrap();
+        assert!(matches!(desc.syntax, Syntax::Proto2));
+
+        let msg = r#"syntax = "proto2"; message A { optional int32 a = 1; }"#;
+        let desc = FileDescriptor::parse_with_options(msg, &options).unwrap();
+        assert!(matches!(desc.syntax, Syntax::Proto2));
+    }
+
+    #[test]
+    fn test_syntax_statement() {
+        let msg = "// comment\nsyntax = 'proto3';\nmessage A { int32 a = 1; }";
+        let desc = FileDescriptor::parse(msg).unwrap();
+        assert_eq!(Syntax::Proto3, desc.syntax);
+        let desc = FileDescriptor::parse(r#"syntax = "proto" "3";"#).unwrap();
+        assert_eq!(Syntax::Proto3, desc.syntax);
+
+        let err = FileDescriptor::parse("syntax = \"proto4\";").unwrap_err();
+        assert_eq!(ParserError::InvalidSyntax("proto4".to_string()), err.error);
+        assert_eq!((1, 1), (err.line, err.col));
+
+        let msg = "message A { int32 a = 1; }\nsyntax = \"proto3\";";
+        let err = FileDescriptor::parse(msg).unwrap_err();
+        assert_eq!(ParserError::MisplacedSyntax, err.error);
+        assert_eq!((2, 1), (err.line, err.col));
+        let (desc, _) = FileDescriptor::parse_recovering(msg);
+        assert_eq!(Syntax::Proto2, desc.syntax);
+
+        let msg = "package a;\nsyntax = \"proto3\";";
+        let items = FileDescriptor::iter_top_level(msg).collect::<Vec<_>>();
+        assert_eq!(Ok(TopLevel::Package("a".to_string())), items[0]);
+        assert_eq!(ParserError::MisplacedSyntax, items[1].clone().unwrap_err().error);
+        assert_eq!(2, items.len());
+
+        // empty and invalid statements are statements too
+        for msg in &[
+            ";syntax = \"proto3\";",
+            "/* a */ ;\nsyntax = \"proto3\";",
+            "x;syntax = \"proto3\";",
+        ] {
+            let (desc, errors) = FileDescriptor::parse_recovering(msg);
+            assert_eq!(Syntax::Proto2, desc.syntax, "{}", msg);
+            assert_eq!(ParserError::MisplacedSyntax, errors.last().unwrap().error, "{}", msg);
+        }
+        let items = FileDescriptor::iter_top_level(";syntax = \"proto3\";").collect::<Vec<_>>();
+        assert_eq!(ParserError::MisplacedSyntax, items[0].clone().unwrap_err().error);
+    }
+
+    #[test]
+    fn test_extension_ranges() {
+        let msg = r#"message Sample {
+            extensions 100 to 199 [
+                declaration = {
+                    number: 100,
+                    full_name: ".my.package.event_annotations",
+                    type: ".logs.proto.ValidationAnnotations",
+                    repeated: true
+                }
+            ];
+            extensions 1000, 2000 to 2010;
+            optional int32 a = 1;
+        }"#;
+
+        let mess = parse(message, msg).unwrap().1 .0;
+        let ranges = mess.extension_ranges.iter().map(|r| r.range.clone()).collect::<Vec<_>>();
+        assert_eq!(vec![100..200, 1000..1001, 2000..2011], ranges);
+        let options = &mess.extension_ranges[0].options;
+        assert_eq!(vec!["declaration"], options.iter().map(|o| &*o.name).collect::<Vec<_>>());
+        assert_eq!(Some(&ProtobufConstant::Int(100)), options[0].value.field("number"));
+        assert!(mess.extension_ranges[1..].iter().all(|r| r.options.is_empty()));
+        assert_eq!(1, mess.fields.len());
+    }
+
+    #[test]
+    fn test_extension_range_options() {
+        let msg = r#"message Sample {
+            extensions 100 to 200 [declaration = { full_name: "]x" }];
+            extensions 300, 400 [
+                declaration = [{number: 300}, {number: 400}],
+                verification = UNVERIFIED
+            ];
+        }"#;
+
+        let mess = parse(message, msg).unwrap().1 .0;
+        let options = mess.extension_ranges
+            .iter()
+            .map(|r| {
+                let names = r.options.iter().map(|o| &*o.name).collect::<Vec<_>>();
+                (r.range.clone(), names)
+            })
+            .collect::<Vec<_>>();
+        let both = vec!["declaration", "declaration", "verification"];
+        assert_eq!(
+            vec![(100..201, vec!["declaration"]), (300..301, both.clone()), (400..401, both)],
+            options
+        );
+        let full_name = mess.extension_ranges[0].options[0].value.field("full_name");
+        assert_eq!(Some(&ProtobufConstant::String("]x".to_string())), full_name);
+        let number = mess.extension_ranges[1].options[1].value.field("number");
+        assert_eq!(Some(&ProtobufConstant::Int(400)), number);
+    }
+
+    #[test]
+    fn test_extension_ranges_max() {
+        let msg = r#"message Sample {
+            extensions 4 to max;
+            reserved 2 to max;
+        }"#;
+
+        let mess = parse(message, msg).unwrap().1 .0;
+        assert_eq!(4..MAX_FIELD_NUMBER + 1, mess.extension_ranges[0].range);
+        assert_eq!(vec![2..MAX_FIELD_NUMBER + 1], mess.reserved_nums);
+        assert_eq!(536_870_911, mess.extension_ranges[0].range.end - 1);
+    }
+
+    #[test]
+    fn test_reserved_and_extensions_statements() {
+        let msg = r#"message Sample {
+            reserved 1, 2 to 3;
+            extensions 100 to 199;
+            optional int32 a = 4;
+            reserved "b", "c";
+            extensions 500, 1000 to max;
+        }"#;
+
+        let mess = parse(message, msg).unwrap().1 .0;
+        assert_eq!(vec![1..2, 2..4], mess.reserved_nums);
+        assert_eq!(vec!["b".to_string(), "c".to_string()], mess.reserved_names);
+        let ranges = mess.extension_ranges.iter().map(|r| r.range.clone()).collect::<Vec<_>>();
+        assert_eq!(vec![100..200, 500..501, 1000..MAX_FIELD_NUMBER + 1], ranges);
+        assert_eq!(1, mess.fields.len());
+    }
+
+    #[test]
+    fn test_message_constant() {
+        let msg = r#"
+            option features = { field_presence: EXPLICIT, enum_type: CLOSED };
+            option (my.opt) = {
+                name: "a" "b"  // concatenated
+                values: [1, -2.5, inf]
+                inner { flag: true; [my.ext]: X }
+                inner: { }
+            };
+            option (my.angle) = { inner < a: 1 > };
+        "#;
+
+        let desc = FileDescriptor::parse(msg).expect("parse");
+        let features = &desc.options[0].value;
+        let ident = |s: &str| ProtobufConstant::Ident(s.to_string());
+        assert_eq!(Some(&ident("EXPLICIT")), features.field("field_presence"));
+        assert_eq!(Some(&ident("CLOSED")), features.field("enum_type"));
+        assert_eq!(None, features.field("message_encoding"));
+
+        let opt = &desc.options[1].value;
+        let inner = ProtobufConstant::Message(vec![
+            ("flag".to_string(), ProtobufConstant::Bool(true)),
+            ("[my.ext]".to_string(), ident("X")),
+        ]);
+        assert_eq!(
+            &ProtobufConstant::Message(vec![
+                ("name".to_string(), ProtobufConstant::String("ab".to_string())),
+                ("values".to_string(), ProtobufConstant::Int(1)),
+                ("values".to_string(), ProtobufConstant::Float(-2.5)),
+                ("values".to_string(), ProtobufConstant::Float(f64::INFINITY)),
+                ("inner".to_string(), inner),
+                ("inner".to_string(), ProtobufConstant::Message(Vec::new())),
+            ]),
+            opt
+        );
+        assert_eq!(
+            concat!(
+                r#"{ name: "ab" values: 1 values: -2.5 values: inf "#,
+                "inner { flag: true [my.ext]: X } inner {} }"
+            ),
+            opt.to_string()
+        );
+
+        // unsupported syntax is kept as written
+        assert_eq!(
+            ProtobufConstant::Aggregate("{ inner < a: 1 > }".to_string()),
+            desc.options[2].value
+        );
+    }
+
+    #[test]
+    fn test_message_constant_nested() {
+        let msg = r#"message A {
+            optional int32 a = 1 [(my.opt) = { a: 1 b: "x" nested { c: true d { e = 2, f: E } } }];
+        }"#;
+
+        let mess = parse(message, msg).unwrap().1 .0;
+        let opt = &mess.fields[0].options[0].value;
+        assert_eq!(Some(&ProtobufConstant::Int(1)), opt.field("a"));
+        assert_eq!(Some(&ProtobufConstant::String("x".to_string())), opt.field("b"));
+        let nested = opt.field("nested").unwrap();
+        assert_eq!(Some(&ProtobufConstant::Bool(true)), nested.field("c"));
+        let d = nested.field("d").unwrap();
+        assert_eq!(
+            &ProtobufConstant::Message(vec![
+                ("e".to_string(), ProtobufConstant::Int(2)),
+                ("f".to_string(), ProtobufConstant::Ident("E".to_string())),
+            ]),
+            d
+        );
+        assert_eq!(None, d.field("a"));
+    }
+
+    #[test]
+    fn test_aggregate_options_ignored() {
+        let msg = r#"message A {
+            option (my.bounds) = { x: -1 y: 3.14 z: -2.5e-3 inner { w: -0x10 } };
+            optional int32 a = 1;
+            enum E {
+                option (my.enum_opt) = { min: -10 max: 1.5 };
+                X = 0;
+            }
+        }"#;
+
+        let mess = parse(message, msg).unwrap().1 .0;
+        assert_eq!(1, mess.fields.len());
+        assert_eq!(1, mess.enums[0].values.len());
+    }
+
+    #[test]
+    fn test_group_nested() {
+        let msg = r#"message SearchResponse {
+            repeated group Result = 1 {
+                required string url = 2;
+                optional group Snippet = 3 {
+                    repeated string text = 4;
+                }
+            }
+        }"#;
+        let mess = parse(message, msg).unwrap().1 .0;
+        let result = &mess.fields[0];
+        assert_eq!((Rule::Repeated, 1), (result.rule, result.number));
+        let fields = match result.typ {
+            FieldType::Group(ref g) => &g.fields,
+            ref t => panic!("expecting group, got {:?}", t),
+        };
+        assert_eq!(2, fields.len());
+        let url = &fields[0];
+        assert_eq!(("url", Rule::Required, 2), (&*url.name, url.rule, url.number));
+        assert_eq!(FieldType::String, fields[0].typ);
+        assert_eq!(("snippet", 3), (&*fields[1].name, fields[1].number));
+        match fields[1].typ {
+            FieldType::Group(ref g) => {
+                assert_eq!("Snippet", g.name);
+                assert_eq!(("text", 4), (&*g.fields[0].name, g.fields[0].number));
+            }
+            ref t => panic!("expecting group, got {:?}", t),
+        }
+    }
+
+    #[test]
+    fn test_group_names() {
+        let msg = r#"message A {
+            optional group MyGroup = 1 {
+                repeated group Inner_Result = 2 {}
+            }
+        }"#;
+        let mess = parse(message, msg).unwrap().1 .0;
+        assert_eq!("mygroup", mess.fields[0].name);
+        match mess.fields[0].typ {
+            FieldType::Group(ref g) => {
+                assert_eq!("MyGroup", g.name);
+                assert_eq!("inner_result", g.fields[0].name);
+            }
+            ref t => panic!("expecting group, got {:?}", t),
+        }
+
+        let msg = r#"message A {
+            optional group lowercase = 1 {}
+        }"#;
+        assert!(FileDescriptor::parse(msg.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_iter_top_level() {
+        let msg = r#"syntax = "proto3";
+package foo;
+import "a.proto";
+option java_package = "foo";
+message A { int32 a = 1; }
+enum E { X = 0; }
+extend A { int32 b = 2; int32 c = 3; }
+service S { rpc M(A) returns (A); }
+message B {}
+"#;
+        let mut counts = (0, 0, 0);
+        for top_level in FileDescriptor::iter_top_level(msg) {
+            match top_level.unwrap() {
+                TopLevel::Message(m) => {
+                    assert_eq!(None, m.span);
+                    counts.0 += 1;
+                }
+                TopLevel::Extensions(e) => counts.1 += e.len(),
+                _ => counts.2 += 1,
+            }
+        }
+        assert_eq!((2, 2, 6), counts);
+
+        let msg = r#"message A {}
+int32 x = 1;
+message B {
+    int32 b = ;
+}
+message C {"#;
+        let items = FileDescriptor::iter_top_level(msg)
+            .map(|t| match t {
+                Ok(TopLevel::Message(m)) => Ok(m.name),
+                Ok(t) => panic!("unexpected {:?}", t),
+                Err(e) => Err((e.error, e.line)),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Ok("A".to_string()),
+                Err((ParserError::InvalidDeclaration, 2)),
+                Err((ParserError::InvalidDeclaration, 4)),
+                Ok("B".to_string()),
+                Err((ParserError::UnexpectedEof, 6)),
+            ],
+            items
+        );
+
+        // comments are attached as when parsing the whole file
+        let msg = "// A\nmessage A {\n  int32 a = 1; // a\n}\n\n/* E */\nenum E { X = 0; } // E\n";
+        let desc = FileDescriptor::parse(msg).unwrap();
+        let items = FileDescriptor::iter_top_level(msg)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            vec![
+                TopLevel::Message(desc.messages[0].clone()),
+                TopLevel::Enum(desc.enums[0].clone()),
+            ],
+            items
+        );
+        assert_eq!(vec!["A"], desc.messages[0].comments);
+        assert_eq!(Some("a"), desc.messages[0].fields[0].trailing_comment.as_deref());
+        assert_eq!(Some("E"), desc.enums[0].trailing_comment.as_deref());
+    }
+
+    #[test]
+    fn test_parse_recovering() {
+        let msg = r#"message A {
+    optional int32 a = 1;
+    optional int32 b = ;
+    oneof c {
+        int32 c1 = 3;
+        int32 c2 = "x; }";
+    }
+    message B {
+        optional int32 d = 4;
+        optional e = 5 6;
+    }
+    optional int32 f = 7;
+}
+
+enum E {
+    X = 0;
+    Y;
+    Z = 2;
+}
+"#;
+        let (desc, errors) = FileDescriptor::parse_recovering(msg);
+        let locs = errors.iter().map(|e| (e.line, e.col)).collect::<Vec<_>>();
+        assert_eq!(vec![(3, 5), (6, 9), (10, 9), (17, 5)], locs);
+
+        let a = &desc.messages[0];
+        assert_eq!(vec!["a", "f"], a.fields.iter().map(|f| &*f.name).collect::<Vec<_>>());
+        let c = a.oneofs[0].fields.iter().map(|f| &*f.name).collect::<Vec<_>>();
+        assert_eq!(vec!["c1"], c);
+        assert_eq!(1, a.messages[0].fields.len());
+        let values = desc.enums[0].values.iter().map(|v| &*v.name).collect::<Vec<_>>();
+        assert_eq!(vec!["X", "Z"], values);
+
+        let err = FileDescriptor::parse(msg).unwrap_err();
+        assert_eq!((3, 5), (err.line, err.col));
+    }
+
+    #[test]
+    fn test_parse_recovering_top_level() {
+        let msg = r#"syntax = "proto2";
+message A { optional int32 a = 1; }
+mesage B { optional int32 b = 2; }
+message C { optional int32 c = 3; }
+optional int32 x = 1;
+}
+enum D { X = 0; }
+"#;
+        let (desc, errors) = FileDescriptor::parse_recovering(msg);
+        let errors = errors
+            .iter()
+            .map(|e| (e.error.clone(), e.line, e.col))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (ParserError::InvalidDeclaration, 3, 1),
+                (ParserError::InvalidDeclaration, 5, 1),
+                (ParserError::InvalidDeclaration, 6, 1),
+            ],
+            errors
+        );
+        let names = desc.messages.iter().map(|m| &*m.name).collect::<Vec<_>>();
+        assert_eq!(vec!["A", "C"], names);
+        assert_eq!("D", desc.enums[0].name);
+
+        let (desc, errors) = FileDescriptor::parse_recovering("message A {}
+message B {");
+        assert_eq!(1, desc.messages.len());
+        assert_eq!(ParserError::UnexpectedEof, errors[0].error);
+        assert_eq!((2, 1), (errors[0].line, errors[0].col));
+    }
+
+    #[test]
+    fn test_oneof_deprecated() {
+        let msg = r#"message A
+    {
+        oneof a {
+            option deprecated = true;
+            string a1 = 1;
+            int32 a2 = 2;
+        }
+        oneof b {
+            option (my.option) = 3;
+            string b1 = 3;
+        }
+    }"#;
+
+        let mess = parse(message, msg).unwrap().1 .0;
+        assert!(mess.oneofs[0].deprecated);
+        assert_eq!(2, mess.oneofs[0].fields.len());
+        assert!(!mess.oneofs[1].deprecated);
+        assert_eq!(1, mess.oneofs[1].fields.len());
+        let options = mess.oneofs.iter().map(|o| o.options.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                vec![ProtobufOption {
+                    name: "deprecated".to_string(),
+                    value: ProtobufConstant::Bool(true),
+                }],
+                vec![ProtobufOption {
+                    name: "(my.option)".to_string(),
+                    value: ProtobufConstant::Int(3),
+                }],
+            ],
+            options
+        );
+    }
+
+    #[test]
+    fn test_oneof_field_options() {
+        let msg = r#"message A {
+        oneof a {
+            string a1 = 1 [deprecated = true, (my.opt) = "x"];
+        }
+    }"#;
+
+        let mess = parse(message, msg).unwrap().1 .0;
+        let a1 = &mess.oneofs[0].fields[0];
+        assert_eq!(Rule::Singular, a1.rule);
+        assert!(a1.deprecated);
+        let names = a1.options.iter().map(|o| &*o.name).collect::<Vec<_>>();
+        assert_eq!(vec!["deprecated", "(my.opt)"], names);
+    }
+
+    #[test]
+    fn test_type_def_in_oneof() {
+        let msg = r#"message A {
+        oneof a {
+            string a1 = 1;
+            enum E { X = 0; }
+            int32 a2 = 2;
+        }
+    }"#;
+
+        let err = FileDescriptor::parse(msg.as_bytes()).unwrap_err();
+        assert_eq!(ParserError::TypeDefInOneof, err.error);
+        assert_eq!((4, 13), (err.line, err.col));
+
+        let (desc, errors) = FileDescriptor::parse_recovering(msg.as_bytes());
+        assert_eq!(1, errors.len());
+        assert_eq!(2, desc.messages[0].oneofs[0].fields.len());
+    }
+
+    #[test]
+    fn test_empty_statements() {
+        let desc = FileDescriptor::parse("message Foo {};").unwrap();
+        assert_eq!("Foo", desc.messages[0].name);
+
+        let desc = FileDescriptor::parse("enum E { A = 0; ; B = 1; }").unwrap();
+        let values = desc.enums[0]
+            .values
+            .iter()
+            .map(|v| (&*v.name, v.number))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![("A", 0), ("B", 1)], values);
+
+        let msg = r#"syntax = "proto2"; ;
+            ;
+            message M {
+                ; optional int32 a = 1; ;
+                message N {};
+                oneof o { ; int32 b = 2; ; };
+            };
+            service S { ; rpc A (M) returns (M) { ; }; ; }"#;
+        let desc = FileDescriptor::parse(msg).unwrap();
+        let m = &desc.messages[0];
+        assert_eq!(vec!["a"], m.fields.iter().map(|f| &*f.name).collect::<Vec<_>>());
+        assert_eq!("b", m.oneofs[0].fields[0].name);
+        assert_eq!("N", m.messages[0].name);
+        assert_eq!("A", desc.services[0].methods[0].name);
+    }
 }
@@ -0,0 +1,1434 @@
+//! Type name resolution, following protobuf scoping rules
+
+use std::collections::{HashMap, HashSet};
+
+use super::{Enumeration, Extension, Field, FieldType, FileDescriptor, ImportKind, Message,
+            ProtobufConstant, Rule, Syntax};
+use dependencies::FileDescriptorWithContext;
+
+/// An error while resolving type names
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ResolveError {
+    /// A field references a type which cannot be found
+    UnknownType {
+        /// Fully qualified name of the message (or group) declaring the field, or package
+        /// scope (e.g. `.pkg`) for extensions and service methods
+        message: String,
+        /// Field (or method) name
+        field: String,
+        /// Referenced type name, as declared
+        type_name: String,
+    },
+    /// Two types would have the same name
+    NameCollision(String),
+}
+
+/// Kind of a type referenced by a `FieldType::MessageOrEnum`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TypeKind {
+    /// A message (or a group)
+    Message,
+    /// An enum
+    Enum,
+}
+
+/// Presence of a field, i.e. how a generator should represent it
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Presence {
+    /// No presence tracking, the zero value is not serialized (`T`)
+    Implicit,
+    /// The field may be set or not (`Option<T>`)
+    Explicit,
+    /// Any number of values, including maps (`Vec<T>`)
+    Repeated,
+    /// The field must be set (`T`)
+    Required,
+}
+
+/// A type declared in a .proto file
+enum Type<'a> {
+    Message(&'a Message),
+    Group(&'a [Field]),
+    Enum,
+}
+
+/// All the types declared in some files, by fully qualified name (e.g. `.pkg.Outer.Inner`)
+pub struct Types<'a> {
+    /// Types and the index of the file declaring them
+    types: HashMap<String, (Type<'a>, usize)>,
+    /// Packages and their parents (e.g. `.foo.bar` and `.foo`)
+    packages: HashSet<String>,
+}
+
+impl<'a> Types<'a> {
+    pub fn new(desc: &'a FileDescriptor) -> Types<'a> {
+        Types::with_files(&[desc])
+    }
+
+    fn with_files(files: &[&'a FileDescriptor]) -> Types<'a> {
+        let mut types = Types {
+            types: HashMap::new(),
+            packages: HashSet::new(),
+        };
+        for (i, desc) in files.iter().enumerate() {
+            types.add_file(i, desc);
+        }
+        types
+    }
+
+    fn add_file(&mut self, file: usize, desc: &'a FileDescriptor) {
+        let scope = package_scope(&desc.package);
+        let mut package = &*scope;
+        while !package.is_empty() {
+            self.packages.insert(package.to_string());
+            package = parent(package);
+        }
+        for m in &desc.messages {
+            self.add_message(file, &scope, m);
+        }
+        for e in &desc.enums {
+            self.insert(format!("{}.{}", scope, e.name), Type::Enum, file);
+        }
+    }
+
+    /// Adds a type, unless a type with the same name has already been added
+    fn insert(&mut self, name: String, typ: Type<'a>, file: usize) {
+        self.types.entry(name).or_insert((typ, file));
+    }
+
+    fn add_message(&mut self, file: usize, scope: &str, msg: &'a Message) {
+        let name = format!("{}.{}", scope, msg.name);
+        for m in &msg.messages {
+            self.add_message(file, &name, m);
+        }
+        for e in &msg.enums {
+            self.insert(format!("{}.{}", name, e.name), Type::Enum, file);
+        }
+        self.add_groups(file, &name, &msg.fields);
+        for o in &msg.oneofs {
+            self.add_groups(file, &name, &o.fields);
+        }
+        self.insert(name, Type::Message(msg), file);
+    }
+
+    fn add_groups(&mut self, file: usize, scope: &str, fields: &'a [Field]) {
+        for f in fields {
+            if let FieldType::Group(ref g) = f.typ {
+                let name = format!("{}.{}", scope, g.name);
+                self.add_groups(file, &name, &g.fields);
+                self.insert(name, Type::Group(&g.fields), file);
+            }
+        }
+    }
+
+    /// Fields of a message or group, including `OneOf` fields
+    fn fields(&self, name: &str) -> Vec<&'a Field> {
+        match self.types.get(name) {
+            Some(&(Type::Message(m), _)) => m.fields
+                .iter()
+                .chain(m.oneofs.iter().flat_map(|o| o.fields.iter()))
+                .collect(),
+            Some(&(Type::Group(fields), _)) => fields.iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Finds the fully qualified name of the type `name` referenced from `scope`
+    ///
+    /// The first component of `name` is searched from the innermost scope outward, the
+    /// remaining components must then be nested in it.
+    pub fn resolve(&self, scope: &str, name: &str) -> Option<String> {
+        if name.starts_with('.') {
+            return if self.types.contains_key(name) {
+                Some(name.to_string())
+            } else {
+                None
+            };
+        }
+        let first = name.split('.').next().unwrap_or(name);
+        let mut scope = scope;
+        loop {
+            let full = format!("{}.{}", scope, name);
+            if self.types.contains_key(&full) {
+                return Some(full);
+            }
+            let first = format!("{}.{}", scope, first);
+            if self.types.contains_key(&first) || self.packages.contains(&first) {
+                // protoc does not search further once the first component is found
+                return None;
+            }
+            if scope.is_empty() {
+                return None;
+            }
+            scope = parent(scope);
+        }
+    }
+
+    /// Kind of the type with the fully qualified name `name`
+    pub fn kind(&self, name: &str) -> Option<TypeKind> {
+        match self.types.get(name) {
+            Some(&(Type::Message(_), _)) | Some(&(Type::Group(_), _)) => Some(TypeKind::Message),
+            Some(&(Type::Enum, _)) => Some(TypeKind::Enum),
+            None => None,
+        }
+    }
+
+    /// Fully qualified names of the messages and groups referenced by the fields of `name`
+    fn references(&self, name: &str) -> Vec<(&'a Field, String)> {
+        self.fields(name)
+            .into_iter()
+            .filter_map(|f| {
+                let target = match f.typ {
+                    FieldType::MessageOrEnum(ref t) => self.resolve(name, t),
+                    FieldType::Map(ref m) => match m.value {
+                        FieldType::MessageOrEnum(ref t) => self.resolve(name, t),
+                        _ => None,
+                    },
+                    FieldType::Group(ref g) => Some(format!("{}.{}", name, g.name)),
+                    _ => None,
+                };
+                target.map(|t| (f, t))
+            })
+            .collect()
+    }
+
+    /// Is there a path of field references from type `from` to type `to`
+    fn reaches(&self, from: &str, to: &str) -> bool {
+        let mut visited = HashSet::new();
+        let mut stack = vec![from.to_string()];
+        while let Some(name) = stack.pop() {
+            if name == to {
+                return true;
+            }
+            if visited.insert(name.clone()) {
+                stack.extend(self.references(&name).into_iter().map(|(_, t)| t));
+            }
+        }
+        false
+    }
+}
+
+pub fn package_scope(package: &str) -> String {
+    if package.is_empty() {
+        String::new()
+    } else {
+        format!(".{}", package)
+    }
+}
+
+fn parent(name: &str) -> &str {
+    &name[..name.rfind('.').unwrap_or(0)]
+}
+
+/// Shortest name referencing the fully qualified type `fqn` (e.g. `.pkg.Outer.Inner`) from
+/// `current_scope` (see `FileDescriptor::resolve_kind`)
+///
+/// A shorter name is only returned if its first component is not shadowed by one of the
+/// enclosing scopes of `current_scope`, else `fqn` is returned as is. Types declared in these
+/// scopes are not known, the result is only unambiguous if none of them is named like the
+/// first component.
+pub fn relative_type_name(fqn: &str, current_scope: &str) -> String {
+    let name = fqn.split('.').filter(|c| !c.is_empty()).collect::<Vec<_>>();
+    let scope = current_scope
+        .split('.')
+        .filter(|c| !c.is_empty())
+        .collect::<Vec<_>>();
+    if name.is_empty() {
+        return fqn.to_string();
+    }
+    let common = name.iter().zip(&scope).take_while(|&(n, s)| n == s).count();
+    for i in (0..common.min(name.len() - 1) + 1).rev() {
+        // searching outward from `current_scope`, the first component must not be found
+        // before reaching the common prefix
+        if scope.iter().skip(i + 1).all(|s| *s != name[i]) {
+            return name[i..].join(".");
+        }
+    }
+    fqn.to_string()
+}
+
+impl FileDescriptor {
+    /// Resolves the type `name` referenced from `scope`, returning whether it is a message or
+    /// an enum
+    ///
+    /// `scope` is the fully qualified name of the message declaring the field (e.g.
+    /// `.pkg.Outer`), or the package scope (e.g. `.pkg`) for extensions. Only types declared in
+    /// this file are considered.
+    pub fn resolve_kind(&self, scope: &str, name: &str) -> Option<TypeKind> {
+        let types = Types::new(self);
+        types.kind(&types.resolve(scope, name)?)
+    }
+
+    /// Fully qualified name of `msg` (e.g. `.pkg.Outer.Inner`), if `msg` is a reference to a
+    /// message, nested or not, of this file
+    pub fn qualified_name_of(&self, msg: &Message) -> Option<String> {
+        fn in_message(scope: &str, m: &Message, msg: &Message) -> Option<String> {
+            let name = format!("{}.{}", scope, m.name);
+            if ::std::ptr::eq(m, msg) {
+                return Some(name);
+            }
+            m.messages.iter().filter_map(|n| in_message(&name, n, msg)).next()
+        }
+
+        let scope = package_scope(&self.package);
+        self.messages
+            .iter()
+            .filter_map(|m| in_message(&scope, m, msg))
+            .next()
+    }
+
+    /// Fully qualified name of `enumeration` (e.g. `.pkg.Outer.Enum`), if `enumeration` is a
+    /// reference to an enum, nested or not, of this file
+    pub fn qualified_enum_name_of(&self, enumeration: &Enumeration) -> Option<String> {
+        fn in_enums(scope: &str, enums: &[Enumeration], e: &Enumeration) -> Option<String> {
+            enums
+                .iter()
+                .find(|en| ::std::ptr::eq(*en, e))
+                .map(|en| format!("{}.{}", scope, en.name))
+        }
+        fn in_message(scope: &str, m: &Message, e: &Enumeration) -> Option<String> {
+            let scope = format!("{}.{}", scope, m.name);
+            in_enums(&scope, &m.enums, e)
+                .or_else(|| m.messages.iter().filter_map(|n| in_message(&scope, n, e)).next())
+        }
+
+        let scope = package_scope(&self.package);
+        in_enums(&scope, &self.enums, enumeration).or_else(|| {
+            self.messages
+                .iter()
+                .filter_map(|m| in_message(&scope, m, enumeration))
+                .next()
+        })
+    }
+
+    /// Fields whose type refers back, directly or not, to the message containing them
+    ///
+    /// Returns the fully qualified name of the containing message (or group) and the field
+    /// name, in declaration order. Generators typically need to `Box` the singular ones.
+    ///
+    /// Only types declared in this file are considered.
+    pub fn recursive_fields(&self) -> Vec<(String, String)> {
+        let types = Types::new(self);
+        let mut names = Vec::new();
+        let scope = package_scope(&self.package);
+        for m in &self.messages {
+            collect_names(&scope, m, &mut names);
+        }
+        let mut recursive = Vec::new();
+        for name in names {
+            for (field, target) in types.references(&name) {
+                if types.reaches(&target, &name) {
+                    recursive.push((name.clone(), field.name.clone()));
+                }
+            }
+        }
+        recursive
+    }
+}
+
+/// Calls `f` with the scope, the field name and the referenced type name of every field
+/// referencing a message or an enum
+fn walk_fields<F>(scope: &str, fields: &mut [Field], f: &mut F) -> Result<(), ResolveError>
+where
+    F: FnMut(&str, &str, &mut String) -> Result<(), ResolveError>,
+{
+    for field in fields {
+        match field.typ {
+            FieldType::MessageOrEnum(ref mut name) => f(scope, &field.name, name)?,
+            FieldType::Map(ref mut map) => {
+                if let FieldType::MessageOrEnum(ref mut name) = map.value {
+                    f(scope, &field.name, name)?;
+                }
+            }
+            FieldType::Group(ref mut g) => {
+                walk_fields(&format!("{}.{}", scope, g.name), &mut g.fields, f)?
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// `walk_fields` over all the fields of `msg` declared in `scope`, nested messages included
+fn walk_message<F>(scope: &str, msg: &mut Message, f: &mut F) -> Result<(), ResolveError>
+where
+    F: FnMut(&str, &str, &mut String) -> Result<(), ResolveError>,
+{
+    let name = format!("{}.{}", scope, msg.name);
+    walk_fields(&name, &mut msg.fields, f)?;
+    for o in &mut msg.oneofs {
+        walk_fields(&name, &mut o.fields, f)?;
+    }
+    for e in &mut msg.extensions {
+        walk_fields(&name, ::std::slice::from_mut(&mut e.field), f)?;
+    }
+    for m in &mut msg.messages {
+        walk_message(&name, m, f)?;
+    }
+    Ok(())
+}
+
+/// `walk_message` over all messages and extensions of `desc`, then over the method types of
+/// all services (the method being the field)
+fn walk_file<F>(desc: &mut FileDescriptor, f: &mut F) -> Result<(), ResolveError>
+where
+    F: FnMut(&str, &str, &mut String) -> Result<(), ResolveError>,
+{
+    let scope = package_scope(&desc.package);
+    for m in &mut desc.messages {
+        walk_message(&scope, m, f)?;
+    }
+    for e in &mut desc.extensions {
+        walk_fields(&scope, ::std::slice::from_mut(&mut e.field), f)?;
+    }
+    for s in &mut desc.services {
+        for m in &mut s.methods {
+            f(&scope, &m.name, &mut m.input_type)?;
+            f(&scope, &m.name, &mut m.output_type)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sets `Field::type_kind` for `fields` declared in `scope`, group fields included
+fn set_field_kinds(types: &Types, scope: &str, fields: &mut [Field]) {
+    for f in fields {
+        f.type_kind = match *f.element_type() {
+            FieldType::MessageOrEnum(ref name) => types
+                .resolve(scope, name)
+                .and_then(|full| types.kind(&full)),
+            _ => None,
+        };
+        if let FieldType::Group(ref mut g) = f.typ {
+            set_field_kinds(types, &format!("{}.{}", scope, g.name), &mut g.fields);
+        }
+    }
+}
+
+/// `set_field_kinds` over all the fields of `msg` declared in `scope`, nested messages included
+fn set_message_kinds(types: &Types, scope: &str, msg: &mut Message) {
+    let name = format!("{}.{}", scope, msg.name);
+    set_field_kinds(types, &name, &mut msg.fields);
+    for o in &mut msg.oneofs {
+        set_field_kinds(types, &name, &mut o.fields);
+    }
+    for e in &mut msg.extensions {
+        set_field_kinds(types, &name, ::std::slice::from_mut(&mut e.field));
+    }
+    for m in &mut msg.messages {
+        set_message_kinds(types, &name, m);
+    }
+}
+
+/// Sets `Field::type_kind` for all the fields of `desc`, the types being searched in `types`
+fn set_file_kinds(types: &Types, desc: &mut FileDescriptor) {
+    let scope = package_scope(&desc.package);
+    for m in &mut desc.messages {
+        set_message_kinds(types, &scope, m);
+    }
+    for e in &mut desc.extensions {
+        set_field_kinds(types, &scope, ::std::slice::from_mut(&mut e.field));
+    }
+}
+
+/// Sets `Field::type_kind` for all the fields of `desc`, from the types declared in `desc`
+pub fn resolve_type_kinds(desc: &mut FileDescriptor) {
+    let file = desc.clone();
+    set_file_kinds(&Types::new(&file), desc);
+}
+
+impl Field {
+    /// Presence of the field, for a field of `file`
+    ///
+    /// | Field                                    | proto2     | proto3     |
+    /// |------------------------------------------|------------|------------|
+    /// | `repeated` or map                        | `Repeated` | `Repeated` |
+    /// | `required`                               | `Required` | -          |
+    /// | `optional`                               | `Explicit` | `Explicit` |
+    /// | no label, oneof member                   | `Explicit` | `Explicit` |
+    /// | no label, message or group type          | `Explicit` | `Explicit` |
+    /// | no label, scalar or enum type            | `Explicit` | `Implicit` |
+    ///
+    /// In editions files, fields without label behave as in proto2 by default. The
+    /// `field_presence` feature (`features.field_presence = ...` or `features = { ... }`
+    /// option) of the field, or else of the file, can change it:
+    /// `IMPLICIT` behaves as in proto3, `LEGACY_REQUIRED` gives `Required`.
+    ///
+    /// Oneof members and enum types are told by `Field::in_oneof` and `Field::type_kind`,
+    /// types of unknown kind are considered messages.
+    pub fn presence(&self, file: &FileDescriptor) -> Presence {
+        if self.rule == Rule::Repeated {
+            return Presence::Repeated;
+        }
+        match (self.rule, &self.typ) {
+            (Rule::Required, _) => return Presence::Required,
+            (Rule::Optional, _) => return Presence::Explicit,
+            (_, &FieldType::Map(_)) => return Presence::Repeated,
+            (_, &FieldType::Group(_)) => return Presence::Explicit,
+            _ if self.in_oneof => return Presence::Explicit,
+            _ => match self.default_presence(file) {
+                Presence::Implicit => (),
+                presence => return presence,
+            },
+        };
+        match self.typ {
+            FieldType::MessageOrEnum(_) if self.type_kind != Some(TypeKind::Enum) => {
+                Presence::Explicit
+            }
+            _ => Presence::Implicit,
+        }
+    }
+
+    /// Whether a single value of the field tracks being set, for a field of a `syntax` file
+    ///
+    /// Repeated fields and maps have no presence. Otherwise all proto2 fields have presence,
+    /// while proto3 fields only have it if they are `optional`, oneof members (see
+    /// `Field::in_oneof`) or of a message or group type: types of unknown kind (see
+    /// `Field::type_kind`) are considered messages. Use `Field::presence` for editions files.
+    pub fn has_presence(&self, syntax: Syntax) -> bool {
+        match (self.rule, &self.typ) {
+            (Rule::Repeated, _) | (_, &FieldType::Map(_)) => false,
+            (Rule::Required, _) | (Rule::Optional, _) | (_, &FieldType::Group(_)) => true,
+            _ if self.in_oneof || syntax == Syntax::Proto2 => true,
+            (_, &FieldType::MessageOrEnum(_)) => self.type_kind != Some(TypeKind::Enum),
+            _ => false,
+        }
+    }
+
+    /// Does this field use the packed encoding, for a field of a `syntax` file
+    ///
+    /// Only packable fields (see `Field::is_packable`, enums being told by `Field::type_kind`)
+    /// can be packed. The explicit `packed` option wins, otherwise they are packed by default
+    /// in proto3 and not in proto2. The `repeated_field_encoding` feature of editions files is
+    /// not followed.
+    pub fn is_packed(&self, syntax: Syntax) -> bool {
+        if !self.is_packable(|_| self.type_kind) {
+            return false;
+        }
+        self.packed.unwrap_or(syntax == Syntax::Proto3)
+    }
+
+    /// Value of the edition feature `name`, set on the field or else on the file
+    ///
+    /// Both `features.<name> = ...` and `features = { ... }` options are looked up, the last
+    /// one wins.
+    fn feature<'a>(
+        &'a self,
+        file: &'a FileDescriptor,
+        name: &str,
+    ) -> Option<&'a ProtobufConstant> {
+        self.options
+            .iter()
+            .rev()
+            .chain(file.options.iter().rev())
+            .filter_map(|o| match o.name.strip_prefix("features") {
+                Some("") => o.value.field(name),
+                Some(n) if n.strip_prefix('.') == Some(name) => Some(&o.value),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Presence of the scalar fields without label, following the file syntax or edition
+    /// features
+    fn default_presence(&self, file: &FileDescriptor) -> Presence {
+        if file.edition.is_none() {
+            return match file.syntax {
+                Syntax::Proto2 => Presence::Explicit,
+                Syntax::Proto3 => Presence::Implicit,
+            };
+        }
+        match self.feature(file, "field_presence") {
+            Some(ProtobufConstant::Ident(v)) => match &**v {
+                "IMPLICIT" => Presence::Implicit,
+                "LEGACY_REQUIRED" => Presence::Required,
+                _ => Presence::Explicit,
+            },
+            _ => Presence::Explicit,
+        }
+    }
+}
+
+/// A resolver replacing type names by their fully qualified name
+///
+/// Calls `found` with the fully qualified names.
+fn resolver<'a, F>(
+    types: &'a Types,
+    mut found: F,
+) -> impl FnMut(&str, &str, &mut String) -> Result<(), ResolveError> + 'a
+where
+    F: FnMut(&str) + 'a,
+{
+    move |scope, field, name| match types.resolve(scope, name) {
+        Some(full) => {
+            found(&full);
+            *name = full;
+            Ok(())
+        }
+        None => Err(ResolveError::UnknownType {
+            message: scope.to_string(),
+            field: field.to_string(),
+            type_name: name.clone(),
+        }),
+    }
+}
+
+impl FileDescriptor {
+    /// Replaces all type references by their fully qualified name (e.g. `.pkg.Outer.Inner`)
+    ///
+    /// Types are searched in this file and in `imports`, following protobuf scoping rules.
+    /// Field types, map values and method input and output types are resolved, extendees are
+    /// kept as declared. `Field::type_kind` is set for the imported types too.
+    ///
+    /// A type which cannot be found returns a `ResolveError::UnknownType`, the file is then left
+    /// unchanged.
+    pub fn resolve_types(&mut self, imports: &[FileDescriptor]) -> Result<(), ResolveError> {
+        let mut desc = self.clone();
+        {
+            let files = Some(&*self).into_iter().chain(imports).collect::<Vec<_>>();
+            let types = Types::with_files(&files);
+            walk_file(&mut desc, &mut resolver(&types, |_| ()))?;
+            set_file_kinds(&types, &mut desc);
+        }
+        *self = desc;
+        Ok(())
+    }
+
+    /// Paths of the imports of this file which are used, in import order
+    ///
+    /// `imports` are the imported files, along with the paths they are imported with. An
+    /// import is used if it declares, or publicly imports (`import public`) a file declaring:
+    /// - a type referenced by a field or a method
+    /// - an extendee of an extension of this file
+    /// - a custom option used in this file, searched from the package scope
+    ///
+    /// Types which cannot be found are ignored.
+    pub fn used_imports(&self, imports: &[FileDescriptorWithContext]) -> Vec<&str> {
+        let files = Some(self)
+            .into_iter()
+            .chain(imports.iter().map(|i| &i.descriptor))
+            .collect::<Vec<_>>();
+        let types = Types::with_files(&files);
+        let file_of = |scope: &str, name: &str| {
+            types
+                .resolve(scope, name)
+                .and_then(|t| types.types.get(&t).map(|&(_, file)| file))
+        };
+        // indexes in `files` of the files declaring used types
+        let mut used = HashSet::new();
+        // cannot fail, unknown types are ignored
+        let _ = walk_file(&mut self.clone(), &mut |scope: &str, _: &str, name: &mut String| {
+            used.extend(file_of(scope, name));
+            Ok(())
+        });
+
+        for (scope, e) in scoped_extensions(self) {
+            used.extend(file_of(&scope, &e.extendee));
+        }
+
+        // fully qualified names the custom options may refer to
+        let scope = package_scope(&self.package);
+        let options = self.custom_option_names()
+            .into_iter()
+            .flat_map(|o| {
+                if o.starts_with('.') {
+                    return vec![o];
+                }
+                let mut names = Vec::new();
+                let mut scope = &*scope;
+                loop {
+                    names.push(format!("{}.{}", scope, o));
+                    if scope.is_empty() {
+                        return names;
+                    }
+                    scope = parent(scope);
+                }
+            })
+            .collect::<HashSet<_>>();
+        for (i, file) in files.iter().enumerate().skip(1) {
+            let mut extensions = scoped_extensions(file).into_iter();
+            let name = |(scope, e): (String, &Extension)| format!("{}.{}", scope, e.field.name);
+            if extensions.any(|e| options.contains(&name(e))) {
+                used.insert(i);
+            }
+        }
+
+        // whether the file imported as `path` is used or publicly imports a used file
+        fn is_used(
+            path: &str,
+            imports: &[FileDescriptorWithContext],
+            used: &HashSet<usize>,
+            visited: &mut HashSet<String>,
+        ) -> bool {
+            if !visited.insert(path.to_string()) {
+                return false;
+            }
+            let i = match imports.iter().position(|i| i.path == path) {
+                Some(i) => i,
+                None => return false,
+            };
+            used.contains(&(i + 1))
+                || imports[i]
+                    .descriptor
+                    .imports
+                    .iter()
+                    .filter(|i| i.kind == ImportKind::Public)
+                    .any(|i| is_used(&i.path, imports, used, visited))
+        }
+        self.imports
+            .iter()
+            .filter(|i| is_used(&i.path, imports, &used, &mut HashSet::new()))
+            .map(|i| &*i.path)
+            .collect()
+    }
+
+    /// Returns a copy of this file containing the types it uses from `imports`
+    ///
+    /// This is a flattening for tools which cannot handle several files, it changes the
+    /// semantics of the file:
+    /// - the imported top level messages and enums containing the referenced types are copied
+    ///   (along with the types they use themselves) as top level declarations
+    /// - copied types from another package are renamed by prefixing their name with their
+    ///   package, dots being replaced by underscores (`google.protobuf.Timestamp` becomes
+    ///   `google_protobuf_Timestamp`)
+    /// - all type references are replaced by their fully qualified name (e.g. `.pkg.Foo`)
+    /// - imports are removed, extendees are kept as declared
+    ///
+    /// A copied type whose name is already used at the top level of this file returns a
+    /// `ResolveError::NameCollision`, a type which cannot be found in this file nor in
+    /// `imports` a `ResolveError::UnknownType`.
+    pub fn with_resolved_imports(
+        &self,
+        imports: &[FileDescriptor],
+    ) -> Result<FileDescriptor, ResolveError> {
+        let files = Some(self).into_iter().chain(imports).collect::<Vec<_>>();
+        let types = Types::with_files(&files);
+
+        let mut desc = self.clone();
+        desc.imports.clear();
+        let mut used = Vec::new();
+        walk_file(&mut desc, &mut resolver(&types, |t| used.push(t.to_string())))?;
+
+        // copy the imported top level types, each of them may use other imported types
+        let mut names = desc.messages
+            .iter()
+            .map(|m| m.name.clone())
+            .chain(desc.enums.iter().map(|e| e.name.clone()))
+            .collect::<HashSet<_>>();
+        let mut renames = Vec::new();
+        let mut copied = HashSet::new();
+        while let Some(full) = used.pop() {
+            let file = match types.types.get(&full) {
+                Some(&(_, file)) if file > 0 => files[file],
+                _ => continue,
+            };
+            let scope = package_scope(&file.package);
+            let top = full[scope.len() + 1..].split('.').next().unwrap_or("");
+            if !copied.insert(format!("{}.{}", scope, top)) {
+                continue;
+            }
+            let name = if file.package == self.package {
+                top.to_string()
+            } else {
+                format!("{}_{}", file.package.replace('.', "_"), top)
+            };
+            if !names.insert(name.clone()) {
+                return Err(ResolveError::NameCollision(name));
+            }
+            let mut resolve = resolver(&types, |t| used.push(t.to_string()));
+            if let Some(m) = file.messages.iter().find(|m| m.name == top) {
+                let mut m = m.clone();
+                walk_message(&scope, &mut m, &mut resolve)?;
+                m.name = name.clone();
+                desc.messages.push(m);
+            } else if let Some(e) = file.enums.iter().find(|e| e.name == top) {
+                let mut e = e.clone();
+                e.name = name.clone();
+                desc.enums.push(e);
+            }
+            let new_scope = package_scope(&self.package);
+            renames.push((
+                format!("{}.{}", scope, top),
+                format!("{}.{}", new_scope, name),
+            ));
+        }
+
+        walk_file(&mut desc, &mut |_: &str, _: &str, name: &mut String| {
+            for (from, to) in &renames {
+                if name == from || name.starts_with(&format!("{}.", from)) {
+                    *name = format!("{}{}", to, &name[from.len()..]);
+                    break;
+                }
+            }
+            Ok(())
+        })?;
+        resolve_type_kinds(&mut desc);
+        Ok(desc)
+    }
+}
+
+/// The extensions declared in `desc`, at top level or in messages, along with their scope
+fn scoped_extensions(desc: &FileDescriptor) -> Vec<(String, &Extension)> {
+    let scope = package_scope(&desc.package);
+    let mut extensions = desc.extensions
+        .iter()
+        .map(|e| (scope.clone(), e))
+        .collect::<Vec<_>>();
+    let mut messages = desc.messages
+        .iter()
+        .map(|m| (scope.clone(), m))
+        .collect::<Vec<_>>();
+    while let Some((scope, m)) = messages.pop() {
+        let scope = format!("{}.{}", scope, m.name);
+        extensions.extend(m.extensions.iter().map(|e| (scope.clone(), e)));
+        messages.extend(m.messages.iter().map(|n| (scope.clone(), n)));
+    }
+    extensions
+}
+
+/// Fully qualified names of `msg`, its groups and its nested messages, depth first
+fn collect_names(scope: &str, msg: &Message, names: &mut Vec<String>) {
+    let name = format!("{}.{}", scope, msg.name);
+    names.push(name.clone());
+    let fields = msg.fields
+        .iter()
+        .chain(msg.oneofs.iter().flat_map(|o| o.fields.iter()));
+    collect_group_names(&name, fields, names);
+    for m in &msg.messages {
+        collect_names(&name, m, names);
+    }
+}
+
+fn collect_group_names<'a, I>(scope: &str, fields: I, names: &mut Vec<String>)
+where
+    I: Iterator<Item = &'a Field>,
+{
+    for f in fields {
+        if let FieldType::Group(ref g) = f.typ {
+            let name = format!("{}.{}", scope, g.name);
+            names.push(name.clone());
+            collect_group_names(&name, g.fields.iter(), names);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn recursive_fields(proto: &str) -> Vec<(String, String)> {
+        FileDescriptor::parse(proto.as_bytes())
+            .expect("parse")
+            .recursive_fields()
+    }
+
+    fn pairs(v: &[(&str, &str)]) -> Vec<(String, String)> {
+        v.iter()
+            .map(|&(m, f)| (m.to_string(), f.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_direct_recursion() {
+        let proto = r#"
+            package tree;
+            message Tree {
+                repeated Tree children = 1;
+                optional int32 value = 2;
+                map<string, Tree> named = 3;
+            }
+        "#;
+        assert_eq!(
+            pairs(&[(".tree.Tree", "children"), (".tree.Tree", "named")]),
+            recursive_fields(proto)
+        );
+    }
+
+    #[test]
+    fn test_mutual_recursion() {
+        let proto = r#"
+            message A {
+                optional B b = 1;
+                optional C c = 2;
+            }
+            message B {
+                oneof x {
+                    A a = 1;
+                    int32 i = 2;
+                }
+            }
+            message C {
+                optional B b = 1;
+            }
+            message D {
+                optional A a = 1;
+            }
+        "#;
+        assert_eq!(
+            pairs(&[(".A", "b"), (".A", "c"), (".B", "a"), (".C", "b")]),
+            recursive_fields(proto)
+        );
+    }
+
+    #[test]
+    fn test_nested_recursion() {
+        let proto = r#"
+            package foo.bar;
+            message Outer {
+                message Inner {
+                    optional Outer outer = 1;
+                    optional .foo.bar.Outer.Inner inner = 2;
+                }
+                optional Inner inner = 1;
+                optional foo.bar.Other other = 2;
+            }
+            message Other {
+                optional Outer.Inner inner = 1;
+            }
+        "#;
+        assert_eq!(
+            pairs(&[
+                (".foo.bar.Outer", "inner"),
+                (".foo.bar.Outer", "other"),
+                (".foo.bar.Outer.Inner", "outer"),
+                (".foo.bar.Outer.Inner", "inner"),
+                (".foo.bar.Other", "inner"),
+            ]),
+            recursive_fields(proto)
+        );
+    }
+
+    #[test]
+    fn test_with_resolved_imports() {
+        let b = r#"
+            package b;
+            message B {
+                message Nested {}
+                optional C c = 1;
+                optional E e = 2;
+            }
+            message C {}
+            enum E { X = 0; }
+            message Unused {}
+        "#;
+        let a = r#"
+            package a;
+            import "b.proto";
+            message A {
+                optional b.B b = 1;
+                optional .b.B.Nested n = 2;
+                repeated A a = 3;
+            }
+        "#;
+        let b = FileDescriptor::parse(b.as_bytes()).unwrap();
+        let a = FileDescriptor::parse(a.as_bytes()).unwrap();
+
+        let flat = a.with_resolved_imports(&[b]).unwrap();
+        assert!(flat.imports.is_empty());
+        let names = flat.messages.iter().map(|m| &*m.name).collect::<Vec<_>>();
+        assert_eq!(vec!["A", "b_B", "b_C"], names);
+        assert_eq!(vec!["b_E"], flat.enums.iter().map(|e| &*e.name).collect::<Vec<_>>());
+        let types = |m: &Message| {
+            m.fields
+                .iter()
+                .map(|f| match f.typ {
+                    FieldType::MessageOrEnum(ref t) => t.clone(),
+                    ref t => panic!("unexpected type {:?}", t),
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![".a.b_B", ".a.b_B.Nested", ".a.A"], types(&flat.messages[0]));
+        assert_eq!(vec![".a.b_C", ".a.b_E"], types(&flat.messages[1]));
+    }
+
+    #[test]
+    fn test_with_resolved_imports_errors() {
+        let b = FileDescriptor::parse("package b; message A {}".as_bytes()).unwrap();
+        let a = "package a; message A { optional b.A a = 1; }";
+        let a = FileDescriptor::parse(a.as_bytes()).unwrap();
+        assert_eq!(2, a.with_resolved_imports(&[b]).unwrap().messages.len());
+
+        let b = FileDescriptor::parse("package a; message A {}".as_bytes()).unwrap();
+        let a = "package a; message B { optional A a = 1; } message A {}";
+        let a = FileDescriptor::parse(a.as_bytes()).unwrap();
+        assert_eq!(2, a.with_resolved_imports(&[b]).unwrap().messages.len());
+
+        let b = FileDescriptor::parse("package b; message B {}".as_bytes()).unwrap();
+        let a = "package a; message b_B {} message A { optional b.B b = 1; }";
+        let a = FileDescriptor::parse(a.as_bytes()).unwrap();
+        assert_eq!(
+            ResolveError::NameCollision("b_B".to_string()),
+            a.with_resolved_imports(&[b]).unwrap_err()
+        );
+
+        let a = "package a; message A { optional Missing m = 1; }";
+        let a = FileDescriptor::parse(a.as_bytes()).unwrap();
+        assert_eq!(
+            ResolveError::UnknownType {
+                message: ".a.A".to_string(),
+                field: "m".to_string(),
+                type_name: "Missing".to_string(),
+            },
+            a.with_resolved_imports(&[]).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_resolve_absolute() {
+        let proto = r#"
+            package foo;
+            message Bar {}
+            message A {
+                message Bar {}
+                optional Bar relative = 1;
+                optional .foo.Bar absolute = 2;
+                optional .Bar missing = 3;
+            }
+        "#;
+        let desc = FileDescriptor::parse(proto).unwrap();
+        let types = desc.messages[1]
+            .fields
+            .iter()
+            .map(|f| f.typ.clone())
+            .collect::<Vec<_>>();
+        let typ = |n: &str| FieldType::MessageOrEnum(n.to_string());
+        assert_eq!(vec![typ("Bar"), typ(".foo.Bar"), typ(".Bar")], types);
+
+        assert_eq!(Some(TypeKind::Message), desc.resolve_kind(".foo.A", ".foo.Bar"));
+        assert_eq!(None, desc.resolve_kind(".foo.A", ".Bar"));
+
+        let err = desc.clone().resolve_types(&[]).unwrap_err();
+        assert_eq!(
+            ResolveError::UnknownType {
+                message: ".foo.A".to_string(),
+                field: "missing".to_string(),
+                type_name: ".Bar".to_string(),
+            },
+            err
+        );
+
+        let mut desc = desc;
+        desc.messages[1].fields.pop();
+        desc.resolve_types(&[]).unwrap();
+        let types = desc.messages[1]
+            .fields
+            .iter()
+            .map(|f| f.typ.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![typ(".foo.A.Bar"), typ(".foo.Bar")], types);
+    }
+
+    #[test]
+    fn test_resolve_types() {
+        let other = FileDescriptor::parse("package other; message Imported {}").unwrap();
+        let proto = r#"
+            package pkg;
+            import "other.proto";
+            message A {
+                message B {}
+                optional B b = 1;
+                optional other.Imported i = 2;
+                map<string, C> c = 3;
+            }
+            message C {
+                optional A.B b = 1;
+                extend A { optional C c_ext = 10; }
+            }
+            service S {
+                rpc M(A) returns (.pkg.C);
+            }
+        "#;
+        let mut desc = FileDescriptor::parse(proto).unwrap();
+        desc.resolve_types(&[other]).unwrap();
+        let types = desc
+            .all_fields()
+            .map(|(_, f)| f.element_type().clone())
+            .collect::<Vec<_>>();
+        let names = [".pkg.A.B", ".other.Imported", ".pkg.C", ".pkg.A.B"];
+        let expected = names
+            .iter()
+            .map(|n| FieldType::MessageOrEnum(n.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(expected, types);
+        let ext = &desc.messages[1].extensions[0].field.typ;
+        assert_eq!(&FieldType::MessageOrEnum(".pkg.C".to_string()), ext);
+        let method = &desc.services[0].methods[0];
+        assert_eq!((".pkg.A", ".pkg.C"), (&*method.input_type, &*method.output_type));
+
+        let mut desc = FileDescriptor::parse(proto).unwrap();
+        let original = desc.clone();
+        assert_eq!(
+            ResolveError::UnknownType {
+                message: ".pkg.A".to_string(),
+                field: "i".to_string(),
+                type_name: "other.Imported".to_string(),
+            },
+            desc.resolve_types(&[]).unwrap_err()
+        );
+        assert_eq!(original, desc);
+    }
+
+    #[test]
+    fn test_resolve_nested_enum() {
+        let proto = r#"
+            package pkg;
+            message M {
+                enum Status {
+                    OK = 0;
+                }
+                optional Status status = 1;
+                optional M m = 2;
+            }
+            message Status {}
+        "#;
+        let desc = FileDescriptor::parse(proto.as_bytes()).unwrap();
+        assert_eq!(Some(TypeKind::Enum), desc.resolve_kind(".pkg.M", "Status"));
+        assert_eq!(Some(TypeKind::Message), desc.resolve_kind(".pkg", "Status"));
+        assert_eq!(Some(TypeKind::Enum), desc.resolve_kind(".pkg", "M.Status"));
+        assert_eq!(Some(TypeKind::Message), desc.resolve_kind(".pkg.M", "M"));
+        assert_eq!(None, desc.resolve_kind(".pkg.M", "Unknown"));
+    }
+
+    #[test]
+    fn test_resolve_forward_map_value() {
+        let proto = r#"
+            package pkg;
+            message A {
+                map<string, Later> m = 1;
+                map<int32, Later.Inner> n = 2;
+            }
+            message Later {
+                message Inner {}
+            }
+        "#;
+        let desc = FileDescriptor::parse(proto.as_bytes()).unwrap();
+        assert_eq!(Some(TypeKind::Message), desc.resolve_kind(".pkg.A", "Later"));
+        let resolved = desc.with_resolved_imports(&[]).unwrap();
+        let values = resolved.messages[0]
+            .fields
+            .iter()
+            .map(|f| match f.typ {
+                FieldType::Map(ref m) => m.value.clone(),
+                ref t => panic!("unexpected type {:?}", t),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                FieldType::MessageOrEnum(".pkg.Later".to_string()),
+                FieldType::MessageOrEnum(".pkg.Later.Inner".to_string()),
+            ],
+            values
+        );
+    }
+
+    #[test]
+    fn test_presence() {
+        let proto2 = r#"
+            syntax = "proto2";
+            message A {
+                optional int32 a = 1;
+                required int32 b = 2;
+                repeated int32 c = 3;
+                map<int32, A> d = 4;
+                oneof o {
+                    int32 e = 5;
+                }
+            }
+        "#;
+        let desc = FileDescriptor::parse(proto2.as_bytes()).unwrap();
+        let presence = desc
+            .all_fields()
+            .map(|(_, f)| f.presence(&desc))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Presence::Explicit,
+                Presence::Required,
+                Presence::Repeated,
+                Presence::Repeated,
+                Presence::Explicit,
+            ],
+            presence
+        );
+
+        let proto3 = r#"
+            syntax = "proto3";
+            package pkg;
+            message A {
+                enum E { X = 0; }
+                int32 a = 1;
+                optional int32 b = 2;
+                repeated int32 c = 3;
+                map<string, int32> d = 4;
+                A e = 5;
+                E f = 6;
+                other.Imported g = 7;
+                oneof o {
+                    int32 h = 8;
+                    E i = 9;
+                }
+            }
+        "#;
+        let desc = FileDescriptor::parse(proto3.as_bytes()).unwrap();
+        let presence = desc
+            .all_fields()
+            .map(|(_, f)| f.presence(&desc))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Presence::Implicit,
+                Presence::Explicit,
+                Presence::Repeated,
+                Presence::Repeated,
+                Presence::Explicit,
+                Presence::Implicit,
+                Presence::Explicit,
+                Presence::Explicit,
+                Presence::Explicit,
+            ],
+            presence
+        );
+    }
+
+    #[test]
+    fn test_qualified_name_of() {
+        let proto = r#"
+            package pkg;
+            message Outer {
+                message Inner {
+                    message Deepest {}
+                    enum E { X = 0; }
+                }
+            }
+            enum F { Y = 0; }
+        "#;
+        let desc = FileDescriptor::parse(proto).unwrap();
+        let outer = &desc.messages[0];
+        let inner = &outer.messages[0];
+        let names = [outer, inner, &inner.messages[0]]
+            .iter()
+            .map(|m| desc.qualified_name_of(m))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Some(".pkg.Outer".to_string()),
+                Some(".pkg.Outer.Inner".to_string()),
+                Some(".pkg.Outer.Inner.Deepest".to_string()),
+            ],
+            names
+        );
+        assert_eq!(
+            Some(".pkg.Outer.Inner.E".to_string()),
+            desc.qualified_enum_name_of(&inner.enums[0])
+        );
+        assert_eq!(Some(".pkg.F".to_string()), desc.qualified_enum_name_of(&desc.enums[0]));
+
+        // a copy is not part of the file
+        let copy = inner.clone();
+        assert_eq!(None, desc.qualified_name_of(&copy));
+    }
+
+    #[test]
+    fn test_has_presence() {
+        let fields = r#"
+            message A {
+                enum E { X = 0; }
+                %s int32 a = 1;
+                %s A b = 2;
+                %s E c = 3;
+                optional int32 d = 4;
+                repeated int32 e = 5;
+                oneof o {
+                    int32 f = 6;
+                }
+            }
+        "#;
+        let proto2 = format!("syntax = \"proto2\";{}", fields.replace("%s", "optional"));
+        let desc = FileDescriptor::parse(&proto2).unwrap();
+        let presence = desc
+            .all_fields()
+            .map(|(_, f)| f.has_presence(Syntax::Proto2))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![true, true, true, true, false, true], presence);
+
+        let proto3 = format!("syntax = \"proto3\";{}", fields.replace("%s", ""));
+        let desc = FileDescriptor::parse(&proto3).unwrap();
+        let presence = desc
+            .all_fields()
+            .map(|(_, f)| f.has_presence(Syntax::Proto3))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![false, true, false, true, false, true], presence);
+
+        // the context is carried by the field, copies included
+        let oneof = desc.messages[0].oneofs[0].fields[0].clone();
+        assert!(oneof.in_oneof && oneof.has_presence(Syntax::Proto3));
+        let e = desc.messages[0].fields[2].clone();
+        assert_eq!(Some(TypeKind::Enum), e.type_kind);
+        assert!(!e.has_presence(Syntax::Proto3));
+    }
+
+    #[test]
+    fn test_presence_edition() {
+        let proto = r#"
+            edition = "2023";
+            message A {
+                enum E { X = 0; }
+                int32 a = 1;
+                E b = 2;
+                int32 c = 3 [features.field_presence = IMPLICIT];
+                E d = 4 [features.field_presence = IMPLICIT];
+                A e = 5 [features.field_presence = IMPLICIT];
+                int32 f = 6 [features.field_presence = LEGACY_REQUIRED];
+            }
+        "#;
+        let desc = FileDescriptor::parse(proto).unwrap();
+        let presence = desc
+            .all_fields()
+            .map(|(_, f)| f.presence(&desc))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Presence::Explicit,
+                Presence::Explicit,
+                Presence::Implicit,
+                Presence::Implicit,
+                Presence::Explicit,
+                Presence::Required,
+            ],
+            presence
+        );
+
+        let proto = r#"
+            edition = "2023";
+            option features = { field_presence: IMPLICIT };
+            message A {
+                int32 a = 1;
+                int32 b = 2 [features.field_presence = EXPLICIT];
+            }
+        "#;
+        let desc = FileDescriptor::parse(proto).unwrap();
+        let presence = desc
+            .all_fields()
+            .map(|(_, f)| f.presence(&desc))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![Presence::Implicit, Presence::Explicit], presence);
+    }
+
+    #[test]
+    fn test_is_packed() {
+        let fields = r#"
+            message A {
+                enum E { X = 0; }
+                repeated int32 a = 1;
+                repeated int32 b = 2 [packed = true];
+                repeated int32 c = 3 [packed = false];
+                repeated string d = 4;
+                repeated string e = 5 [packed = true];
+                repeated A f = 6;
+                %s int32 g = 7;
+                repeated E h = 8;
+                repeated Imported i = 9;
+            }
+        "#;
+        let packed = |proto: &str, syntax| {
+            let desc = FileDescriptor::parse(proto).unwrap();
+            desc.all_fields()
+                .map(|(_, f)| f.is_packed(syntax))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![false, true, false, false, false, false, false, false, false],
+            packed(&fields.replace("%s", "optional"), Syntax::Proto2)
+        );
+        let proto3 = format!("syntax = \"proto3\";{}", fields.replace("%s", ""));
+        assert_eq!(
+            vec![true, true, false, false, false, false, false, true, false],
+            packed(&proto3, Syntax::Proto3)
+        );
+
+        // imported enums are known once resolved
+        let enums = FileDescriptor::parse("enum Imported { X = 0; }").unwrap();
+        let mut desc = FileDescriptor::parse(&proto3).unwrap();
+        desc.resolve_types(&[enums]).unwrap();
+        let i = &desc.messages[0].fields[8];
+        assert_eq!(Some(TypeKind::Enum), i.type_kind);
+        assert!(i.is_packed(Syntax::Proto3));
+    }
+
+    #[test]
+    fn test_relative_type_name() {
+        // same package
+        assert_eq!("B", relative_type_name(".foo.bar.B", ".foo.bar"));
+        assert_eq!("B", relative_type_name(".foo.bar.B", ".foo.bar.A"));
+        assert_eq!("A.E", relative_type_name(".foo.bar.A.E", ".foo.bar.B"));
+        assert_eq!("E", relative_type_name(".foo.bar.A.E", ".foo.bar.A"));
+        assert_eq!("A", relative_type_name(".foo.bar.A", ".foo.bar.A.B"));
+        assert_eq!("Foo", relative_type_name(".Foo", ""));
+        // imported types
+        assert_eq!("baz.C", relative_type_name(".foo.baz.C", ".foo.bar.A"));
+        assert_eq!(
+            "google.protobuf.Timestamp",
+            relative_type_name(".google.protobuf.Timestamp", ".foo.bar")
+        );
+        // shortening would be shadowed by an enclosing scope
+        assert_eq!("bar.B", relative_type_name(".foo.bar.B", ".foo.bar.A.B"));
+        assert_eq!(".bar.C", relative_type_name(".bar.C", ".foo.bar.A"));
+    }
+
+    #[test]
+    fn test_resolve_scopes() {
+        let proto = r#"
+            package foo.bar;
+            message A {
+                message B {}
+                enum E { X = 0; }
+            }
+            message B {}
+        "#;
+        let desc = FileDescriptor::parse(proto.as_bytes()).unwrap();
+        let types = Types::new(&desc);
+        assert_eq!(Some(".foo.bar.A.B"), types.resolve(".foo.bar.A", "B").as_deref());
+        assert_eq!(Some(".foo.bar.B"), types.resolve(".foo.bar", "B").as_deref());
+        assert_eq!(Some(".foo.bar.A.E"), types.resolve(".foo.bar.A.B", "A.E").as_deref());
+        assert_eq!(Some(".foo.bar.B"), types.resolve(".foo.bar.A", "bar.B").as_deref());
+        assert_eq!(None, types.resolve(".foo.bar.A", "C"));
+        assert_eq!(None, types.resolve(".foo.bar", "A.C"));
+    }
+
+    #[test]
+    fn test_used_imports() {
+        let file = |path: &str, content: &str| FileDescriptorWithContext {
+            path: path.to_string(),
+            descriptor: FileDescriptor::parse(content.as_bytes()).unwrap(),
+        };
+        let imports = vec![
+            file("used.proto", "package used; message U {}"),
+            file("unused.proto", "package unused; message N {}"),
+            file(
+                "opts.proto",
+                "package opts; extend google.protobuf.FieldOptions { optional bool flag = 5000; }",
+            ),
+            file("reexport.proto", r#"import public "deep.proto";"#),
+            file("deep.proto", "package deep; message D {}"),
+        ];
+        let proto = r#"
+            package main;
+            import "used.proto";
+            import "unused.proto";
+            import "opts.proto";
+            import "reexport.proto";
+            message A {
+                optional used.U u = 1;
+                optional int32 i = 2 [(opts.flag) = true];
+                optional deep.D d = 3;
+            }
+        "#;
+        let desc = FileDescriptor::parse(proto.as_bytes()).unwrap();
+        assert_eq!(
+            vec!["used.proto", "opts.proto", "reexport.proto"],
+            desc.used_imports(&imports)
+        );
+        assert_eq!(vec!["used.proto"], desc.used_imports(&imports[..2]));
+    }
+}
@@ -0,0 +1,301 @@
+//! Splitting of a .proto file into tokens, e.g. for syntax highlighting
+//!
+//! The tokenizer never fails: unterminated strings end with their line, unterminated block
+//! comments with the file, and any unexpected char is a `Token::Symbol`.
+//!
+//! The parser reads the same tokens, comments aside.
+
+use super::{Loc, Span};
+
+/// A lexical token of a .proto file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// An identifier or a keyword, without dots (e.g. `message`, `int32`, `Foo`)
+    Ident(String),
+    /// An integer literal as written, decimal, octal or hexadecimal (e.g. `0x1F`)
+    IntLit(String),
+    /// A float literal as written (e.g. `1.5e-3`), `inf` and `nan` are identifiers
+    FloatLit(String),
+    /// A quoted string, without the quotes, escapes are kept as written
+    StringLit(String),
+    /// A comment as written, `//` or `/* */` markers included
+    Comment(String),
+    /// Any other char, e.g. `{`, `;`, `=`, `.` or `-`
+    Symbol(char),
+}
+
+/// An iterator over the tokens of a .proto file and their `Span`s
+///
+/// Whitespaces (spaces, tabs and line breaks) are skipped, comments are kept.
+#[derive(Debug, Clone)]
+pub struct Tokenizer<'a> {
+    file: &'a [u8],
+    loc: Loc,
+}
+
+impl<'a> Tokenizer<'a> {
+    /// Creates a tokenizer of the .proto file content `file`
+    pub fn new(file: &'a str) -> Tokenizer<'a> {
+        Tokenizer::from_bytes(file.as_bytes())
+    }
+
+    /// Creates a tokenizer of a .proto file content which may not be valid UTF-8
+    ///
+    /// Invalid UTF-8 sequences are replaced by `U+FFFD` in the tokens, spans are byte offsets
+    /// in `file`.
+    pub fn from_bytes(file: &'a [u8]) -> Tokenizer<'a> {
+        Tokenizer {
+            file,
+            loc: Loc {
+                offset: 0,
+                line: 1,
+                col: 1,
+            },
+        }
+    }
+
+    /// Moves the current position `len` bytes forward
+    fn advance(&mut self, len: usize) {
+        let end = self.loc.offset + len;
+        for &b in &self.file[self.loc.offset..end] {
+            match b {
+                b'\n' => {
+                    self.loc.line += 1;
+                    self.loc.col = 1;
+                }
+                // skip carriage returns and utf8 continuation bytes, as `LineIndex::loc`
+                b'\r' | 0x80..=0xBF => (),
+                _ => self.loc.col += 1,
+            }
+        }
+        self.loc.offset = end;
+    }
+}
+
+/// Kind of a lexeme, the text of a `Token`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lexeme {
+    /// See `Token::Ident`
+    Ident,
+    /// See `Token::IntLit`
+    IntLit,
+    /// See `Token::FloatLit`
+    FloatLit,
+    /// See `Token::StringLit`, `closed` being false if it ends with its line or the file
+    StringLit { closed: bool },
+    /// See `Token::Comment`, `closed` being false for a block comment without `*/`
+    Comment { closed: bool },
+    /// See `Token::Symbol`
+    Symbol,
+}
+
+/// Whitespaces separating tokens
+pub fn is_space(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\r' | b'\n')
+}
+
+/// The lexeme at the start of `s` and its length, `None` if `s` is empty
+///
+/// `s` must not start with a whitespace.
+pub fn lex(s: &[u8]) -> Option<(Lexeme, usize)> {
+    let hex = s.starts_with(b"0x") || s.starts_with(b"0X");
+    Some(match *s {
+        [] => return None,
+        [b'/', b'/', ..] => {
+            let len = s.iter().position(|&b| b == b'\n').unwrap_or(s.len());
+            (Lexeme::Comment { closed: true }, len)
+        }
+        [b'/', b'*', ref rest @ ..] => match rest.windows(2).position(|w| w == b"*/") {
+            Some(p) => (Lexeme::Comment { closed: true }, p + 4),
+            None => (Lexeme::Comment { closed: false }, s.len()),
+        },
+        [b'"', ..] | [b'\'', ..] => {
+            let (len, closed) = string_len(s);
+            (Lexeme::StringLit { closed }, len)
+        }
+        [b'0'..=b'9', ..] | [b'.', b'0'..=b'9', ..] => {
+            let len = number_len(s);
+            if !hex && s[..len].iter().any(|&b| b == b'.' || b == b'e' || b == b'E') {
+                (Lexeme::FloatLit, len)
+            } else {
+                (Lexeme::IntLit, len)
+            }
+        }
+        [b, ..] if b.is_ascii_alphabetic() || b == b'_' => {
+            let is_word = |b: &u8| b.is_ascii_alphanumeric() || *b == b'_';
+            let len = s.iter().position(|b| !is_word(b)).unwrap_or(s.len());
+            (Lexeme::Ident, len)
+        }
+        // a whole UTF-8 char
+        [b, ..] => {
+            let len = match b {
+                0xF0..=0xFF => 4,
+                0xE0..=0xEF => 3,
+                0xC0..=0xDF => 2,
+                _ => 1,
+            };
+            (Lexeme::Symbol, len.min(s.len()))
+        }
+    })
+}
+
+/// Length of the number at the start of `s`, its exponent sign included
+fn number_len(s: &[u8]) -> usize {
+    let hex = s.starts_with(b"0x") || s.starts_with(b"0X");
+    let mut len = 0;
+    while len < s.len() {
+        len += match s[len] {
+            b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'_' | b'.' => 1,
+            b'+' | b'-' if !hex && (s[len - 1] == b'e' || s[len - 1] == b'E') => 1,
+            _ => break,
+        };
+    }
+    len
+}
+
+/// Length of the quoted string at the start of `s`, quotes included, and whether it is closed
+fn string_len(s: &[u8]) -> (usize, bool) {
+    let mut escaped = false;
+    for (i, &b) in s.iter().enumerate().skip(1) {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            b'\n' => return (i, false),
+            _ if b == s[0] => return (i + 1, true),
+            _ => (),
+        }
+    }
+    (s.len(), false)
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = (Token, Span);
+
+    fn next(&mut self) -> Option<(Token, Span)> {
+        let rest = &self.file[self.loc.offset..];
+        let spaces = rest.iter().take_while(|&&b| is_space(b)).count();
+        self.advance(spaces);
+        let rest = &rest[spaces..];
+        let (lexeme, len) = lex(rest)?;
+        let text = String::from_utf8_lossy(&rest[..len]).into_owned();
+
+        let token = match lexeme {
+            Lexeme::Ident => Token::Ident(text),
+            Lexeme::IntLit => Token::IntLit(text),
+            Lexeme::FloatLit => Token::FloatLit(text),
+            Lexeme::StringLit { closed } => {
+                let end = if closed { len - 1 } else { len };
+                Token::StringLit(String::from_utf8_lossy(&rest[1..end]).into_owned())
+            }
+            Lexeme::Comment { .. } => Token::Comment(text),
+            Lexeme::Symbol => Token::Symbol(text.chars().next()?),
+        };
+
+        let start = self.loc;
+        self.advance(len);
+        Some((
+            token,
+            Span {
+                start,
+                end: self.loc,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tokens(file: &str) -> Vec<Token> {
+        Tokenizer::new(file).map(|(t, _)| t).collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let file = "message Foo { int32 x = 1; }";
+        let tokens = Tokenizer::new(file)
+            .map(|(t, s)| (t, s.start.line, s.start.col, s.range()))
+            .collect::<Vec<_>>();
+        let ident = |s: &str| Token::Ident(s.to_string());
+        assert_eq!(
+            vec![
+                (ident("message"), 1, 1, 0..7),
+                (ident("Foo"), 1, 9, 8..11),
+                (Token::Symbol('{'), 1, 13, 12..13),
+                (ident("int32"), 1, 15, 14..19),
+                (ident("x"), 1, 21, 20..21),
+                (Token::Symbol('='), 1, 23, 22..23),
+                (Token::IntLit("1".to_string()), 1, 25, 24..25),
+                (Token::Symbol(';'), 1, 26, 25..26),
+                (Token::Symbol('}'), 1, 28, 27..28),
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn test_tokenize_literals() {
+        let file = r#"// a comment
+option (my.opt) = -1.5e-3; /* block
+comment */ x = 0x1F 017 .5 inf
+"it's \"quoted\"" 'é'"#;
+        assert_eq!(
+            vec![
+                Token::Comment("// a comment".to_string()),
+                Token::Ident("option".to_string()),
+                Token::Symbol('('),
+                Token::Ident("my".to_string()),
+                Token::Symbol('.'),
+                Token::Ident("opt".to_string()),
+                Token::Symbol(')'),
+                Token::Symbol('='),
+                Token::Symbol('-'),
+                Token::FloatLit("1.5e-3".to_string()),
+                Token::Symbol(';'),
+                Token::Comment("/* block\ncomment */".to_string()),
+                Token::Ident("x".to_string()),
+                Token::Symbol('='),
+                Token::IntLit("0x1F".to_string()),
+                Token::IntLit("017".to_string()),
+                Token::FloatLit(".5".to_string()),
+                Token::Ident("inf".to_string()),
+                Token::StringLit(r#"it's \"quoted\""#.to_string()),
+                Token::StringLit("é".to_string()),
+            ],
+            tokens(file)
+        );
+
+        let (_, span) = Tokenizer::new(file).last().unwrap();
+        assert_eq!((4, 19), (span.start.line, span.start.col));
+        assert_eq!(file.len(), span.end.offset);
+    }
+
+    #[test]
+    fn test_tokenize_unterminated() {
+        assert_eq!(
+            vec![
+                Token::StringLit("abc".to_string()),
+                Token::Ident("x".to_string()),
+                Token::Comment("/* end".to_string()),
+            ],
+            tokens("\"abc\nx /* end")
+        );
+        assert!(tokens(" \n\t").is_empty());
+    }
+
+    #[test]
+    fn test_tokenize_bytes() {
+        let tokens = Tokenizer::from_bytes(b"x \"a\xffb\" \xff")
+            .map(|(t, s)| (t, s.range()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (Token::Ident("x".to_string()), 0..1),
+                (Token::StringLit("a\u{fffd}b".to_string()), 2..7),
+                (Token::Symbol('\u{fffd}'), 8..9),
+            ],
+            tokens
+        );
+    }
+}
@@ -0,0 +1,403 @@
+//! Opt-in validation of field numbers and names, as done by protoc, and lints of suspicious
+//! declarations
+
+use std::collections::HashSet;
+
+use super::{Field, FieldType, FileDescriptor, Message};
+use parser::MAX_FIELD_NUMBER;
+use resolve::{package_scope, Types};
+
+/// Field numbers reserved for the protobuf implementation
+const IMPLEMENTATION_RESERVED: ::std::ops::Range<i32> = 19_000..20_000;
+
+const SCALARS: [&str; 15] = [
+    "double", "float", "int32", "int64", "uint32", "uint64", "sint32", "sint64", "fixed32",
+    "fixed64", "sfixed32", "sfixed64", "bool", "string", "bytes",
+];
+
+/// A numbering or naming rule of protobuf messages
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ValidationRule {
+    /// Field numbers must be between 1 and 536870911
+    NumberOutOfRange,
+    /// Field numbers 19000 to 19999 are reserved for the protobuf implementation
+    ImplementationReserved,
+    /// Field numbers must be unique within a message, oneofs included
+    DuplicateNumber,
+    /// Field names must be unique within a message, oneofs included
+    DuplicateName,
+    /// Field numbers must not be declared `reserved`
+    ReservedNumber,
+    /// Field names must not be declared `reserved`
+    ReservedName,
+    /// Messages, enums and groups nested in a message must be named neither like the message
+    /// nor like each other
+    NestedNameCollision,
+}
+
+/// A field breaking a `ValidationRule`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ValidationError {
+    /// Fully qualified name of the message (or group) declaring the field (e.g. `.pkg.Outer`)
+    pub message: String,
+    /// Field name, or nested type name for `ValidationRule::NestedNameCollision`
+    pub field: String,
+    /// The rule broken
+    pub rule: ValidationRule,
+}
+
+/// A suspicious declaration, which does not make the file invalid (see
+/// `FileDescriptor::warnings`)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Warning {
+    /// The field type is not declared in the file and is one edit away from a scalar type
+    /// (e.g. `in32`), it is likely a typo
+    ///
+    /// Such types are parsed as `FieldType::MessageOrEnum`.
+    MisspelledScalar {
+        /// Fully qualified name of the message (or group) declaring the field
+        message: String,
+        /// Field name
+        field: String,
+        /// Referenced type name, as declared
+        type_name: String,
+    },
+}
+
+/// Whether `a` can be changed into `b` by inserting, deleting or replacing a character or by
+/// swapping two adjacent ones
+fn one_edit(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let prefix = a.iter().zip(b).take_while(|&(x, y)| x == y).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    match (a.len(), b.len()) {
+        (0, 0) => false,
+        (la, lb) if la == lb + 1 => a[1..] == *b,
+        (la, lb) if la + 1 == lb => *a == b[1..],
+        (la, lb) if la == lb => {
+            a[1..] == b[1..] || (la > 1 && a[0] == b[1] && a[1] == b[0] && a[2..] == b[2..])
+        }
+        _ => false,
+    }
+}
+
+/// Checks the fields declared in `scope`, `msg` being the message for reserved declarations
+fn validate_fields<'a, I>(
+    scope: &str,
+    fields: I,
+    msg: Option<&Message>,
+    errors: &mut Vec<ValidationError>,
+) where
+    I: Iterator<Item = &'a Field>,
+{
+    let mut numbers = HashSet::new();
+    let mut names = HashSet::new();
+    let mut groups = Vec::new();
+    for f in fields {
+        let mut error = |rule| {
+            errors.push(ValidationError {
+                message: scope.to_string(),
+                field: f.name.clone(),
+                rule,
+            })
+        };
+        if f.number < 1 || f.number > MAX_FIELD_NUMBER {
+            error(ValidationRule::NumberOutOfRange);
+        } else if IMPLEMENTATION_RESERVED.contains(&f.number) {
+            error(ValidationRule::ImplementationReserved);
+        }
+        if !numbers.insert(f.number) {
+            error(ValidationRule::DuplicateNumber);
+        }
+        if !names.insert(&f.name) {
+            error(ValidationRule::DuplicateName);
+        }
+        if let Some(msg) = msg {
+            if msg.reserved_nums.iter().any(|r| r.contains(&f.number)) {
+                error(ValidationRule::ReservedNumber);
+            }
+            if msg.reserved_names.contains(&f.name) {
+                error(ValidationRule::ReservedName);
+            }
+        }
+        if let FieldType::Group(ref g) = f.typ {
+            groups.push(g);
+        }
+    }
+    for g in groups {
+        let scope = format!("{}.{}", scope, g.name);
+        validate_fields(&scope, g.fields.iter(), None, errors);
+    }
+}
+
+/// Checks that the messages, enums and groups nested in `msg` are named neither like `msg` nor
+/// like each other
+///
+/// The error is reported for the second declaration of the name, in source order if the spans
+/// are set.
+fn validate_nested_names(scope: &str, msg: &Message, errors: &mut Vec<ValidationError>) {
+    let groups = msg.fields
+        .iter()
+        .chain(msg.oneofs.iter().flat_map(|o| o.fields.iter()))
+        .filter_map(|f| match f.typ {
+            FieldType::Group(ref g) => Some((f.span, &*g.name)),
+            _ => None,
+        });
+    let mut nested = msg.messages
+        .iter()
+        .map(|m| (m.span, &*m.name))
+        .chain(msg.enums.iter().map(|e| (e.span, &*e.name)))
+        .chain(groups)
+        .collect::<Vec<_>>();
+    nested.sort_by_key(|&(span, _)| span.map(|s| s.start.offset));
+
+    let mut names = HashSet::new();
+    names.insert(&*msg.name);
+    for (_, name) in nested {
+        if !names.insert(name) {
+            errors.push(ValidationError {
+                message: scope.to_string(),
+                field: name.to_string(),
+                rule: ValidationRule::NestedNameCollision,
+            });
+        }
+    }
+}
+
+fn validate_message(scope: &str, msg: &Message, errors: &mut Vec<ValidationError>) {
+    let scope = format!("{}.{}", scope, msg.name);
+    let fields = msg.fields
+        .iter()
+        .chain(msg.oneofs.iter().flat_map(|o| o.fields.iter()));
+    validate_fields(&scope, fields, Some(msg), errors);
+    validate_nested_names(&scope, msg, errors);
+    for m in &msg.messages {
+        validate_message(&scope, m, errors);
+    }
+}
+
+/// Collects the warnings of the fields declared in `scope`, group fields included
+fn warn_fields(types: &Types, scope: &str, fields: &[Field], warnings: &mut Vec<Warning>) {
+    for f in fields {
+        if let FieldType::MessageOrEnum(ref name) = *f.element_type() {
+            // scalar types are lowercase, capitalized names are most likely imported types
+            if !name.starts_with(|c: char| c.is_ascii_uppercase())
+                && SCALARS.iter().any(|s| one_edit(name, s))
+                && types.resolve(scope, name).is_none()
+            {
+                warnings.push(Warning::MisspelledScalar {
+                    message: scope.to_string(),
+                    field: f.name.clone(),
+                    type_name: name.clone(),
+                });
+            }
+        }
+        if let FieldType::Group(ref g) = f.typ {
+            warn_fields(types, &format!("{}.{}", scope, g.name), &g.fields, warnings);
+        }
+    }
+}
+
+fn warn_message(types: &Types, scope: &str, msg: &Message, warnings: &mut Vec<Warning>) {
+    let scope = format!("{}.{}", scope, msg.name);
+    warn_fields(types, &scope, &msg.fields, warnings);
+    for o in &msg.oneofs {
+        warn_fields(types, &scope, &o.fields, warnings);
+    }
+    for m in &msg.messages {
+        warn_message(types, &scope, m, warnings);
+    }
+}
+
+impl FileDescriptor {
+    /// Checks the numbers and names of the fields and nested types of all messages against the
+    /// rules enforced by protoc (see `ValidationRule`)
+    ///
+    /// Parsing does not check them. Errors are returned message by message (depth first), in
+    /// field declaration order, followed by the nested name collisions. Extensions are not
+    /// checked.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let scope = package_scope(&self.package);
+        let mut errors = Vec::new();
+        for m in &self.messages {
+            validate_message(&scope, m, &mut errors);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Suspicious declarations of all messages (see `Warning`), which `validate` does not
+    /// report
+    ///
+    /// Only types declared in this file are known: a lowercase type name from an import may
+    /// be reported. Warnings are returned message by message (depth first), in field
+    /// declaration order.
+    pub fn warnings(&self) -> Vec<Warning> {
+        let scope = package_scope(&self.package);
+        let types = Types::new(self);
+        let mut warnings = Vec::new();
+        for m in &self.messages {
+            warn_message(&types, &scope, m, &mut warnings);
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let proto = r#"
+            package pkg;
+            message A {
+                optional int32 a = 0;
+                optional int32 b = 19500;
+                optional int32 c = 536870912;
+                optional int32 d = 4;
+                oneof o {
+                    int32 e = 4;
+                }
+                optional int32 f = 10;
+                optional int32 foo = 20;
+                optional group G = 21 {
+                    optional int32 g = 21;
+                    optional int32 h = 21;
+                }
+                reserved 8 to 12;
+                reserved "foo";
+                message B {
+                    optional int32 i = 19999;
+                }
+            }
+        "#;
+        let desc = FileDescriptor::parse(proto).unwrap();
+        let errors = desc
+            .validate()
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.message, e.field, e.rule))
+            .collect::<Vec<_>>();
+        let error = |m: &str, f: &str, rule| (m.to_string(), f.to_string(), rule);
+        assert_eq!(
+            vec![
+                error(".pkg.A", "a", ValidationRule::NumberOutOfRange),
+                error(".pkg.A", "b", ValidationRule::ImplementationReserved),
+                error(".pkg.A", "c", ValidationRule::NumberOutOfRange),
+                error(".pkg.A", "f", ValidationRule::ReservedNumber),
+                error(".pkg.A", "foo", ValidationRule::ReservedName),
+                error(".pkg.A", "e", ValidationRule::DuplicateNumber),
+                error(".pkg.A.G", "h", ValidationRule::DuplicateNumber),
+                error(".pkg.A.B", "i", ValidationRule::ImplementationReserved),
+            ],
+            errors
+        );
+
+        let desc = FileDescriptor::parse("message A { optional int32 a = 1; }").unwrap();
+        assert_eq!(Ok(()), desc.validate());
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let proto = r#"
+            message A {
+                optional int32 a = 1;
+                optional string a = 2;
+                optional int32 b = 3;
+                oneof o {
+                    int32 c = 4;
+                    string b = 5;
+                }
+                message B {
+                    optional int32 a = 1;
+                }
+            }
+        "#;
+        let desc = FileDescriptor::parse(proto).unwrap();
+        let error = |f: &str| ValidationError {
+            message: ".A".to_string(),
+            field: f.to_string(),
+            rule: ValidationRule::DuplicateName,
+        };
+        assert_eq!(Err(vec![error("a"), error("b")]), desc.validate());
+    }
+
+    #[test]
+    fn test_misspelled_scalar() {
+        let proto = r#"
+            message A {
+                optional in32 a = 1;
+                optional strnig b = 2;
+                repeated uint6 c = 3;
+                map<string, boll> d = 4;
+                optional sint32 e = 6;
+                optional Int32 f = 7;
+                optional bytes g = 8;
+                message byte {}
+                optional byte h = 9;
+                optional pkg.in32 i = 10;
+                optional Bytes j = 11;
+            }
+        "#;
+        let desc = FileDescriptor::parse(proto).unwrap();
+        assert_eq!(
+            FieldType::MessageOrEnum("in32".to_string()),
+            desc.messages[0].fields[0].typ
+        );
+        assert_eq!(Ok(()), desc.validate());
+        let warning = |f: &str, t: &str| Warning::MisspelledScalar {
+            message: ".A".to_string(),
+            field: f.to_string(),
+            type_name: t.to_string(),
+        };
+        assert_eq!(
+            vec![
+                warning("a", "in32"),
+                warning("b", "strnig"),
+                warning("c", "uint6"),
+                warning("d", "boll"),
+            ],
+            desc.warnings()
+        );
+    }
+
+    #[test]
+    fn test_nested_name_collision() {
+        let desc = FileDescriptor::parse("message Foo { message Foo {} }").unwrap();
+        let error = |m: &str, f: &str| ValidationError {
+            message: m.to_string(),
+            field: f.to_string(),
+            rule: ValidationRule::NestedNameCollision,
+        };
+        assert_eq!(Err(vec![error(".Foo", "Foo")]), desc.validate());
+
+        let proto = r#"message A {
+            message B {
+                enum C { X = 0; }
+                optional group C = 1 {}
+            }
+            enum B { Y = 0; }
+            message Foo {}
+        }
+        message Foo {}"#;
+        let desc = FileDescriptor::parse(proto).unwrap();
+        assert_eq!(Err(vec![error(".A", "B"), error(".A.B", "C")]), desc.validate());
+        let desc = FileDescriptor::parse_with_spans(proto).unwrap();
+        assert_eq!(Err(vec![error(".A", "B"), error(".A.B", "C")]), desc.validate());
+    }
+
+    #[test]
+    fn test_one_edit() {
+        assert!(one_edit("in32", "int32"));
+        assert!(one_edit("int322", "int32"));
+        assert!(one_edit("itn32", "int32"));
+        assert!(one_edit("unt32", "int32"));
+        assert!(!one_edit("int32", "int32"));
+        assert!(!one_edit("i32", "int32"));
+        assert!(!one_edit("tin23", "int32"));
+    }
+}
@@ -0,0 +1,193 @@
+//! Traversal of the descriptor tree
+
+use super::{
+    EnumValue, Enumeration, Extension, Field, FieldType, FileDescriptor, Message, Method, OneOf,
+    Service,
+};
+
+/// A visitor of the items declared in a .proto file (see `FileDescriptor::accept`)
+///
+/// All methods do nothing by default, only the relevant ones need to be overridden.
+pub trait Visitor {
+    /// Called for each message, nested messages included (but not groups)
+    fn visit_message(&mut self, _message: &Message) {}
+    /// Called for each field of messages, oneofs, groups and extensions
+    fn visit_field(&mut self, _field: &Field) {}
+    /// Called for each oneof, before its fields
+    fn visit_oneof(&mut self, _oneof: &OneOf) {}
+    /// Called for each enum, nested enums included
+    fn visit_enum(&mut self, _enumeration: &Enumeration) {}
+    /// Called for each enum value, after its enum
+    fn visit_enum_value(&mut self, _value: &EnumValue) {}
+    /// Called for each extension, before its field, at file or message level
+    fn visit_extension(&mut self, _extension: &Extension) {}
+    /// Called for each service
+    fn visit_service(&mut self, _service: &Service) {}
+    /// Called for each service method, after its service
+    fn visit_method(&mut self, _method: &Method) {}
+}
+
+fn walk_fields<V: Visitor + ?Sized>(fields: &[Field], v: &mut V) {
+    for f in fields {
+        v.visit_field(f);
+        if let FieldType::Group(ref g) = f.typ {
+            walk_fields(&g.fields, v);
+        }
+    }
+}
+
+fn walk_extension<V: Visitor + ?Sized>(extension: &Extension, v: &mut V) {
+    v.visit_extension(extension);
+    walk_fields(::std::slice::from_ref(&extension.field), v);
+}
+
+fn walk_enum<V: Visitor + ?Sized>(enumeration: &Enumeration, v: &mut V) {
+    v.visit_enum(enumeration);
+    for value in &enumeration.values {
+        v.visit_enum_value(value);
+    }
+}
+
+fn walk_message<V: Visitor + ?Sized>(message: &Message, v: &mut V) {
+    v.visit_message(message);
+    walk_fields(&message.fields, v);
+    for o in &message.oneofs {
+        v.visit_oneof(o);
+        walk_fields(&o.fields, v);
+    }
+    for e in &message.extensions {
+        walk_extension(e, v);
+    }
+    for e in &message.enums {
+        walk_enum(e, v);
+    }
+    for m in &message.messages {
+        walk_message(m, v);
+    }
+}
+
+impl FileDescriptor {
+    /// Walks all the items declared in the file with the visitor `v`, depth first
+    ///
+    /// Each kind of item is visited in declaration order: messages, then enums, extensions
+    /// and services. Within a message, its fields come first (the fields of a group right
+    /// after the group field), then its oneofs, extensions, enums and nested messages.
+    pub fn accept<V: Visitor + ?Sized>(&self, v: &mut V) {
+        for m in &self.messages {
+            walk_message(m, v);
+        }
+        for e in &self.enums {
+            walk_enum(e, v);
+        }
+        for e in &self.extensions {
+            walk_extension(e, v);
+        }
+        for s in &self.services {
+            v.visit_service(s);
+            for m in &s.methods {
+                v.visit_method(m);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Default)]
+    struct FieldNames(Vec<String>);
+
+    impl Visitor for FieldNames {
+        fn visit_field(&mut self, field: &Field) {
+            self.0.push(field.name.clone());
+        }
+    }
+
+    #[derive(Default)]
+    struct Names(Vec<String>);
+
+    impl Visitor for Names {
+        fn visit_message(&mut self, message: &Message) {
+            self.0.push(format!("message {}", message.name));
+        }
+        fn visit_oneof(&mut self, oneof: &OneOf) {
+            self.0.push(format!("oneof {}", oneof.name));
+        }
+        fn visit_enum(&mut self, enumeration: &Enumeration) {
+            self.0.push(format!("enum {}", enumeration.name));
+        }
+        fn visit_enum_value(&mut self, value: &EnumValue) {
+            self.0.push(format!("value {}", value.name));
+        }
+        fn visit_extension(&mut self, extension: &Extension) {
+            self.0.push(format!("extend {}", extension.extendee));
+        }
+        fn visit_service(&mut self, service: &Service) {
+            self.0.push(format!("service {}", service.name));
+        }
+        fn visit_method(&mut self, method: &Method) {
+            self.0.push(format!("rpc {}", method.name));
+        }
+    }
+
+    const PROTO: &str = r#"
+        message A {
+            optional int32 a = 1;
+            optional group G = 2 {
+                optional int32 g = 3;
+            }
+            oneof o {
+                string b = 4;
+            }
+            map<string, int32> c = 5;
+            extend A {
+                optional int32 d = 100;
+            }
+            enum E { X = 0; }
+            message B {
+                optional int32 e = 1;
+            }
+            extensions 100 to max;
+        }
+        enum F { Y = 0; Z = 1; }
+        extend A {
+            optional int32 f = 101;
+        }
+        service S {
+            rpc M(A) returns (A);
+        }
+    "#;
+
+    #[test]
+    fn test_visit_fields() {
+        let desc = FileDescriptor::parse(PROTO).unwrap();
+        let mut names = FieldNames::default();
+        desc.accept(&mut names);
+        assert_eq!(vec!["a", "g", "g", "c", "b", "d", "e", "f"], names.0);
+    }
+
+    #[test]
+    fn test_visit_order() {
+        let desc = FileDescriptor::parse(PROTO).unwrap();
+        let mut names = Names::default();
+        desc.accept(&mut names);
+        assert_eq!(
+            vec![
+                "message A",
+                "oneof o",
+                "extend A",
+                "enum E",
+                "value X",
+                "message B",
+                "enum F",
+                "value Y",
+                "value Z",
+                "extend A",
+                "service S",
+                "rpc M",
+            ],
+            names.0
+        );
+    }
+}
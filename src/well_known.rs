@@ -0,0 +1,132 @@
+//! Recognition of the well-known types of the `google.protobuf` package
+
+use super::FieldType;
+
+/// A well-known message or enum, declared in `google/protobuf/*.proto`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum WellKnownType {
+    /// `google.protobuf.Any`
+    Any,
+    /// `google.protobuf.Duration`
+    Duration,
+    /// `google.protobuf.Empty`
+    Empty,
+    /// `google.protobuf.FieldMask`
+    FieldMask,
+    /// `google.protobuf.Timestamp`
+    Timestamp,
+    /// `google.protobuf.Struct`
+    Struct,
+    /// `google.protobuf.Value`
+    Value,
+    /// `google.protobuf.ListValue`
+    ListValue,
+    /// `google.protobuf.NullValue`, an enum
+    NullValue,
+    /// `google.protobuf.DoubleValue`
+    DoubleValue,
+    /// `google.protobuf.FloatValue`
+    FloatValue,
+    /// `google.protobuf.Int64Value`
+    Int64Value,
+    /// `google.protobuf.UInt64Value`
+    UInt64Value,
+    /// `google.protobuf.Int32Value`
+    Int32Value,
+    /// `google.protobuf.UInt32Value`
+    UInt32Value,
+    /// `google.protobuf.BoolValue`
+    BoolValue,
+    /// `google.protobuf.StringValue`
+    StringValue,
+    /// `google.protobuf.BytesValue`
+    BytesValue,
+}
+
+const WELL_KNOWN_TYPES: [(&str, WellKnownType); 18] = [
+    ("Any", WellKnownType::Any),
+    ("Duration", WellKnownType::Duration),
+    ("Empty", WellKnownType::Empty),
+    ("FieldMask", WellKnownType::FieldMask),
+    ("Timestamp", WellKnownType::Timestamp),
+    ("Struct", WellKnownType::Struct),
+    ("Value", WellKnownType::Value),
+    ("ListValue", WellKnownType::ListValue),
+    ("NullValue", WellKnownType::NullValue),
+    ("DoubleValue", WellKnownType::DoubleValue),
+    ("FloatValue", WellKnownType::FloatValue),
+    ("Int64Value", WellKnownType::Int64Value),
+    ("UInt64Value", WellKnownType::UInt64Value),
+    ("Int32Value", WellKnownType::Int32Value),
+    ("UInt32Value", WellKnownType::UInt32Value),
+    ("BoolValue", WellKnownType::BoolValue),
+    ("StringValue", WellKnownType::StringValue),
+    ("BytesValue", WellKnownType::BytesValue),
+];
+
+impl WellKnownType {
+    /// Type name, without package (e.g. `Timestamp`)
+    pub fn name(&self) -> &'static str {
+        WELL_KNOWN_TYPES
+            .iter()
+            .find(|&&(_, t)| t == *self)
+            .map_or("", |&(name, _)| name)
+    }
+}
+
+impl FieldType {
+    /// The well-known type referenced by a `MessageOrEnum`, `None` for any other type
+    ///
+    /// Both bare (`Timestamp`) and fully qualified names, with or without leading dot
+    /// (`google.protobuf.Timestamp` or `.google.protobuf.Timestamp`), are recognized. A bare
+    /// name may refer to a type of the file itself: use `FileDescriptor::resolve_types` first
+    /// to only match the `google.protobuf` types.
+    pub fn well_known(&self) -> Option<WellKnownType> {
+        let name = match *self {
+            FieldType::MessageOrEnum(ref name) => name,
+            _ => return None,
+        };
+        let name = name.strip_prefix('.').unwrap_or(name);
+        let name = match name.strip_prefix("google.protobuf.") {
+            Some(name) => name,
+            None if !name.contains('.') => name,
+            None => return None,
+        };
+        WELL_KNOWN_TYPES
+            .iter()
+            .find(|&&(n, _)| n == name)
+            .map(|&(_, t)| t)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_well_known() {
+        let typ = |name: &str| FieldType::MessageOrEnum(name.to_string());
+        assert_eq!(
+            Some(WellKnownType::Timestamp),
+            typ("google.protobuf.Timestamp").well_known()
+        );
+        assert_eq!(
+            Some(WellKnownType::Any),
+            typ(".google.protobuf.Any").well_known()
+        );
+        assert_eq!(
+            Some(WellKnownType::UInt64Value),
+            typ(".google.protobuf.UInt64Value").well_known()
+        );
+        assert_eq!(None, typ("my.pkg.Timestamp").well_known());
+        assert_eq!(Some(WellKnownType::Timestamp), typ("Timestamp").well_known());
+        assert_eq!(Some(WellKnownType::Duration), typ("Duration").well_known());
+        assert_eq!(None, typ("Custom").well_known());
+        assert_eq!(None, typ("google.protobuf.Custom").well_known());
+        assert_eq!(None, FieldType::String.well_known());
+
+        for &(name, t) in &WELL_KNOWN_TYPES {
+            assert_eq!(name, t.name());
+        }
+    }
+}
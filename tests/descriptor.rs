@@ -0,0 +1,124 @@
+extern crate protobuf_parser;
+
+use protobuf_parser::{FieldType, FileDescriptor, Message, Rule, Syntax};
+
+fn descriptor() -> FileDescriptor {
+    let file = include_str!("../test_data/descriptor.proto");
+    FileDescriptor::parse(file).expect("parse descriptor.proto")
+}
+
+fn message<'a>(desc: &'a FileDescriptor, name: &str) -> &'a Message {
+    desc.messages
+        .iter()
+        .find(|m| m.name == name)
+        .unwrap_or_else(|| panic!("missing message {}", name))
+}
+
+fn field_names(msg: &Message) -> Vec<&str> {
+    msg.fields.iter().map(|f| &*f.name).collect()
+}
+
+#[test]
+fn test_descriptor_file() {
+    let desc = descriptor();
+    assert!(matches!(desc.syntax, Syntax::Proto2));
+    assert_eq!("google.protobuf", desc.package);
+    assert_eq!(Some(true), desc.bool_option("cc_enable_arenas"));
+    assert_eq!(21, desc.messages.len());
+    assert_eq!(27, desc.message_count_recursive());
+    assert!(desc.enums.is_empty());
+    assert_eq!(Ok(()), desc.validate());
+    assert!(desc.all_fields().all(|(_, f)| f.typed_default().is_ok()));
+}
+
+#[test]
+fn test_descriptor_messages() {
+    let desc = descriptor();
+
+    let file = message(&desc, "FileDescriptorProto");
+    assert_eq!(
+        vec![
+            "name",
+            "package",
+            "dependency",
+            "public_dependency",
+            "weak_dependency",
+            "message_type",
+            "enum_type",
+            "service",
+            "extension",
+            "options",
+            "source_code_info",
+            "syntax",
+        ],
+        field_names(file)
+    );
+    assert_eq!(
+        Some("file name, relative to root of source tree"),
+        file.fields[0].trailing_comment.as_deref()
+    );
+    assert_eq!(
+        vec!["Names of files imported by this file."],
+        file.fields[2].comments
+    );
+    let message_type = &file.fields[5];
+    assert_eq!(Rule::Repeated, message_type.rule);
+    assert_eq!(
+        FieldType::MessageOrEnum("DescriptorProto".to_string()),
+        message_type.typ
+    );
+    assert_eq!(4, message_type.number);
+
+    let message = message(&desc, "DescriptorProto");
+    assert_eq!(10, message.fields.len());
+    let nested = message.messages.iter().map(|m| &*m.name).collect::<Vec<_>>();
+    assert_eq!(vec!["ExtensionRange", "ReservedRange"], nested);
+    assert_eq!(3, message.messages[0].fields.len());
+}
+
+#[test]
+fn test_descriptor_enums() {
+    let desc = descriptor();
+
+    let field = message(&desc, "FieldDescriptorProto");
+    let enums = field.enums.iter().map(|e| &*e.name).collect::<Vec<_>>();
+    assert_eq!(vec!["Type", "Label"], enums);
+    let types = &field.enums[0].values;
+    assert_eq!(18, types.len());
+    assert_eq!(("TYPE_DOUBLE", 1), (&*types[0].name, types[0].number));
+    assert_eq!(("TYPE_SINT64", 18), (&*types[17].name, types[17].number));
+
+    let options = message(&desc, "FileOptions");
+    assert_eq!("OptimizeMode", options.enums[0].name);
+    let optimize_for = options
+        .fields
+        .iter()
+        .find(|f| f.name == "optimize_for")
+        .expect("optimize_for");
+    assert_eq!(Some("SPEED"), optimize_for.default.as_deref());
+}
+
+#[test]
+fn test_descriptor_options_and_ranges() {
+    let desc = descriptor();
+
+    let options = message(&desc, "FieldOptions");
+    assert_eq!(1, options.extension_ranges.len());
+    assert_eq!(1000..536_870_912, options.extension_ranges[0].range);
+    assert_eq!(vec![4..5], options.reserved_nums);
+    let path = &message(&desc, "SourceCodeInfo").messages[0].fields[0];
+    assert_eq!(Some(true), path.packed);
+
+    let options = message(&desc, "MessageOptions");
+    assert_eq!(vec![4..5, 5..6, 6..7, 8..9, 9..10], options.reserved_nums);
+
+    let part = &message(&desc, "UninterpretedOption").messages[0];
+    assert!(part.fields.iter().all(|f| f.rule == Rule::Required));
+}
+
+#[test]
+fn test_descriptor_round_trip() {
+    let desc = descriptor();
+    let printed = desc.to_string();
+    assert_eq!(desc, FileDescriptor::parse(&printed).expect("parse printed"));
+}
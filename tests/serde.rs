@@ -0,0 +1,47 @@
+#![cfg(feature = "serde")]
+
+extern crate protobuf_parser;
+extern crate serde_json;
+
+use protobuf_parser::{FieldType, FileDescriptor};
+
+fn json_round_trip(desc: &FileDescriptor) -> FileDescriptor {
+    let json = serde_json::to_string(desc).expect("serialize");
+    serde_json::from_str(&json).expect("deserialize")
+}
+
+#[test]
+fn test_json_round_trip() {
+    let proto = r#"
+        syntax = "proto3";
+        package pkg;
+        import public "other.proto";
+        option (file.opt) = { a: 1 };
+        // A message
+        message A {
+            map<string, B> m = 1 [json_name = "mm"];
+            oneof o {
+                int32 i = 2;
+                double d = 3 [(f) = 1.5];
+            }
+            reserved 10 to max;
+            message B {}
+            enum E { X = 0; reserved 5, 10 to max; }
+        }
+        service S { rpc M(stream A) returns (A); }
+    "#;
+    let desc = FileDescriptor::parse_with_spans(proto).expect("parse");
+    assert_eq!(desc, json_round_trip(&desc));
+
+    let map = serde_json::to_string(&desc.messages[0].fields[0].typ).expect("serialize");
+    assert_eq!(r#"{"Map":{"key":"String","value":{"MessageOrEnum":"B"}}}"#, map);
+    let typ = serde_json::from_str::<FieldType>(r#"{"MessageOrEnum":".pkg.A"}"#);
+    assert_eq!(FieldType::MessageOrEnum(".pkg.A".to_string()), typ.expect("deserialize"));
+}
+
+#[test]
+fn test_json_round_trip_descriptor() {
+    let file = include_str!("../test_data/descriptor.proto");
+    let desc = FileDescriptor::parse(file).expect("parse");
+    assert_eq!(desc, json_round_trip(&desc));
+}